@@ -1,62 +1,97 @@
-use super::{chat_list::ChatList, ButtonStyle};
+use super::{
+    chat_list::ChatList, format_relative, message_text, style_muted, truncate_message, ButtonStyle,
+};
 use crate::components::web_image::{WebImage, WebImageMessage};
-use crate::server::get_profile_picture;
+use crate::{e2ee, server::get_user_status};
 use iced::{
     alignment,
     theme::Button,
-    widget::{button, column, container, row, text},
+    widget::{button, column, container, row, text, Space},
     Command, Element, Length,
 };
-use structs::{DateTime, Utc};
+use structs::{
+    requests::{ChatKey, ChatMember, ChatPreferences, ChatWithMembers, UserStatus, WsChatMessage},
+    DateTime, Utc,
+};
+
+use super::icon;
 
 pub struct Chat {
     pub id: String,
-    pub members: Vec<String>,
+    pub members: Vec<ChatMember>,
     pub profile_picture: WebImage,
+    /// Profile of the other member
+    pub status: Option<UserStatus>,
     pub last_updated: DateTime<Utc>,
+    pub preferences: ChatPreferences,
+    pub last_message: Option<WsChatMessage>,
+    /// Seconds after which new messages disappear
+    pub message_ttl: Option<u32>,
+    /// Oldest first, the chat is end-to-end encrypted once it has one
+    pub chat_keys: Vec<ChatKey>,
     pub is_open: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ChatMessage {
     OpenChat,
-    ProfilePictureLoaded(Option<String>),
+    StatusLoaded(Option<UserStatus>),
     ProfilePicture(WebImageMessage),
+    LastMessageLoaded(Option<WsChatMessage>),
 }
 
 impl Chat {
     pub fn new(
         chat_list: &mut ChatList,
         current_user_id: String,
-        id: String,
-        members: Vec<String>,
-        last_updated: DateTime<Utc>,
+        chat: ChatWithMembers,
     ) -> (Command<ChatMessage>, String) {
+        let other_member_id =
+            Chat::get_other_member(&current_user_id, &chat.members).map(|member| member.id.clone());
+        chat.chat_keys.iter().for_each(e2ee::learn);
+        let mut last_message = chat.last_message;
+        if let Some(message) = &mut last_message {
+            e2ee::decrypt(message);
+        }
         chat_list.chats.insert(
-            id.clone(),
+            chat.id.clone(),
             Self {
-                id: id.clone(),
-                members: members.clone(),
+                id: chat.id.clone(),
+                members: chat.members,
                 profile_picture: WebImage::new(chat_list.client.clone()),
+                status: None,
                 is_open: false,
-                last_updated,
+                last_updated: chat.last_updated,
+                preferences: chat.preferences,
+                last_message,
+                message_ttl: chat.message_ttl,
+                chat_keys: chat.chat_keys,
             },
         );
 
         let client = chat_list.client.clone();
+        let session_id = chat_list.session.session_id.clone();
         (
-            Command::perform(
-                get_profile_picture(client, Chat::get_other_member(current_user_id, &members)),
-                ChatMessage::ProfilePictureLoaded,
-            ),
-            id,
+            match other_member_id {
+                Some(id) => Command::perform(
+                    get_user_status(client, session_id, id),
+                    ChatMessage::StatusLoaded,
+                ),
+                None => Command::none(),
+            },
+            chat.id,
         )
     }
 
     pub fn update(&mut self, message: ChatMessage) -> Command<ChatMessage> {
         match message {
-            ChatMessage::ProfilePictureLoaded(pfp) => {
-                if let Some(pfp) = pfp {
+            ChatMessage::StatusLoaded(status) => {
+                self.status = status;
+                if let Some(pfp) = self
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.profile_picture.clone())
+                {
                     self.profile_picture
                         .load_image(pfp)
                         .map(ChatMessage::ProfilePicture)
@@ -68,28 +103,29 @@ impl Chat {
                 .profile_picture
                 .update(msg)
                 .map(ChatMessage::ProfilePicture),
+            ChatMessage::LastMessageLoaded(message) => {
+                self.last_message = message;
+                Command::none()
+            }
             ChatMessage::OpenChat => unreachable!(),
         }
     }
 
-    pub fn get_other_member(current_user_id: String, members: &[String]) -> String {
-        let first_member = members.get(0);
-        let members = match (first_member, members.get(1).or(first_member)) {
-            (Some(a), Some(b)) => (a.clone(), b.clone()),
-            _ => panic!("group chats are not supported ( иди нахуй шаман )"),
-        };
-
-        let other_member = if members.0 == current_user_id {
-            members.1
-        } else {
-            members.0
-        };
-        other_member
+    /// `None` once the other member has deleted their account
+    pub fn get_other_member<'a>(
+        current_user_id: &str,
+        members: &'a [ChatMember],
+    ) -> Option<&'a ChatMember> {
+        members.iter().find(|member| member.id != current_user_id)
     }
 
     pub fn view(&self, current_user_id: String) -> Element<ChatMessage> {
-        let other_member = Chat::get_other_member(current_user_id, &self.members);
-        let nickname = text(other_member.clone());
+        let other_member = Chat::get_other_member(&current_user_id, &self.members);
+        let nickname = text(
+            other_member
+                .map(ChatMember::name)
+                .unwrap_or("Удалённый аккаунт"),
+        );
 
         let chat_button_style = if self.is_open {
             ButtonStyle::Hover
@@ -97,14 +133,45 @@ impl Chat {
             ButtonStyle::Simple
         };
 
+        let mut title = row![nickname]
+            .spacing(5)
+            .align_items(alignment::Alignment::Center);
+        if let Some(member) = other_member {
+            title = title
+                .push(container(text(format!("@{}", member.username)).size(11)).style(style_muted));
+        }
+        if self.preferences.pinned {
+            title = title.push(icon('').size(12));
+        }
+        if self.preferences.is_muted() {
+            title = title.push(icon('').size(12));
+        }
+
+        let mut details = column![title].spacing(2).width(Length::Fill);
+        if let Some(message) = &self.last_message {
+            let content = truncate_message(message_text(message), 30);
+            details = details.push(
+                row![
+                    text(if message.sender_id == current_user_id {
+                        format!("Вы: {content}")
+                    } else {
+                        content
+                    })
+                    .size(12),
+                    Space::with_width(Length::Fill),
+                    container(text(format_relative(message.created_at)).size(11))
+                        .style(style_muted),
+                ]
+                .spacing(5)
+                .align_items(alignment::Alignment::Center),
+            );
+        }
+
         let content = button(
-            row![
-                self.profile_picture.view().width(32).height(32),
-                column![nickname]
-            ]
-            .width(Length::Fill)
-            .spacing(10)
-            .align_items(alignment::Alignment::Center),
+            row![self.profile_picture.view().width(32).height(32), details]
+                .width(Length::Fill)
+                .spacing(10)
+                .align_items(alignment::Alignment::Center),
         )
         .style(Button::Custom(Box::new(chat_button_style)))
         .on_press(ChatMessage::OpenChat)
@@ -1,33 +1,43 @@
 use std::collections::HashMap;
 
 use iced::{
-    theme::Scrollable,
-    widget::{column, container, row, scrollable, text_input},
-    Command, Element, Length,
+    theme::{Button, Scrollable},
+    widget::{button, column, container, row, scrollable, text},
+    window, Command, Element, Length,
 };
 use structs::{
     requests::{
-        ChatWithMembers, CreateChat, Session, WsChatMessage, WsDeleteMessage, WsMessageData,
+        ChatMember, ChatPreferences, ChatWithMembers, CreateChat, Session, UpdateChatPreferences,
+        WsChatMessage, WsDeleteMessage, WsMessageData,
     },
     Utc,
 };
 
-use crate::{server::server_get, server::server_post, ws_client::WsEvent};
+use crate::{
+    e2ee,
+    notifications::{self, Notification},
+    server::server_get,
+    server::server_post,
+    ws_client::WsEvent,
+};
 
 use super::{
     chat::{Chat, ChatMessage},
-    icon_button,
-    letter_list::{LetterList, LetterListMessage},
-    style_outline, ScrollableStyle,
+    letter_list::{ForwardTarget, LetterList, LetterListMessage},
+    member_name, message_text, style_outline, truncate_message,
+    user_search::{UserSearch, UserSearchMessage},
+    ButtonStyle, ScrollableStyle,
 };
 
 pub struct ChatList {
     pub chats: HashMap<String, Chat>,
     pub client: reqwest::Client,
-    pub username_input: String,
+    pub user_search: UserSearch,
     pub session: Session,
     pub opened_chat: Option<String>,
     pub opened_chat_messages: LetterList,
+    pub show_archived: bool,
+    pub window_focused: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,22 +45,94 @@ pub enum ChatListMessage {
     Chat(ChatMessage, String),
     AddChat,
     ChatAdded(ChatWithMembers),
-    UsernameInputChanged(String),
+    UserSearch(UserSearchMessage),
     MessagesLoaded(Vec<WsChatMessage>),
     LetterListMessage(LetterListMessage),
+    ArchiveToggled,
+    PreferencesSaved,
+    Tick,
+    WindowFocusChanged(bool),
+    NotificationClicked(String),
+    NotificationClosed,
     Error(String),
 }
 
 impl ChatList {
-    pub fn new(client: reqwest::Client, session: Session) -> Self {
-        Self {
-            chats: HashMap::new(),
-            client: client.clone(),
-            username_input: String::new(),
-            session: session.clone(),
-            opened_chat: None,
-            opened_chat_messages: LetterList::new(client, None, session),
+    pub fn new(client: reqwest::Client, session: Session) -> (Self, Command<ChatListMessage>) {
+        let (user_search, load_contacts) = UserSearch::new(client.clone(), session.clone());
+        let opened_chat_messages = LetterList::new(client.clone(), None, session.clone());
+        let load_limits = opened_chat_messages.load_limits();
+        (
+            Self {
+                chats: HashMap::new(),
+                client,
+                user_search,
+                session,
+                opened_chat: None,
+                opened_chat_messages,
+                show_archived: false,
+                window_focused: true,
+            },
+            Command::batch(vec![
+                load_contacts.map(ChatListMessage::UserSearch),
+                load_limits.map(ChatListMessage::LetterListMessage),
+            ]),
+        )
+    }
+
+    fn reload_last_message(&self, chat_id: String) -> Command<ChatListMessage> {
+        Command::perform(
+            server_get::<Vec<WsChatMessage>>(
+                self.client.clone(),
+                format!("messages/{chat_id}"),
+                Some(self.session.session_id.clone()),
+            ),
+            move |messages| {
+                ChatListMessage::Chat(
+                    ChatMessage::LastMessageLoaded(
+                        messages
+                            .ok()
+                            .and_then(|messages| messages.into_iter().last())
+                            .map(|mut message| {
+                                e2ee::decrypt(&mut message);
+                                message
+                            }),
+                    ),
+                    chat_id,
+                )
+            },
+        )
+    }
+
+    /// Shows a desktop notification for a message the user can't currently see
+    fn notify(&self, message: &WsChatMessage) -> Command<ChatListMessage> {
+        let is_visible = self.window_focused && self.opened_chat.as_ref() == Some(&message.chat_id);
+        if message.sender_id == self.session.user_id || is_visible {
+            return Command::none();
         }
+        let Some(chat) = self
+            .chats
+            .get(&message.chat_id)
+            .filter(|chat| !chat.preferences.is_muted())
+        else {
+            return Command::none();
+        };
+
+        let chat_id = message.chat_id.clone();
+        Command::perform(
+            notifications::show(Notification {
+                sender: member_name(&chat.members, &message.sender_id),
+                preview: truncate_message(message_text(message), 80),
+                avatar: chat.profile_picture.bytes().map(Vec::from),
+            }),
+            move |clicked| {
+                if clicked {
+                    ChatListMessage::NotificationClicked(chat_id)
+                } else {
+                    ChatListMessage::NotificationClosed
+                }
+            },
+        )
     }
 
     pub fn update(&mut self, message: ChatListMessage) -> Command<ChatListMessage> {
@@ -69,14 +151,34 @@ impl ChatList {
 
                     self.opened_chat_messages.chat_id = Some(chat_id.clone());
                     self.opened_chat_messages.replying_to = None;
-                    Command::perform(
-                        server_get::<Vec<WsChatMessage>>(
-                            self.client.clone(),
-                            format!("messages/{chat_id}"),
-                            Some(self.session.session_id.clone()),
+                    self.opened_chat_messages.reacting_to = None;
+                    self.opened_chat_messages.selected.clear();
+                    self.opened_chat_messages.reset_schedule();
+                    let chat = &self.chats[&chat_id];
+                    let load_encryption = self.opened_chat_messages.load_encryption(
+                        chat.chat_keys.last().cloned(),
+                        Chat::get_other_member(&self.session.user_id, &chat.members)
+                            .map(|member| member.id.clone()),
+                    );
+                    Command::batch(vec![
+                        Command::perform(
+                            server_get::<Vec<WsChatMessage>>(
+                                self.client.clone(),
+                                format!("messages/{chat_id}"),
+                                Some(self.session.session_id.clone()),
+                            ),
+                            |msgs| {
+                                ChatListMessage::MessagesLoaded(e2ee::decrypt_all(msgs.unwrap()))
+                            },
                         ),
-                        |msgs| ChatListMessage::MessagesLoaded(msgs.unwrap()),
-                    )
+                        self.opened_chat_messages
+                            .load_scheduled()
+                            .map(|msg| ChatListMessage::LetterListMessage(msg)),
+                        load_encryption.map(|msg| match msg {
+                            LetterListMessage::Error(err) => ChatListMessage::Error(err),
+                            msg => ChatListMessage::LetterListMessage(msg),
+                        }),
+                    ])
                 }
                 msg => self
                     .chats
@@ -91,7 +193,7 @@ impl ChatList {
                         self.client.clone(),
                         "create_chat",
                         CreateChat {
-                            other_members: self.username_input.clone(),
+                            other_members: self.user_search.query.clone(),
                         },
                         Some(self.session.session_id.clone()),
                     ),
@@ -100,30 +202,40 @@ impl ChatList {
                         Err(error) => ChatListMessage::Error(error.to_string()),
                     },
                 );
-                self.username_input = "".into();
+                self.user_search.clear();
                 command
             }
 
             ChatListMessage::ChatAdded(chat) => {
-                let (cmd, id) = Chat::new(
-                    self,
-                    self.session.user_id.clone(),
-                    chat.id,
-                    chat.members,
-                    chat.last_updated,
-                );
+                // The server returns the existing chat if there already is one with the user
+                if self.chats.contains_key(&chat.id) {
+                    return self.update(ChatListMessage::Chat(ChatMessage::OpenChat, chat.id));
+                }
+                let (cmd, id) = Chat::new(self, self.session.user_id.clone(), chat);
                 cmd.map(move |msg| ChatListMessage::Chat(msg, id.clone()))
             }
-            ChatListMessage::UsernameInputChanged(user_id) => {
-                self.username_input = user_id;
-                Command::none()
+            ChatListMessage::UserSearch(UserSearchMessage::Submit) => {
+                self.update(ChatListMessage::AddChat)
             }
+            ChatListMessage::UserSearch(UserSearchMessage::UserSelected(username)) => {
+                self.user_search.query = username;
+                self.update(ChatListMessage::AddChat)
+            }
+            ChatListMessage::UserSearch(msg) => self.user_search.update(msg).map(|msg| {
+                if let UserSearchMessage::Error(err) = msg {
+                    ChatListMessage::Error(err)
+                } else {
+                    ChatListMessage::UserSearch(msg)
+                }
+            }),
             ChatListMessage::MessagesLoaded(messages) => {
                 self.opened_chat_messages.clear();
                 for msg in messages {
                     self.opened_chat_messages.add_message(msg);
                 }
-                Command::none()
+                self.opened_chat_messages
+                    .load_preview_images()
+                    .map(|msg| ChatListMessage::LetterListMessage(msg))
             }
             ChatListMessage::LetterListMessage(msg) => match msg {
                 LetterListMessage::MessageSent { .. }
@@ -138,23 +250,138 @@ impl ChatList {
                         .map(|msg| ChatListMessage::LetterListMessage(msg))
                 }
                 LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::CreateChat(chat))) => {
+                    if self.chats.contains_key(&chat.chat_id) {
+                        return Command::none();
+                    }
                     self.update(ChatListMessage::ChatAdded(ChatWithMembers {
                         id: chat.chat_id,
                         members: chat.members,
                         last_updated: Utc::now(),
+                        preferences: ChatPreferences::default(),
+                        last_message: None,
+                        message_ttl: None,
+                        chat_keys: Vec::new(),
                     }))
                 }
+                // Left from another device, the chat stays for the other member
+                LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::LeaveChat(chat))) => {
+                    if chat.member == self.session.user_id {
+                        self.update(ChatListMessage::LetterListMessage(
+                            LetterListMessage::ChatDeleted(chat),
+                        ))
+                    } else {
+                        Command::none()
+                    }
+                }
+                LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::ChatPreferences(
+                    update,
+                ))) => {
+                    if let Some(chat) = self.chats.get_mut(&update.chat_id) {
+                        chat.preferences = update.preferences;
+                    }
+                    Command::none()
+                }
+                LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::ChatTtl(update))) => {
+                    if let Some(chat) = self.chats.get_mut(&update.chat_id) {
+                        chat.message_ttl = update.message_ttl;
+                    }
+                    Command::none()
+                }
+                LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::ProfileUpdated(
+                    status,
+                ))) => {
+                    let own_id = self.session.user_id.clone();
+                    let user_search = self
+                        .user_search
+                        .profile_updated(&status)
+                        .map(ChatListMessage::UserSearch);
+                    let chats = Command::batch(
+                        self.chats
+                            .iter_mut()
+                            .filter_map(|(chat_id, chat)| {
+                                let member = chat
+                                    .members
+                                    .iter_mut()
+                                    .find(|member| member.id == status.id)?;
+                                member.username = status.username.clone();
+                                member.display_name = status.display_name.clone();
+                                if status.id == own_id {
+                                    return None;
+                                }
+                                let chat_id = chat_id.clone();
+                                Some(
+                                    chat.update(ChatMessage::StatusLoaded(Some(status.clone())))
+                                        .map(move |msg| {
+                                            ChatListMessage::Chat(msg, chat_id.clone())
+                                        }),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    );
+                    Command::batch(vec![user_search, chats])
+                }
+                LetterListMessage::WsEvent(WsEvent::Message(WsMessageData::ContactsChanged)) => {
+                    self.update(ChatListMessage::UserSearch(
+                        UserSearchMessage::ReloadContacts,
+                    ))
+                }
                 LetterListMessage::WsEvent(WsEvent::Message(ref ws_msg)) => {
-                    match ws_msg {
-                        WsMessageData::ChatMessage(WsChatMessage { chat_id, .. })
-                        | WsMessageData::DeleteMessage(WsDeleteMessage { chat_id, .. }) => {
-                            self.chats.get_mut(chat_id).unwrap().last_updated = Utc::now();
+                    let notification = match ws_msg {
+                        WsMessageData::ChatMessage(chat_message) => {
+                            let chat = self.chats.get_mut(&chat_message.chat_id).unwrap();
+                            chat.last_updated = Utc::now();
+                            chat.last_message = Some(chat_message.as_ref().clone());
+                            self.notify(chat_message)
                         }
-                        _ => {}
-                    }
-                    self.opened_chat_messages
-                        .update(msg)
-                        .map(|msg| ChatListMessage::LetterListMessage(msg))
+                        WsMessageData::ChatKey(chat_key) => {
+                            if let Some(chat) = self.chats.get_mut(&chat_key.chat_id) {
+                                chat.chat_keys.push(chat_key.clone());
+                            }
+                            Command::none()
+                        }
+                        WsMessageData::DeleteMessage(WsDeleteMessage {
+                            chat_id,
+                            message_id,
+                        }) => {
+                            let chat = self.chats.get_mut(chat_id).unwrap();
+                            chat.last_updated = Utc::now();
+                            if chat
+                                .last_message
+                                .as_ref()
+                                .is_some_and(|message| &message.message_id == message_id)
+                            {
+                                self.reload_last_message(chat_id.clone())
+                            } else {
+                                Command::none()
+                            }
+                        }
+                        _ => Command::none(),
+                    };
+                    Command::batch(vec![
+                        notification,
+                        self.opened_chat_messages
+                            .update(msg)
+                            .map(|msg| ChatListMessage::LetterListMessage(msg)),
+                    ])
+                }
+                LetterListMessage::PreferencesChanged(preferences) => {
+                    let chat_id = self.opened_chat.clone().unwrap();
+                    self.chats.get_mut(&chat_id).unwrap().preferences = preferences.clone();
+                    Command::perform(
+                        server_post::<()>(
+                            self.client.clone(),
+                            "chat_preferences",
+                            UpdateChatPreferences {
+                                chat_id,
+                                preferences,
+                            },
+                            Some(self.session.session_id.clone()),
+                        ),
+                        |result| match result {
+                            Ok(_) => ChatListMessage::PreferencesSaved,
+                            Err(err) => ChatListMessage::Error(err.to_string()),
+                        },
+                    )
                 }
                 LetterListMessage::ChatDeleted(chat) => {
                     if self
@@ -176,49 +403,89 @@ impl ChatList {
                     }
                 }),
             },
+            ChatListMessage::ArchiveToggled => {
+                self.show_archived = !self.show_archived;
+                Command::none()
+            }
+            ChatListMessage::WindowFocusChanged(focused) => {
+                self.window_focused = focused;
+                Command::none()
+            }
+            ChatListMessage::NotificationClicked(chat_id) => {
+                if !self.chats.contains_key(&chat_id) {
+                    return Command::none();
+                }
+                Command::batch(vec![
+                    window::gain_focus(),
+                    self.update(ChatListMessage::Chat(ChatMessage::OpenChat, chat_id)),
+                ])
+            }
             _ => Command::none(),
         }
     }
 
     pub fn subscription(&self) -> iced::Subscription<ChatListMessage> {
-        self.opened_chat_messages
-            .subscription()
-            .map(|msg| ChatListMessage::LetterListMessage(msg))
+        iced::Subscription::batch(vec![
+            self.opened_chat_messages
+                .subscription()
+                .map(|msg| ChatListMessage::LetterListMessage(msg)),
+            // Keeps relative timestamps of the chats fresh
+            iced::time::every(std::time::Duration::from_secs(60)).map(|_| ChatListMessage::Tick),
+            iced::subscription::events_with(|event, _| match event {
+                iced::Event::Window(window::Event::Focused) => {
+                    Some(ChatListMessage::WindowFocusChanged(true))
+                }
+                iced::Event::Window(window::Event::Unfocused) => {
+                    Some(ChatListMessage::WindowFocusChanged(false))
+                }
+                _ => None,
+            }),
+        ])
     }
 
     pub fn view(&self, current_user_id: String) -> Element<ChatListMessage> {
-        let mut chats = self
-            .chats
-            .iter()
-            .map(|chat| {
-                (
-                    chat.1
-                        .view(current_user_id.clone())
-                        .map(|msg| ChatListMessage::Chat(msg, chat.0.clone())),
-                    chat.1.last_updated,
-                )
-            })
-            .collect::<Vec<_>>();
+        // Pinned chats go first, then the most recently updated ones
+        let chat_views = |archived: bool| {
+            let mut chats = self
+                .chats
+                .iter()
+                .filter(|(_, chat)| chat.preferences.archived == archived)
+                .collect::<Vec<_>>();
+            chats.sort_unstable_by_key(|(_, chat)| (chat.preferences.pinned, chat.last_updated));
+            chats
+                .into_iter()
+                .rev()
+                .map(|(id, chat)| {
+                    chat.view(current_user_id.clone())
+                        .map(|msg| ChatListMessage::Chat(msg, id.clone()))
+                })
+                .collect::<Vec<_>>()
+        };
 
-        chats.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
+        let mut chats = column(chat_views(false));
+        let archived_chats = chat_views(true);
+        if !archived_chats.is_empty() {
+            chats = chats.push(
+                container(
+                    button(text(format!("Архив ({})", archived_chats.len())))
+                        .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+                        .on_press(ChatListMessage::ArchiveToggled)
+                        .width(Length::Fill),
+                )
+                .padding(5),
+            );
+            if self.show_archived {
+                for chat in archived_chats {
+                    chats = chats.push(chat);
+                }
+            }
+        }
 
         let mut chat_list_letter_list = row![container(
             column![
-                row![
-                    text_input("Имя пользователя", &self.username_input)
-                        .padding(8)
-                        .on_input(ChatListMessage::UsernameInputChanged)
-                        .on_submit(ChatListMessage::AddChat),
-                    icon_button('').on_press(ChatListMessage::AddChat),
-                ]
-                .spacing(5),
-                scrollable(
-                    container(column(
-                        chats.into_iter().rev().map(|(chat, _)| chat).collect()
-                    ))
-                    .padding([0, 10, 0, 0]),
-                )
-                .style(Scrollable::Custom(Box::new(ScrollableStyle))),
+                self.user_search.view().map(ChatListMessage::UserSearch),
+                scrollable(container(chats).padding([0, 10, 0, 0]))
+                    .style(Scrollable::Custom(Box::new(ScrollableStyle))),
             ]
             .max_width(350)
             .spacing(5)
@@ -236,6 +503,24 @@ impl ChatList {
                         .view(
                             self.chats.get(opened_chat).unwrap().members.clone(),
                             current_user_id,
+                            self.chats.get(opened_chat).unwrap().preferences.clone(),
+                            self.chats.get(opened_chat).unwrap().status.clone(),
+                            self.chats.get(opened_chat).unwrap().message_ttl,
+                            self.chats
+                                .iter()
+                                // Forwarded messages can't be encrypted
+                                .filter(|(_, chat)| chat.chat_keys.is_empty())
+                                .map(|(id, chat)| ForwardTarget {
+                                    chat_id: id.clone(),
+                                    name: Chat::get_other_member(
+                                        &self.session.user_id,
+                                        &chat.members,
+                                    )
+                                    .map(ChatMember::name)
+                                    .unwrap_or("Удалённый аккаунт")
+                                    .into(),
+                                })
+                                .collect(),
                         )
                         .map(|msg| ChatListMessage::LetterListMessage(msg)),
                 )
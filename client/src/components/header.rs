@@ -1,25 +1,26 @@
 use iced::{
-    widget::{container, row, text, Space},
-    Color, Length, Theme,
+    widget::{column, container, row, text, Space},
+    Length, Theme,
 };
-use structs::requests::Session;
+use structs::requests::{Session, UserStatus};
 
-use crate::server::get_profile_picture;
+use crate::{server::get_user_status, theme};
 
 use super::{
-    icon_button,
+    icon_button, style_muted,
     web_image::{WebImage, WebImageMessage},
 };
 
 pub struct Header {
     pub session: Session,
+    pub display_name: Option<String>,
     pub profile_picture: WebImage,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum HeaderMessage {
     ProfilePicture(WebImageMessage),
-    ProfilePictureLoaded(Option<String>),
+    StatusLoaded(Option<UserStatus>),
     SettingsOpen,
     LogOut,
 }
@@ -27,15 +28,17 @@ pub enum HeaderMessage {
 impl Header {
     pub fn new(session: Session, client: reqwest::Client) -> (Self, iced::Command<HeaderMessage>) {
         let user = session.user_id.clone();
+        let session_id = session.session_id.clone();
         let header = Self {
             session,
+            display_name: None,
             profile_picture: WebImage::new(client.clone()),
         };
         (
             header,
             iced::Command::perform(
-                get_profile_picture(client, user),
-                HeaderMessage::ProfilePictureLoaded,
+                get_user_status(client, session_id, user),
+                HeaderMessage::StatusLoaded,
             ),
         )
     }
@@ -46,8 +49,13 @@ impl Header {
                 .profile_picture
                 .update(msg)
                 .map(HeaderMessage::ProfilePicture),
-            HeaderMessage::ProfilePictureLoaded(pfp) => {
-                if let Some(pfp) = pfp {
+            HeaderMessage::StatusLoaded(status) => {
+                let Some(status) = status else {
+                    return iced::Command::none();
+                };
+                self.session.username = status.username;
+                self.display_name = status.display_name;
+                if let Some(pfp) = status.profile_picture {
                     self.profile_picture
                         .load_image(pfp)
                         .map(HeaderMessage::ProfilePicture)
@@ -65,16 +73,20 @@ impl Header {
             .view()
             .width(Length::Fixed(50.))
             .height(Length::Fixed(50.));
-        let container_style = |_: &Theme| container::Appearance {
+        let container_style = |style: &Theme| container::Appearance {
             border_radius: 0.0.into(),
             border_width: 1.0,
-            border_color: Color::from_rgba8(0, 0, 0, 0.4),
+            border_color: theme::outline(style),
             ..Default::default()
         };
         container(
             row![
                 pfp,
-                text(self.session.user_id.clone()),
+                column![
+                    text(self.display_name.as_ref().unwrap_or(&self.session.username)),
+                    container(text(format!("@{}", self.session.username)).size(12))
+                        .style(style_muted)
+                ],
                 Space::with_width(Length::Fill),
                 icon_button('').on_press(HeaderMessage::SettingsOpen),
                 icon_button('').on_press(HeaderMessage::LogOut)
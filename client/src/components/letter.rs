@@ -1,21 +1,39 @@
 use iced::{
+    font,
     theme::Button,
-    widget::{button, column, container, row, text},
-    Color, Element, Length,
+    widget::{button, column, container, image, mouse_area, row, text, Space},
+    Element, Font, Length,
+};
+use structs::{
+    requests::{ChatMember, MessageKind, WsChatMessage},
+    DateTime, Local,
 };
-use structs::{requests::WsChatMessage, DateTime, Local};
 
-use crate::components::truncate_message;
+use crate::{
+    components::{format_remaining, member_name, message_text, rich_text, truncate_message},
+    voice,
+};
 
-use super::{icon_button, letter_list::LetterList, ButtonStyle};
+use super::{
+    icon_button, letter_list::LetterList, style_muted, style_own_muted, style_own_waveform,
+    style_preview, style_waveform, ButtonStyle,
+};
 
 #[derive(Clone)]
 pub struct Letter(pub WsChatMessage);
 
+/// Offered by the reaction picker
+const QUICK_REACTIONS: [&str; 6] = ["👍", "❤️", "😂", "😮", "😢", "🔥"];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum LetterMessage {
     ReplyStarted,
     LetterDelete,
+    ReactionPickerToggled,
+    ReactionToggled(String),
+    SelectToggled,
+    LinkClicked(String),
+    VoicePlayToggled,
 }
 
 impl Letter {
@@ -23,9 +41,21 @@ impl Letter {
         &self,
         letter_list: &LetterList,
         current_user_id: String,
+        members: &[ChatMember],
     ) -> Element<LetterMessage> {
         // TODO: put your messages on the right
-        let nickname = self.0.sender_id.clone();
+        let is_own = self.0.sender_id == current_user_id;
+        let muted_style = if is_own { style_own_muted } else { style_muted };
+        let sender = members.iter().find(|member| member.id == self.0.sender_id);
+        let mut nickname = row![text(
+            sender.map(ChatMember::name).unwrap_or("Удалённый аккаунт")
+        )]
+        .spacing(5)
+        .align_items(iced::Alignment::Center);
+        if let Some(sender) = sender {
+            nickname = nickname
+                .push(container(text(format!("@{}", sender.username)).size(11)).style(muted_style));
+        }
         let reply_message = self
             .0
             .reply_to
@@ -35,8 +65,8 @@ impl Letter {
         let message_column = if let Some(reply_message) = reply_message {
             column![text(&format!(
                 "↱ {}: {}",
-                reply_message.0.sender_id,
-                truncate_message(reply_message.0.message.clone(), 80)
+                member_name(members, &reply_message.0.sender_id),
+                truncate_message(message_text(&reply_message.0), 80)
             ))
             .size(12),]
         } else {
@@ -44,33 +74,169 @@ impl Letter {
         };
 
         let local_created_at: DateTime<Local> = self.0.created_at.into();
+        let mut timestamp = local_created_at.format("%d/%m/%Y %H:%M").to_string();
+        if let Some(expires_at) = self.0.expires_at {
+            timestamp = format!("{timestamp} · ⏱ {}", format_remaining(expires_at));
+        }
 
-        let message_row = row![button(
-            column![
-                text(nickname.clone()),
-                text(self.0.message.clone()),
-                text(local_created_at.format("%d/%m/%Y %H:%M").to_string())
-                    .style(if nickname == current_user_id {
-                        Color::from_rgba8(255, 255, 255, 0.5)
+        let reactions = row(self
+            .0
+            .reactions
+            .iter()
+            .map(|reaction| {
+                let reacted = reaction.user_ids.contains(&current_user_id);
+                button(text(format!("{} {}", reaction.emoji, reaction.user_ids.len())).size(12))
+                    .padding([2, 8])
+                    .style(Button::Custom(Box::new(if reacted {
+                        ButtonStyle::Blue
                     } else {
-                        Color::from_rgba8(0, 0, 0, 0.5)
-                    })
-                    .size(11)
-            ]
-            .spacing(5),
-        )
-        .padding(10)
-        .style(Button::Custom(Box::new(if nickname == current_user_id {
-            ButtonStyle::Blue
+                        ButtonStyle::Hover
+                    })))
+                    .on_press(LetterMessage::ReactionToggled(reaction.emoji.clone()))
+                    .into()
+            })
+            .collect())
+        .spacing(5);
+
+        let picker_open = letter_list.reacting_to.as_ref() == Some(&self.0.message_id);
+        let reaction_picker = if picker_open {
+            row(QUICK_REACTIONS
+                .into_iter()
+                .map(|emoji| {
+                    button(text(emoji))
+                        .padding([2, 6])
+                        .style(Button::Custom(Box::new(ButtonStyle::Hover)))
+                        .on_press(LetterMessage::ReactionToggled(emoji.into()))
+                        .into()
+                })
+                .collect())
+            .spacing(5)
         } else {
-            ButtonStyle::Simple
-        })))
-        .on_press(LetterMessage::ReplyStarted),]
+            row![]
+        };
+
+        let mut bubble = column![nickname].spacing(5);
+        if let Some(original) = &self.0.forwarded_from {
+            let original_created_at: DateTime<Local> = original.created_at.into();
+            let sender = if original.sender_name.is_empty() {
+                "Удалённый аккаунт"
+            } else {
+                &original.sender_name
+            };
+            bubble = bubble.push(
+                container(
+                    text(format!(
+                        "↪ Переслано от {sender}, {}",
+                        original_created_at.format("%d/%m/%Y %H:%M")
+                    ))
+                    .size(12),
+                )
+                .style(muted_style),
+            );
+        }
+
+        let mut content = column![].spacing(5);
+        if let MessageKind::Voice(voice_note) = &self.0.kind {
+            let is_playing = letter_list
+                .playing
+                .as_ref()
+                .is_some_and(|(id, _)| id == &self.0.message_id);
+            let waveform_style = if is_own {
+                style_own_waveform
+            } else {
+                style_waveform
+            };
+            let waveform = row(voice_note
+                .waveform
+                .iter()
+                .map(|&level| {
+                    container(Space::new(3, 2.0 + level as f32 / 255.0 * 22.0))
+                        .style(waveform_style)
+                        .into()
+                })
+                .collect())
+            .spacing(1)
+            .align_items(iced::Alignment::Center);
+            content = content.push(
+                row![
+                    icon_button(if is_playing { '' } else { '' })
+                        .style(Button::Custom(Box::new(ButtonStyle::Hover)))
+                        .on_press(LetterMessage::VoicePlayToggled),
+                    waveform,
+                    text(voice::format_duration(std::time::Duration::from_millis(
+                        voice_note.duration_ms.into()
+                    )))
+                    .size(12),
+                ]
+                .spacing(8)
+                .align_items(iced::Alignment::Center),
+            );
+        }
+        if !self.0.message.is_empty() {
+            content = content.push(rich_text::view(
+                &self.0.message,
+                is_own,
+                LetterMessage::LinkClicked,
+            ));
+        }
+        if let Some(preview) = &self.0.link_preview {
+            let mut card = column![].spacing(4).max_width(360);
+            if let Some(handle) = preview
+                .image
+                .as_ref()
+                .and_then(|name| letter_list.preview_images.get(name))
+            {
+                card = card.push(image(handle.clone()).width(Length::Fill));
+            }
+            if let Some(title) = &preview.title {
+                card = card.push(text(title).font(Font {
+                    weight: font::Weight::Bold,
+                    ..rich_text::TEXT_FONT
+                }));
+            }
+            if let Some(description) = &preview.description {
+                card = card.push(
+                    container(text(truncate_message(description.clone(), 200)).size(12))
+                        .style(muted_style),
+                );
+            }
+            content = content.push(
+                mouse_area(container(card).padding(8).style(style_preview))
+                    .on_press(LetterMessage::LinkClicked(preview.url.clone())),
+            );
+        }
+
+        let is_selected = letter_list.selected.contains(&self.0.message_id);
+        let message_row = row![
+            icon_button(if is_selected { '' } else { '' })
+                .style(Button::Custom(Box::new(ButtonStyle::Hover)))
+                .on_press(LetterMessage::SelectToggled),
+            button(
+                bubble
+                    .push(content)
+                    .push(container(text(timestamp).size(11)).style(muted_style))
+            )
+            .padding(10)
+            .style(Button::Custom(Box::new(if is_own {
+                ButtonStyle::Blue
+            } else {
+                ButtonStyle::Simple
+            })))
+            .on_press(LetterMessage::ReplyStarted),
+        ]
+        .push(
+            container(
+                icon_button('')
+                    .style(Button::Custom(Box::new(ButtonStyle::Hover)))
+                    .on_press(LetterMessage::ReactionPickerToggled),
+            )
+            .center_y(),
+        )
         .align_items(iced::Alignment::Center);
         container(
             message_column
                 .push(
-                    if nickname == current_user_id {
+                    if is_own {
                         message_row.push(
                             container(
                                 icon_button('')
@@ -84,6 +250,8 @@ impl Letter {
                     }
                     .spacing(15),
                 )
+                .push(reactions)
+                .push(reaction_picker)
                 .spacing(8),
         )
         .padding([0, 10, 0, 0])
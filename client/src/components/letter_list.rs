@@ -5,67 +5,535 @@ use super::{
     ScrollableStyle,
 };
 use crate::{
-    components::{truncate_message, ButtonStyle},
-    server::server_post,
+    components::{
+        member_name, message_text, style_error, style_muted, truncate_message, ButtonStyle,
+    },
+    e2ee::{self, Verification},
+    server::{self, server_get, server_post},
+    voice::{self, Recorder},
     ws_client,
 };
 use iced::{
-    alignment,
+    alignment, keyboard,
     theme::{Button, Scrollable},
     widget::{
-        button, column, row, scrollable, scrollable::RelativeOffset, text, text_input, Space,
+        button, column, container, image, pick_list, row, scrollable, scrollable::RelativeOffset,
+        text, text_input, Space,
     },
-    Command, Element, Length,
+    Command, Element, Event, Length,
 };
 use indexmap::IndexMap;
+use native_dialog::FileDialog;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use structs::requests::{
-    CreateMessage, DeleteMessage, LeaveChat, Session, WsChatMessage, WsLeaveChat, WsMessageData,
+    BlockRequest, CancelScheduledMessage, ChatKey, ChatMember, ChatPreferences, CreateMessage,
+    DeleteMessage, EditScheduledMessage, ForwardMessages, ForwardedFrom, IdentityKey, LeaveChat,
+    Limits, LinkPreview, MessageKind, React, ScheduledMessage, Session, SetChatTtl, UserStatus,
+    VoiceNote, WsChatMessage, WsLeaveChat, WsMessageData,
+};
+use structs::{
+    archive::ChatArchive, markdown::to_plain_text, DateTime, Duration, Local, NaiveDateTime,
+    TimeZone, Utc,
 };
-use structs::Utc;
+
+/// How the time of a scheduled message is typed and shown
+const SCHEDULE_FORMAT: &str = "%d.%m.%Y %H:%M";
+
+/// Reads a local time typed in `SCHEDULE_FORMAT`
+fn parse_schedule_time(input: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(input.trim(), SCHEDULE_FORMAT).ok()?;
+    let local = Local.from_local_datetime(&naive).single()?;
+    Some(local.with_timezone(&Utc))
+}
+
+fn format_schedule_time(time: DateTime<Utc>) -> String {
+    let local: DateTime<Local> = time.into();
+    local.format(SCHEDULE_FORMAT).to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuteOption {
+    Unmute,
+    Hour,
+    EightHours,
+    Forever,
+}
+
+impl MuteOption {
+    const ALL: [MuteOption; 4] = [
+        MuteOption::Unmute,
+        MuteOption::Hour,
+        MuteOption::EightHours,
+        MuteOption::Forever,
+    ];
+
+    fn muted_until(self) -> Option<DateTime<Utc>> {
+        match self {
+            MuteOption::Unmute => None,
+            MuteOption::Hour => Some(Utc::now() + Duration::hours(1)),
+            MuteOption::EightHours => Some(Utc::now() + Duration::hours(8)),
+            MuteOption::Forever => Some(Utc.with_ymd_and_hms(9999, 12, 31, 0, 0, 0).unwrap()),
+        }
+    }
+}
+
+impl Display for MuteOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MuteOption::Unmute => "Включить звук",
+            MuteOption::Hour => "Без звука на час",
+            MuteOption::EightHours => "Без звука на 8 часов",
+            MuteOption::Forever => "Без звука навсегда",
+        })
+    }
+}
+
+/// Retention choices offered for a chat, any other value set from elsewhere is still shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlOption {
+    Off,
+    Hour,
+    Day,
+    Week,
+}
+
+impl TtlOption {
+    const ALL: [TtlOption; 4] = [
+        TtlOption::Off,
+        TtlOption::Hour,
+        TtlOption::Day,
+        TtlOption::Week,
+    ];
+
+    fn message_ttl(self) -> Option<u32> {
+        match self {
+            TtlOption::Off => None,
+            TtlOption::Hour => Some(60 * 60),
+            TtlOption::Day => Some(24 * 60 * 60),
+            TtlOption::Week => Some(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl Display for TtlOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.message_ttl() {
+            Some(ttl) => write!(f, "Исчезают через {}", ttl_label(ttl)),
+            None => f.write_str("Не исчезают"),
+        }
+    }
+}
+
+fn ttl_label(ttl: u32) -> String {
+    match ttl {
+        3600 => "час".into(),
+        86400 => "день".into(),
+        604800 => "неделю".into(),
+        ttl if ttl < 3600 => format!("{} мин", ttl / 60),
+        ttl if ttl < 86400 => format!("{} ч", ttl / 3600),
+        ttl => format!("{} д", ttl / 86400),
+    }
+}
+
+/// Chat messages can be forwarded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardTarget {
+    pub chat_id: String,
+    pub name: String,
+}
+
+impl Display for ForwardTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
 
 #[derive(Clone)]
 pub struct LetterList {
     pub messages: IndexMap<String, Letter>,
+    /// The whole draft, the input edits only its last line
     pub message_input: String,
+    pub input_id: text_input::Id,
+    /// Shift+Enter starts a new line instead of sending
+    pub shift_held: bool,
+    pub limits: Limits,
     pub client: reqwest::Client,
     pub chat_id: Option<String>,
     pub session: Session,
     pub replying_to: Option<String>,
+    /// Message with the reaction picker open
+    pub reacting_to: Option<String>,
+    /// Messages picked for forwarding, in the order they were picked
+    pub selected: Vec<String>,
+    /// Link preview images by their name on the server
+    pub preview_images: HashMap<String, image::Handle>,
+    pub recorder: Option<Recorder>,
+    /// Voice message being played and the flag that stops it
+    pub playing: Option<(String, Arc<AtomicBool>)>,
+    /// Time the draft is sent at, the draft goes out right away while it's `None`
+    pub schedule_input: Option<String>,
+    /// Own messages waiting to be sent to this chat
+    pub scheduled: Vec<ScheduledMessage>,
+    pub show_scheduled: bool,
+    /// Scheduled message loaded into the composer
+    pub editing_scheduled: Option<String>,
+    /// Latest key of the chat, new messages are encrypted with it
+    pub chat_key: Option<ChatKey>,
+    /// Of the other member, from the key directory
+    pub peer_identity: Option<IdentityKey>,
+    pub show_safety_number: bool,
     pub scrollable_id: scrollable::Id,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum LetterListMessage {
     LetterMessage(LetterMessage, String),
     MessageInputChanged(String),
+    ShiftChanged(bool),
+    LimitsLoaded(Limits),
+    /// Enter in the input, sends unless Shift is held
+    InputSubmitted,
     SendPressed,
     MessageSent {
         id: String,
         message: String,
         sender: String,
         reply_to: Option<String>,
+        forwarded_from: Option<ForwardedFrom>,
+        link_preview: Option<LinkPreview>,
+        kind: MessageKind,
+        expires_at: Option<DateTime<Utc>>,
+        key_id: Option<String>,
     },
+    PeerIdentityLoaded(IdentityKey),
+    EnableEncryption,
+    /// The key itself arrives over the socket
+    EncryptionEnabled,
+    SafetyNumberToggled,
+    MarkVerified,
+    RecordPressed,
+    RecordingTick,
+    /// Sends the recording
+    RecordingStopped,
+    RecordingCancelled,
+    VoicePlaybackFinished(String, Result<(), String>),
+    ScheduleToggled,
+    ScheduleInputChanged(String),
+    ScheduledLoaded(Vec<ScheduledMessage>),
+    ScheduledListToggled,
+    ScheduledEdit(String),
+    ScheduledCancel(String),
+    /// Reloads the scheduled messages after one was added, edited or cancelled
+    ScheduledChanged,
+    TtlChanged(TtlOption),
+    TtlSaved,
+    /// Refreshes the countdowns of disappearing messages
+    ExpiryTick,
     CancelReply,
     MessageDeleted(String),
     WsEvent(ws_client::WsEvent),
     ChatDelete,
     ChatDeleted(WsLeaveChat),
+    PreferencesChanged(ChatPreferences),
+    ReactionSaved,
+    PreviewImageLoaded(String, Option<Vec<u8>>),
+    ForwardTo(ForwardTarget),
+    Forwarded,
+    CancelSelection,
+    BlockUser(String),
+    UserBlocked,
+    ExportChat,
+    ChatExported,
     Error(String),
 }
 
+/// Encodes, uploads and sends a recording, returns the id of the message and the note
+async fn send_voice(
+    client: reqwest::Client,
+    session_id: String,
+    chat_id: String,
+    reply_to_id: Option<String>,
+    samples: Vec<f32>,
+) -> Result<(String, VoiceNote), String> {
+    let recording = voice::encode(samples).await?;
+    let duration_ms = recording.duration.as_millis() as u32;
+    let waveform = recording.waveform.clone();
+    let voice_note_id = server::upload_voice(client.clone(), session_id.clone(), recording)
+        .await
+        .map_err(|err| err.to_string())?;
+    let message_id = server_post::<String>(
+        client,
+        "create_message",
+        CreateMessage {
+            chat_id,
+            content: String::new(),
+            reply_to_id,
+            voice_note_id: Some(voice_note_id.clone()),
+            scheduled_for: None,
+            key_id: None,
+        },
+        Some(session_id),
+    )
+    .await
+    .map_err(|err| err.to_string())?;
+    Ok((
+        message_id,
+        VoiceNote {
+            id: voice_note_id,
+            duration_ms,
+            waveform,
+        },
+    ))
+}
+
+/// Saves the archive of the chat as HTML if `path` ends in `.html`, as JSON otherwise.
+/// Encrypted messages are decrypted first
+async fn export_chat(
+    client: reqwest::Client,
+    session_id: String,
+    chat_id: String,
+    path: PathBuf,
+) -> Result<(), String> {
+    let mut archive =
+        server_get::<ChatArchive>(client, format!("chats/{chat_id}/export"), Some(session_id))
+            .await
+            .map_err(|err| err.to_string())?;
+    e2ee::decrypt_archive(&mut archive);
+    let contents = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("html" | "htm") => archive.to_html(),
+        _ => serde_json::to_string_pretty(&archive).unwrap(),
+    };
+    tokio::fs::write(&path, contents)
+        .await
+        .map_err(|err| format!("Не удалось сохранить архив: {err}"))
+}
+
+/// Key to encrypt with, a new one is agreed on if the current one was made for an identity key
+/// the other member has since replaced, or on another device
+async fn current_chat_key(
+    client: reqwest::Client,
+    session_id: String,
+    own_id: String,
+    chat_id: String,
+    chat_key: Option<ChatKey>,
+    peer_key: Option<String>,
+) -> Result<String, String> {
+    let peer_key = peer_key.ok_or("Собеседник ещё не может получать зашифрованные сообщения")?;
+    if let Some(chat_key) = chat_key {
+        let agreed_peer_key = if chat_key.initiator_id == own_id {
+            &chat_key.responder_key
+        } else {
+            &chat_key.initiator_key
+        };
+        if agreed_peer_key == &peer_key && e2ee::has_key(&chat_key.id) {
+            return Ok(chat_key.id);
+        }
+    }
+    let (request, key) = e2ee::agree(&chat_id, &peer_key).ok_or("Неверный ключ собеседника")?;
+    let chat_key = server_post::<ChatKey>(client, "chat_keys", request, Some(session_id))
+        .await
+        .map_err(|err| err.to_string())?;
+    e2ee::remember(&chat_key.id, key);
+    Ok(chat_key.id)
+}
+
+/// Sends a text message, encrypted if the chat has a key. Returns the id of the message and
+/// the key it was encrypted with
+async fn send_text(
+    client: reqwest::Client,
+    session_id: String,
+    own_id: String,
+    message: CreateMessage,
+    chat_key: Option<ChatKey>,
+    peer_key: Option<String>,
+) -> Result<(String, Option<String>), String> {
+    let message = match chat_key {
+        Some(chat_key) => {
+            let key_id = current_chat_key(
+                client.clone(),
+                session_id.clone(),
+                own_id.clone(),
+                message.chat_id.clone(),
+                Some(chat_key),
+                peer_key,
+            )
+            .await?;
+            let content = e2ee::encrypt(&key_id, &own_id, &message.content)
+                .ok_or("Не удалось зашифровать сообщение")?;
+            CreateMessage {
+                content,
+                key_id: Some(key_id),
+                ..message
+            }
+        }
+        None => message,
+    };
+    let key_id = message.key_id.clone();
+    let id = server_post::<String>(client, "create_message", message, Some(session_id))
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok((id, key_id))
+}
+
 impl LetterList {
     pub fn new(client: reqwest::Client, chat_id: Option<String>, session: Session) -> Self {
         Self {
             messages: IndexMap::new(),
             message_input: String::new(),
+            input_id: text_input::Id::unique(),
+            shift_held: false,
+            limits: Limits::default(),
             client,
             chat_id,
             session,
             replying_to: None,
+            reacting_to: None,
+            selected: Vec::new(),
+            preview_images: HashMap::new(),
+            recorder: None,
+            playing: None,
+            schedule_input: None,
+            scheduled: Vec::new(),
+            show_scheduled: false,
+            editing_scheduled: None,
+            chat_key: None,
+            peer_identity: None,
+            show_safety_number: false,
             scrollable_id: scrollable::Id::unique(),
         }
     }
 
+    pub fn load_limits(&self) -> Command<LetterListMessage> {
+        Command::perform(
+            server_get::<Limits>(self.client.clone(), "limits".into(), None),
+            |limits| LetterListMessage::LimitsLoaded(limits.unwrap_or_default()),
+        )
+    }
+
+    pub fn load_scheduled(&self) -> Command<LetterListMessage> {
+        let Some(chat_id) = &self.chat_id else {
+            return Command::none();
+        };
+        Command::perform(
+            server_get::<Vec<ScheduledMessage>>(
+                self.client.clone(),
+                format!("scheduled/{chat_id}"),
+                Some(self.session.session_id.clone()),
+            ),
+            |scheduled| LetterListMessage::ScheduledLoaded(scheduled.unwrap_or_default()),
+        )
+    }
+
+    /// Shows the encryption state of the newly opened chat, `peer_id` is the other member
+    pub fn load_encryption(
+        &mut self,
+        chat_key: Option<ChatKey>,
+        peer_id: Option<String>,
+    ) -> Command<LetterListMessage> {
+        self.chat_key = chat_key;
+        self.show_safety_number = false;
+        self.peer_identity = peer_id.map(|user_id| IdentityKey {
+            user_id,
+            identity_key: None,
+        });
+        let Some(peer) = &self.peer_identity else {
+            return Command::none();
+        };
+        Command::perform(
+            server_get::<IdentityKey>(
+                self.client.clone(),
+                format!("identity_key/{}", peer.user_id),
+                Some(self.session.session_id.clone()),
+            ),
+            |identity| match identity {
+                Ok(identity) => LetterListMessage::PeerIdentityLoaded(identity),
+                Err(err) => LetterListMessage::Error(err.to_string()),
+            },
+        )
+    }
+
+    fn peer_key(&self) -> Option<String> {
+        self.peer_identity.as_ref()?.identity_key.clone()
+    }
+
+    /// Closes the schedule options, e.g. when another chat is opened
+    pub fn reset_schedule(&mut self) {
+        self.schedule_input = None;
+        self.scheduled.clear();
+        self.show_scheduled = false;
+        self.editing_scheduled = None;
+    }
+
+    /// Sends the draft at the typed time or saves it to the scheduled message being edited
+    fn schedule_draft(&mut self, input: &str) -> Command<LetterListMessage> {
+        let Some(scheduled_for) = parse_schedule_time(input) else {
+            return Command::perform(
+                async {
+                    "Укажите время в формате ДД.ММ.ГГГГ ЧЧ:ММ".to_string()
+                },
+                LetterListMessage::Error,
+            );
+        };
+        let content = std::mem::take(&mut self.message_input);
+        self.schedule_input = None;
+        let result = |result: Result<(), server::ServerRequestError>| match result {
+            Ok(_) => LetterListMessage::ScheduledChanged,
+            Err(err) => LetterListMessage::Error(err.to_string()),
+        };
+        match self.editing_scheduled.take() {
+            Some(id) => Command::perform(
+                server_post::<ScheduledMessage>(
+                    self.client.clone(),
+                    "edit_scheduled",
+                    EditScheduledMessage {
+                        id,
+                        content,
+                        scheduled_for,
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                move |scheduled| result(scheduled.map(|_| ())),
+            ),
+            None => Command::perform(
+                server_post::<String>(
+                    self.client.clone(),
+                    "create_message",
+                    CreateMessage {
+                        chat_id: self.chat_id.clone().unwrap(),
+                        content,
+                        reply_to_id: self.replying_to.take(),
+                        voice_note_id: None,
+                        scheduled_for: Some(scheduled_for),
+                        key_id: None,
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                move |id| result(id.map(|_| ())),
+            ),
+        }
+    }
+
+    /// The lines above the one being edited, and that line
+    fn draft_lines(&self) -> (Option<&str>, &str) {
+        match self.message_input.rsplit_once('\n') {
+            Some((previous, current)) => (Some(previous), current),
+            None => (None, &self.message_input),
+        }
+    }
+
+    fn can_send(&self) -> bool {
+        let length = self.message_input.chars().count();
+        !self.message_input.trim().is_empty() && length <= self.limits.max_message_length
+    }
+
     pub fn clear(&mut self) {
         self.messages.clear();
     }
@@ -75,6 +543,28 @@ impl LetterList {
             .insert(chat_message.message_id.clone(), Letter(chat_message));
     }
 
+    /// Downloads the preview images of the messages that aren't loaded yet
+    pub fn load_preview_images(&self) -> Command<LetterListMessage> {
+        let names: Vec<String> = self
+            .messages
+            .values()
+            .filter_map(|letter| letter.0.link_preview.as_ref()?.image.clone())
+            .filter(|name| !self.preview_images.contains_key(name))
+            .collect();
+        Command::batch(names.into_iter().map(|name| {
+            let client = self.client.clone();
+            let url = format!("http://{}/content/{name}", server::server_url());
+            Command::perform(
+                async move {
+                    let response = client.get(url).send().await.ok()?;
+                    let bytes = response.bytes().await.ok()?;
+                    Some(Vec::from(bytes))
+                },
+                move |bytes| LetterListMessage::PreviewImageLoaded(name, bytes),
+            )
+        }))
+    }
+
     pub fn update(&mut self, message: LetterListMessage) -> Command<LetterListMessage> {
         match message {
             LetterListMessage::LetterMessage(msg, id) => {
@@ -92,14 +582,125 @@ impl LetterList {
                         ),
                         move |_| LetterListMessage::MessageDeleted(id),
                     ),
+                    LetterMessage::VoicePlayToggled => {
+                        let was_playing = self
+                            .playing
+                            .as_ref()
+                            .is_some_and(|(playing, _)| playing == &id);
+                        if let Some((_, stop)) = self.playing.take() {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                        let voice_note =
+                            self.messages
+                                .get(&id)
+                                .and_then(|letter| match &letter.0.kind {
+                                    MessageKind::Voice(voice_note) => Some(voice_note.clone()),
+                                    MessageKind::Text => None,
+                                });
+                        let Some(voice_note) = voice_note.filter(|_| !was_playing) else {
+                            return Command::none();
+                        };
+                        let stop = Arc::new(AtomicBool::new(false));
+                        self.playing = Some((id.clone(), stop.clone()));
+                        let client = self.client.clone();
+                        Command::perform(
+                            async move {
+                                let ogg = server::get_content(client, voice_note.file_name())
+                                    .await
+                                    .map_err(|err| err.to_string())?;
+                                voice::play(ogg, stop).await
+                            },
+                            move |result| LetterListMessage::VoicePlaybackFinished(id, result),
+                        )
+                    }
+                    LetterMessage::LinkClicked(url) => {
+                        let _ = open::that_detached(url);
+                        Command::none()
+                    }
+                    LetterMessage::SelectToggled => {
+                        if let Some(index) =
+                            self.selected.iter().position(|selected| selected == &id)
+                        {
+                            self.selected.remove(index);
+                        } else {
+                            self.selected.push(id);
+                        }
+                        Command::none()
+                    }
+                    LetterMessage::ReactionPickerToggled => {
+                        self.reacting_to = if self.reacting_to.as_ref() == Some(&id) {
+                            None
+                        } else {
+                            Some(id)
+                        };
+                        Command::none()
+                    }
+                    LetterMessage::ReactionToggled(emoji) => {
+                        self.reacting_to = None;
+                        let reacted = self.messages.get(&id).is_some_and(|letter| {
+                            letter.0.reactions.iter().any(|reaction| {
+                                reaction.emoji == emoji
+                                    && reaction.user_ids.contains(&self.session.user_id)
+                            })
+                        });
+                        // The change is applied once the server broadcasts it
+                        Command::perform(
+                            server_post::<()>(
+                                self.client.clone(),
+                                if reacted { "unreact" } else { "react" },
+                                React {
+                                    message_id: id,
+                                    emoji,
+                                },
+                                Some(self.session.session_id.clone()),
+                            ),
+                            |result| match result {
+                                Ok(_) => LetterListMessage::ReactionSaved,
+                                Err(err) => LetterListMessage::Error(err.to_string()),
+                            },
+                        )
+                    }
                 }
                 //self.messages.get_mut(&id).unwrap().update(msg);
             }
-            LetterListMessage::MessageInputChanged(value) => {
-                self.message_input = truncate_message(value, 300);
+            LetterListMessage::MessageInputChanged(value) => match self.draft_lines() {
+                // Backspace in an empty line joins it with the previous one
+                (Some(previous), "") if value.is_empty() => {
+                    self.message_input = previous.to_owned();
+                    text_input::move_cursor_to_end(self.input_id.clone())
+                }
+                (Some(previous), _) => {
+                    self.message_input = format!("{previous}\n{value}");
+                    Command::none()
+                }
+                (None, _) => {
+                    self.message_input = value;
+                    Command::none()
+                }
+            },
+            LetterListMessage::ShiftChanged(held) => {
+                self.shift_held = held;
                 Command::none()
             }
+            LetterListMessage::LimitsLoaded(limits) => {
+                self.limits = limits;
+                Command::none()
+            }
+            LetterListMessage::InputSubmitted => {
+                if self.shift_held {
+                    self.message_input.push('\n');
+                    Command::none()
+                } else {
+                    self.update(LetterListMessage::SendPressed)
+                }
+            }
             LetterListMessage::SendPressed => {
+                if !self.can_send() {
+                    return Command::none();
+                }
+                if let Some(input) = self.schedule_input.clone() {
+                    return self.schedule_draft(&input);
+                }
                 let message = self.message_input.clone();
                 self.message_input = String::new();
                 let client = self.client.clone();
@@ -107,21 +708,34 @@ impl LetterList {
                 let sender = self.session.user_id.clone();
                 self.replying_to = None;
                 Command::perform(
-                    server_post::<String>(
+                    send_text(
                         client,
-                        "create_message",
+                        self.session.session_id.clone(),
+                        sender.clone(),
                         CreateMessage {
                             chat_id: self.chat_id.clone().unwrap(),
                             content: message.clone(),
                             reply_to_id: reply_to_id.clone(),
+                            voice_note_id: None,
+                            scheduled_for: None,
+                            key_id: None,
                         },
-                        Some(self.session.session_id.clone()),
+                        self.chat_key.clone(),
+                        self.peer_key(),
                     ),
-                    move |msg| LetterListMessage::MessageSent {
-                        id: msg.unwrap(),
-                        message,
-                        sender,
-                        reply_to: reply_to_id,
+                    move |msg| match msg {
+                        Ok((id, key_id)) => LetterListMessage::MessageSent {
+                            id,
+                            message,
+                            sender,
+                            reply_to: reply_to_id,
+                            forwarded_from: None,
+                            link_preview: None,
+                            kind: MessageKind::Text,
+                            expires_at: None,
+                            key_id,
+                        },
+                        Err(err) => LetterListMessage::Error(err),
                     },
                 )
             }
@@ -130,7 +744,18 @@ impl LetterList {
                 message,
                 sender,
                 reply_to,
+                forwarded_from,
+                link_preview,
+                kind,
+                expires_at,
+                key_id,
             } => {
+                // The socket may have delivered the message before the request returned
+                let existing = self.messages.get(&id).map(|letter| &letter.0);
+                let link_preview = link_preview
+                    .or_else(|| existing.and_then(|message| message.link_preview.clone()));
+                let expires_at =
+                    expires_at.or_else(|| existing.and_then(|message| message.expires_at));
                 self.add_message(WsChatMessage {
                     message_id: id,
                     message,
@@ -138,8 +763,243 @@ impl LetterList {
                     chat_id: self.chat_id.as_ref().unwrap().clone(),
                     reply_to,
                     created_at: Utc::now(),
+                    reactions: Vec::new(),
+                    forwarded_from,
+                    link_preview,
+                    kind,
+                    expires_at,
+                    key_id,
                 });
-                scrollable::snap_to(self.scrollable_id.clone(), RelativeOffset::END)
+                Command::batch(vec![
+                    scrollable::snap_to(self.scrollable_id.clone(), RelativeOffset::END),
+                    self.load_preview_images(),
+                ])
+            }
+            LetterListMessage::PeerIdentityLoaded(identity) => {
+                if self
+                    .peer_identity
+                    .as_ref()
+                    .is_some_and(|peer| peer.user_id == identity.user_id)
+                {
+                    self.peer_identity = Some(identity);
+                }
+                Command::none()
+            }
+            LetterListMessage::EnableEncryption => Command::perform(
+                current_chat_key(
+                    self.client.clone(),
+                    self.session.session_id.clone(),
+                    self.session.user_id.clone(),
+                    self.chat_id.clone().unwrap(),
+                    None,
+                    self.peer_key(),
+                ),
+                |result| match result {
+                    Ok(_) => LetterListMessage::EncryptionEnabled,
+                    Err(err) => LetterListMessage::Error(err),
+                },
+            ),
+            LetterListMessage::SafetyNumberToggled => {
+                self.show_safety_number = !self.show_safety_number;
+                Command::none()
+            }
+            LetterListMessage::MarkVerified => {
+                if let Some(IdentityKey {
+                    user_id,
+                    identity_key: Some(identity_key),
+                }) = &self.peer_identity
+                {
+                    e2ee::mark_verified(user_id, identity_key);
+                }
+                Command::none()
+            }
+            LetterListMessage::RecordPressed => match Recorder::start() {
+                Ok(recorder) => {
+                    self.recorder = Some(recorder);
+                    Command::none()
+                }
+                Err(err) => Command::perform(async { err }, LetterListMessage::Error),
+            },
+            LetterListMessage::RecordingTick => {
+                let max_duration =
+                    std::time::Duration::from_secs(self.limits.max_voice_duration_secs.into());
+                if self
+                    .recorder
+                    .as_ref()
+                    .is_some_and(|recorder| recorder.elapsed() >= max_duration)
+                {
+                    self.update(LetterListMessage::RecordingStopped)
+                } else {
+                    Command::none()
+                }
+            }
+            LetterListMessage::RecordingStopped => {
+                let Some(recorder) = self.recorder.take() else {
+                    return Command::none();
+                };
+                let samples = match recorder.finish() {
+                    Ok(samples) => samples,
+                    Err(err) => return Command::perform(async { err }, LetterListMessage::Error),
+                };
+                let reply_to_id = self.replying_to.take();
+                let sender = self.session.user_id.clone();
+                Command::perform(
+                    send_voice(
+                        self.client.clone(),
+                        self.session.session_id.clone(),
+                        self.chat_id.clone().unwrap(),
+                        reply_to_id.clone(),
+                        samples,
+                    ),
+                    move |result| match result {
+                        Ok((id, voice_note)) => LetterListMessage::MessageSent {
+                            id,
+                            message: String::new(),
+                            sender,
+                            reply_to: reply_to_id,
+                            forwarded_from: None,
+                            link_preview: None,
+                            kind: MessageKind::Voice(Box::new(voice_note)),
+                            expires_at: None,
+                            key_id: None,
+                        },
+                        Err(err) => LetterListMessage::Error(err),
+                    },
+                )
+            }
+            LetterListMessage::RecordingCancelled => {
+                if let Some(recorder) = self.recorder.take() {
+                    recorder.cancel();
+                }
+                Command::none()
+            }
+            LetterListMessage::VoicePlaybackFinished(id, result) => {
+                if self
+                    .playing
+                    .as_ref()
+                    .is_some_and(|(playing, _)| playing == &id)
+                {
+                    self.playing = None;
+                }
+                match result {
+                    Ok(()) => Command::none(),
+                    Err(err) => Command::perform(async { err }, LetterListMessage::Error),
+                }
+            }
+            LetterListMessage::ScheduleToggled => {
+                self.schedule_input = match self.schedule_input {
+                    Some(_) => None,
+                    None => Some(format_schedule_time(Utc::now() + Duration::hours(1))),
+                };
+                // Closing the options turns the edited message back into a normal draft
+                if self.schedule_input.is_none() && self.editing_scheduled.take().is_some() {
+                    self.message_input.clear();
+                }
+                Command::none()
+            }
+            LetterListMessage::ScheduleInputChanged(value) => {
+                self.schedule_input = Some(value);
+                Command::none()
+            }
+            LetterListMessage::ScheduledLoaded(scheduled) => {
+                self.scheduled = scheduled;
+                if self.scheduled.is_empty() {
+                    self.show_scheduled = false;
+                }
+                Command::none()
+            }
+            LetterListMessage::ScheduledListToggled => {
+                self.show_scheduled = !self.show_scheduled;
+                Command::none()
+            }
+            LetterListMessage::ScheduledEdit(id) => {
+                if let Some(scheduled) = self.scheduled.iter().find(|scheduled| scheduled.id == id)
+                {
+                    self.message_input = scheduled.content.clone();
+                    self.schedule_input = Some(format_schedule_time(scheduled.scheduled_for));
+                    self.editing_scheduled = Some(id);
+                    self.replying_to = None;
+                }
+                text_input::move_cursor_to_end(self.input_id.clone())
+            }
+            LetterListMessage::ScheduledCancel(id) => {
+                if self.editing_scheduled.as_ref() == Some(&id) {
+                    self.editing_scheduled = None;
+                    self.schedule_input = None;
+                    self.message_input.clear();
+                }
+                Command::perform(
+                    server_post::<()>(
+                        self.client.clone(),
+                        "cancel_scheduled",
+                        CancelScheduledMessage { id },
+                        Some(self.session.session_id.clone()),
+                    ),
+                    |result| match result {
+                        Ok(_) => LetterListMessage::ScheduledChanged,
+                        Err(err) => LetterListMessage::Error(err.to_string()),
+                    },
+                )
+            }
+            LetterListMessage::ScheduledChanged => self.load_scheduled(),
+            LetterListMessage::TtlChanged(option) => Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "set_chat_ttl",
+                    SetChatTtl {
+                        chat_id: self.chat_id.clone().unwrap(),
+                        message_ttl: option.message_ttl(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |result| match result {
+                    Ok(_) => LetterListMessage::TtlSaved,
+                    Err(err) => LetterListMessage::Error(err.to_string()),
+                },
+            ),
+            LetterListMessage::ExpiryTick => {
+                // The server deletes them too, this covers a socket that missed it
+                let expired: Vec<String> = self
+                    .messages
+                    .values()
+                    .filter(|letter| {
+                        letter
+                            .0
+                            .expires_at
+                            .is_some_and(|expires_at| expires_at <= Utc::now())
+                    })
+                    .map(|letter| letter.0.message_id.clone())
+                    .collect();
+                for id in expired {
+                    let _ = self.update(LetterListMessage::MessageDeleted(id));
+                }
+                Command::none()
+            }
+            LetterListMessage::PreviewImageLoaded(name, bytes) => {
+                if let Some(bytes) = bytes {
+                    self.preview_images
+                        .insert(name, image::Handle::from_memory(bytes));
+                }
+                Command::none()
+            }
+            LetterListMessage::ForwardTo(target) => Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "forward_messages",
+                    ForwardMessages {
+                        message_ids: self.selected.clone(),
+                        chat_id: target.chat_id,
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |result| match result {
+                    Ok(_) => LetterListMessage::Forwarded,
+                    Err(err) => LetterListMessage::Error(err.to_string()),
+                },
+            ),
+            LetterListMessage::Forwarded | LetterListMessage::CancelSelection => {
+                self.selected.clear();
+                Command::none()
             }
             LetterListMessage::CancelReply => {
                 self.replying_to = None;
@@ -151,6 +1011,7 @@ impl LetterList {
                         self.replying_to = None;
                     }
                 }
+                self.selected.retain(|selected| selected != &id);
                 self.messages.remove(&id);
                 Command::none()
             }
@@ -158,11 +1019,21 @@ impl LetterList {
                 WsMessageData::ChatMessage(msg) => {
                     if let Some(chat) = &self.chat_id {
                         if chat == &msg.chat_id {
+                            // Own scheduled messages arrive like any other once they are due
+                            if msg.sender_id == self.session.user_id {
+                                self.scheduled
+                                    .retain(|scheduled| scheduled.scheduled_for > Utc::now());
+                            }
                             return self.update(LetterListMessage::MessageSent {
                                 id: msg.message_id,
                                 message: msg.message,
                                 sender: msg.sender_id,
                                 reply_to: msg.reply_to,
+                                forwarded_from: msg.forwarded_from,
+                                link_preview: msg.link_preview,
+                                kind: msg.kind,
+                                expires_at: msg.expires_at,
+                                key_id: msg.key_id,
                             });
                         }
                     }
@@ -171,6 +1042,38 @@ impl LetterList {
                 WsMessageData::DeleteMessage(msg) => {
                     self.update(LetterListMessage::MessageDeleted(msg.message_id))
                 }
+                WsMessageData::ChatKey(chat_key) => {
+                    if self.chat_id.as_ref() != Some(&chat_key.chat_id) {
+                        return Command::none();
+                    }
+                    // A new key usually means one of the members has a new identity key
+                    let peer_id = self.peer_identity.as_ref().map(|peer| peer.user_id.clone());
+                    let show_safety_number = self.show_safety_number;
+                    let command = self.load_encryption(Some(chat_key), peer_id);
+                    self.show_safety_number = show_safety_number;
+                    command
+                }
+                WsMessageData::ReactionAdded(reaction) => {
+                    if let Some(letter) = self.messages.get_mut(&reaction.message_id) {
+                        letter.0.add_reaction(reaction.user_id, reaction.emoji);
+                    }
+                    Command::none()
+                }
+                WsMessageData::ReactionRemoved(reaction) => {
+                    if let Some(letter) = self.messages.get_mut(&reaction.message_id) {
+                        letter.0.remove_reaction(&reaction.user_id, &reaction.emoji);
+                    }
+                    Command::none()
+                }
+                WsMessageData::LinkPreview(preview) => {
+                    match self.messages.get_mut(&preview.message_id) {
+                        Some(letter) => {
+                            letter.0.link_preview = Some(preview.preview);
+                            self.load_preview_images()
+                        }
+                        None => Command::none(),
+                    }
+                }
                 _ => Command::none(),
             },
             LetterListMessage::ChatDelete => {
@@ -191,30 +1094,341 @@ impl LetterList {
                     },
                 )
             }
+            LetterListMessage::BlockUser(user_id) => Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "block",
+                    BlockRequest { user_id },
+                    Some(self.session.session_id.clone()),
+                ),
+                |result| match result {
+                    Ok(_) => LetterListMessage::UserBlocked,
+                    Err(err) => LetterListMessage::Error(err.to_string()),
+                },
+            ),
+            LetterListMessage::ExportChat => {
+                let path = FileDialog::new()
+                    .set_location("~/Downloads")
+                    .set_filename("chat.html")
+                    .add_filter("HTML", &["html"])
+                    .add_filter("JSON", &["json"])
+                    .show_save_single_file()
+                    .unwrap();
+                let Some(path) = path else {
+                    return Command::none();
+                };
+                Command::perform(
+                    export_chat(
+                        self.client.clone(),
+                        self.session.session_id.clone(),
+                        self.chat_id.clone().unwrap(),
+                        path,
+                    ),
+                    |result| match result {
+                        Ok(()) => LetterListMessage::ChatExported,
+                        Err(err) => LetterListMessage::Error(err),
+                    },
+                )
+            }
             _ => Command::none(),
         }
     }
 
     pub fn subscription(&self) -> iced::Subscription<LetterListMessage> {
-        ws_client::connect(self.session.session_id.clone())
-            .map(|event| LetterListMessage::WsEvent(event))
+        // Keeps the recording timer running and stops at the length limit
+        let recording_timer = if self.recorder.is_some() {
+            iced::time::every(std::time::Duration::from_millis(200))
+                .map(|_| LetterListMessage::RecordingTick)
+        } else {
+            iced::Subscription::none()
+        };
+        let expiry_timer = if self
+            .messages
+            .values()
+            .any(|letter| letter.0.expires_at.is_some())
+        {
+            iced::time::every(std::time::Duration::from_secs(30))
+                .map(|_| LetterListMessage::ExpiryTick)
+        } else {
+            iced::Subscription::none()
+        };
+        iced::Subscription::batch(vec![
+            recording_timer,
+            expiry_timer,
+            ws_client::connect(self.session.session_id.clone())
+                .map(|event| LetterListMessage::WsEvent(event)),
+            iced::subscription::events_with(|event, _| match event {
+                Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                    Some(LetterListMessage::ShiftChanged(modifiers.shift()))
+                }
+                _ => None,
+            }),
+        ])
     }
 
     pub fn view(
         &self,
-        members: Vec<String>,
+        members: Vec<ChatMember>,
         current_user_id: String,
+        preferences: ChatPreferences,
+        status: Option<UserStatus>,
+        message_ttl: Option<u32>,
+        forward_targets: Vec<ForwardTarget>,
     ) -> Element<LetterListMessage> {
-        let other_member = Chat::get_other_member(current_user_id.clone(), &members);
-        let nickname_text = text(other_member).size(25);
+        let other_member = Chat::get_other_member(&current_user_id, &members);
+        let mut title = column![text(
+            other_member
+                .map(ChatMember::name)
+                .unwrap_or("Удалённый аккаунт")
+        )
+        .size(25)]
+        .width(Length::Fill)
+        .align_items(alignment::Alignment::Center);
+        if let Some(member) = other_member {
+            let handle = match status
+                .as_ref()
+                .and_then(|status| status.status_text.as_ref())
+            {
+                Some(status_text) => format!("@{} · {status_text}", member.username),
+                None => format!("@{}", member.username),
+            };
+            title = title.push(container(text(handle).size(12)).style(style_muted));
+        }
+        if let Some(bio) = status.and_then(|status| status.bio) {
+            title = title.push(text(bio).size(12));
+        }
+        if let Some(ttl) = message_ttl {
+            title = title.push(
+                container(text(format!("⏱ Сообщения исчезают через {}", ttl_label(ttl))).size(12))
+                    .style(style_muted),
+            );
+        }
+        if self.chat_key.is_some() {
+            title =
+                title.push(container(text("🔒 Сквозное шифрование").size(12)).style(style_muted));
+        }
+
+        let encryption_row = match (&self.chat_key, other_member) {
+            (Some(_), _) => row![button(if self.show_safety_number {
+                "Скрыть код"
+            } else {
+                "Код безопасности"
+            })
+            .padding([2, 8])
+            .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+            .on_press(LetterListMessage::SafetyNumberToggled)],
+            (None, Some(_)) => row![button("Включить шифрование")
+                .padding([2, 8])
+                .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+                .on_press(LetterListMessage::EnableEncryption)],
+            (None, None) => row![],
+        };
+
+        let mut safety_column = column![].spacing(5);
+        if let (Some(_), Some(own_key), Some(peer_id), Some(peer_key)) = (
+            &self.chat_key,
+            e2ee::identity_key(),
+            self.peer_identity.as_ref().map(|peer| &peer.user_id),
+            self.peer_key(),
+        ) {
+            let verification = e2ee::verification(peer_id, &peer_key);
+            if verification == Verification::Changed {
+                safety_column = safety_column.push(
+                    container(text(
+                        "⚠ Ключ собеседника изменился после проверки, сверьте код снова",
+                    ))
+                    .style(style_error),
+                );
+            }
+            if self.show_safety_number {
+                safety_column = safety_column
+                    .push(
+                        container(text("Сравните код с собеседником лично или по звонку").size(12))
+                            .style(style_muted),
+                    )
+                    .push(
+                        text(e2ee::safety_number(
+                            &current_user_id,
+                            &own_key,
+                            peer_id,
+                            &peer_key,
+                        ))
+                        .font(iced::Font::MONOSPACE),
+                    )
+                    .push(if verification == Verification::Verified {
+                        row![container(text("✓ Собеседник проверен")).style(style_muted)]
+                    } else {
+                        row![button("Отметить как проверенный")
+                            .padding([2, 8])
+                            .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                            .on_press(LetterListMessage::MarkVerified)]
+                    });
+            }
+        }
+
+        let block_button = match other_member {
+            Some(member) => {
+                icon_button('').on_press(LetterListMessage::BlockUser(member.id.clone()))
+            }
+            None => icon_button(''),
+        };
+
+        let selection_row = if self.selected.is_empty() {
+            row![]
+        } else {
+            row![
+                text(format!("Выбрано: {}", self.selected.len())),
+                Space::with_width(Length::Fill),
+                pick_list(forward_targets, None, LetterListMessage::ForwardTo)
+                    .placeholder("Переслать в…")
+                    .padding(8),
+                button("×")
+                    .padding([0, 10])
+                    .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                    .on_press(LetterListMessage::CancelSelection)
+            ]
+            .spacing(5)
+            .align_items(alignment::Alignment::Center)
+        };
+
+        let (previous_lines, current_line) = self.draft_lines();
+        let length = self.message_input.chars().count();
+        let max_length = self.limits.max_message_length;
+        let mut composer = column![].spacing(4).width(Length::Fill);
+        if let Some(schedule_input) = &self.schedule_input {
+            let mut schedule_row = row![
+                text(if self.editing_scheduled.is_some() {
+                    "Изменить время отправки:"
+                } else {
+                    "Отправить в"
+                }),
+                text_input("ДД.ММ.ГГГГ ЧЧ:ММ", schedule_input)
+                    .padding(6)
+                    .width(160)
+                    .on_input(LetterListMessage::ScheduleInputChanged)
+                    .on_submit(LetterListMessage::SendPressed)
+            ]
+            .spacing(8)
+            .align_items(alignment::Alignment::Center);
+            if parse_schedule_time(schedule_input).is_none() {
+                schedule_row = schedule_row
+                    .push(container(text("Неверное время").size(11)).style(style_error));
+            }
+            composer = composer.push(schedule_row);
+        }
+        if let Some(previous_lines) = previous_lines {
+            composer = composer.push(container(text(previous_lines)).padding([0, 9]));
+        }
+        let composer = composer
+            .push(
+                text_input("Сообщение", current_line)
+                    .id(self.input_id.clone())
+                    .padding(8)
+                    .on_input(|value| LetterListMessage::MessageInputChanged(value))
+                    .on_submit(LetterListMessage::InputSubmitted),
+            )
+            .push(
+                container(text(format!("{length} / {max_length}")).size(11))
+                    .style(if length > max_length {
+                        style_error
+                    } else {
+                        style_muted
+                    })
+                    .padding([0, 9]),
+            );
+        let send_button = icon_button('').padding([8, 14]);
+
+        let input_row = match &self.recorder {
+            Some(recorder) => row![
+                text(format!(
+                    "● Запись {} / {}",
+                    voice::format_duration(recorder.elapsed()),
+                    voice::format_duration(std::time::Duration::from_secs(
+                        self.limits.max_voice_duration_secs.into()
+                    ))
+                )),
+                Space::with_width(Length::Fill),
+                button("×")
+                    .padding([0, 10])
+                    .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                    .on_press(LetterListMessage::RecordingCancelled),
+                send_button.on_press(LetterListMessage::RecordingStopped)
+            ]
+            .spacing(8)
+            .align_items(alignment::Alignment::Center),
+            None => {
+                let mut input_row = row![composer]
+                    .spacing(8)
+                    .align_items(alignment::Alignment::End);
+                // Voice notes and scheduled messages can't be encrypted
+                if self.chat_key.is_none() {
+                    input_row = input_row
+                        .push(
+                            icon_button('')
+                                .padding([8, 14])
+                                .on_press(LetterListMessage::ScheduleToggled),
+                        )
+                        .push(
+                            icon_button('')
+                                .padding([8, 14])
+                                .on_press(LetterListMessage::RecordPressed),
+                        );
+                }
+                input_row.push(if self.can_send() {
+                    send_button.on_press(LetterListMessage::SendPressed)
+                } else {
+                    send_button
+                })
+            }
+        };
+
+        let scheduled_column = if self.scheduled.is_empty() {
+            column![]
+        } else {
+            let mut scheduled_column = column![button(text(format!(
+                "{} Запланировано: {}",
+                if self.show_scheduled { '▾' } else { '▸' },
+                self.scheduled.len()
+            )))
+            .padding([2, 8])
+            .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+            .on_press(LetterListMessage::ScheduledListToggled)]
+            .spacing(5);
+            if self.show_scheduled {
+                for scheduled in &self.scheduled {
+                    scheduled_column = scheduled_column.push(
+                        row![
+                            container(text(format_schedule_time(scheduled.scheduled_for)))
+                                .style(style_muted),
+                            text(truncate_message(
+                                to_plain_text(&scheduled.content).replace('\n', " "),
+                                60
+                            )),
+                            Space::with_width(Length::Fill),
+                            button("Изменить")
+                                .padding([2, 8])
+                                .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                                .on_press(LetterListMessage::ScheduledEdit(scheduled.id.clone())),
+                            button("Отменить")
+                                .padding([2, 8])
+                                .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                                .on_press(LetterListMessage::ScheduledCancel(scheduled.id.clone()))
+                        ]
+                        .spacing(8)
+                        .align_items(alignment::Alignment::Center),
+                    );
+                }
+            }
+            scheduled_column
+        };
 
         let message_send_column = if let Some(replying_to) = self.replying_to.clone() {
             let message = self.messages.get(&replying_to).unwrap().0.clone();
-            let content = message.message;
+            let content = message_text(&message);
             column![row![
                 text(&format!(
                     "↱ {}: {}",
-                    message.sender_id,
+                    member_name(&members, &message.sender_id),
                     truncate_message(content, 80)
                 )),
                 Space::with_width(Length::Fill),
@@ -230,12 +1444,52 @@ impl LetterList {
 
         column![
             row![
-                nickname_text
-                    .width(Length::Fill)
-                    .vertical_alignment(alignment::Vertical::Center)
-                    .horizontal_alignment(alignment::Horizontal::Center),
-                icon_button('').on_press(LetterListMessage::ChatDelete)
-            ],
+                title,
+                encryption_row,
+                button("Экспорт")
+                    .padding([2, 8])
+                    .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+                    .on_press(LetterListMessage::ExportChat),
+                pick_list(MuteOption::ALL.to_vec(), None, {
+                    let preferences = preferences.clone();
+                    move |option: MuteOption| {
+                        LetterListMessage::PreferencesChanged(ChatPreferences {
+                            muted_until: option.muted_until(),
+                            ..preferences.clone()
+                        })
+                    }
+                })
+                .placeholder(if preferences.is_muted() {
+                    "Без звука"
+                } else {
+                    "Со звуком"
+                })
+                .padding(8),
+                pick_list(
+                    TtlOption::ALL.to_vec(),
+                    TtlOption::ALL
+                        .into_iter()
+                        .find(|option| option.message_ttl() == message_ttl),
+                    LetterListMessage::TtlChanged
+                )
+                .placeholder("Исчезающие сообщения")
+                .padding(8),
+                icon_button(if preferences.pinned { '' } else { '' }).on_press(
+                    LetterListMessage::PreferencesChanged(ChatPreferences {
+                        pinned: !preferences.pinned,
+                        ..preferences.clone()
+                    })
+                ),
+                icon_button('').on_press(LetterListMessage::PreferencesChanged(ChatPreferences {
+                    archived: !preferences.archived,
+                    ..preferences.clone()
+                })),
+                icon_button('').on_press(LetterListMessage::ChatDelete),
+                block_button
+            ]
+            .spacing(5)
+            .align_items(alignment::Alignment::Center),
+            safety_column,
             scrollable(
                 column(
                     self.messages
@@ -243,7 +1497,7 @@ impl LetterList {
                         .map(|message| {
                             message
                                 .1
-                                .view(self, current_user_id.clone())
+                                .view(self, current_user_id.clone(), &members)
                                 .map(|msg| LetterListMessage::LetterMessage(msg, message.0.clone()))
                         })
                         .collect()
@@ -256,18 +1510,9 @@ impl LetterList {
             .width(Length::Fill)
             .height(Length::Fill)
             .style(Scrollable::Custom(Box::new(ScrollableStyle))),
-            message_send_column.spacing(8).push(
-                row![
-                    text_input("Сообщение", &self.message_input)
-                        .padding(8)
-                        .on_input(|value| LetterListMessage::MessageInputChanged(value))
-                        .on_submit(LetterListMessage::SendPressed),
-                    icon_button('')
-                        .padding([8, 14])
-                        .on_press(LetterListMessage::SendPressed)
-                ]
-                .spacing(8)
-            )
+            selection_row,
+            scheduled_column,
+            message_send_column.spacing(8).push(input_row)
         ]
         .width(Length::Fill)
         .height(Length::Fill)
@@ -5,7 +5,7 @@ use iced::{
     widget::{button, column, container, row, text, text_input},
     Length,
 };
-use structs::requests::{LoginInfo, Session};
+use structs::requests::{LoginInfo, LoginResult, Session, TwoFactorLogin};
 
 use super::ButtonStyle;
 
@@ -14,6 +14,9 @@ pub struct LoginScreen {
     pub logging_in: bool,
     username_input: String,
     password_input: String,
+    /// Set while the password was accepted and the account needs a code too
+    login_token: Option<String>,
+    code_input: String,
     client: reqwest::Client,
 }
 
@@ -24,6 +27,11 @@ pub enum LoginScreenMessage {
     Login,
     Register,
     LoggedIn(Session),
+    TwoFactorRequired(String),
+    CodeInputChanged(String),
+    SubmitCode,
+    /// Back to the username and password
+    CancelCode,
     FocusChange,
     Error(String),
 }
@@ -44,28 +52,82 @@ impl LoginScreen {
                 iced::Command::none()
             }
             LoginScreenMessage::FocusChange => iced::widget::focus_next(),
-            LoginScreenMessage::Register | LoginScreenMessage::Login => {
+            LoginScreenMessage::Login => {
                 self.logging_in = true;
                 iced::Command::perform(
-                    server_post::<Session>(
+                    server_post::<LoginResult>(
                         self.client.clone(),
-                        if message == LoginScreenMessage::Login {
-                            "login"
-                        } else {
-                            "register"
+                        "login",
+                        LoginInfo {
+                            username: self.username_input.clone(),
+                            password: self.password_input.clone(),
                         },
+                        None,
+                    ),
+                    |login_result| match login_result {
+                        Ok(LoginResult::LoggedIn(session)) => LoginScreenMessage::LoggedIn(session),
+                        Ok(LoginResult::TwoFactorRequired { login_token }) => {
+                            LoginScreenMessage::TwoFactorRequired(login_token)
+                        }
+                        Err(err) => LoginScreenMessage::Error(err.to_string()),
+                    },
+                )
+            }
+            LoginScreenMessage::Register => {
+                self.logging_in = true;
+                iced::Command::perform(
+                    server_post::<Session>(
+                        self.client.clone(),
+                        "register",
                         LoginInfo {
                             username: self.username_input.clone(),
                             password: self.password_input.clone(),
                         },
                         None,
                     ),
-                    move |register_result| match register_result {
+                    |register_result| match register_result {
                         Ok(session) => LoginScreenMessage::LoggedIn(session),
                         Err(err) => LoginScreenMessage::Error(err.to_string()),
                     },
                 )
             }
+            LoginScreenMessage::TwoFactorRequired(login_token) => {
+                self.logging_in = false;
+                self.login_token = Some(login_token);
+                self.code_input.clear();
+                iced::Command::none()
+            }
+            LoginScreenMessage::CodeInputChanged(value) => {
+                self.code_input = value;
+                iced::Command::none()
+            }
+            LoginScreenMessage::SubmitCode => {
+                let Some(login_token) = self.login_token.clone() else {
+                    return iced::Command::none();
+                };
+                self.logging_in = true;
+                iced::Command::perform(
+                    server_post::<Session>(
+                        self.client.clone(),
+                        "login/totp",
+                        TwoFactorLogin {
+                            login_token,
+                            code: self.code_input.clone(),
+                        },
+                        None,
+                    ),
+                    |login_result| match login_result {
+                        Ok(session) => LoginScreenMessage::LoggedIn(session),
+                        Err(err) => LoginScreenMessage::Error(err.to_string()),
+                    },
+                )
+            }
+            LoginScreenMessage::CancelCode => {
+                self.login_token = None;
+                self.code_input.clear();
+                self.password_input.clear();
+                iced::Command::none()
+            }
             LoginScreenMessage::LoggedIn(_) => unreachable!(),
             LoginScreenMessage::Error(_) => unreachable!(),
         }
@@ -76,6 +138,25 @@ impl LoginScreen {
     }
 
     pub fn view(&self) -> iced::Element<LoginScreenMessage> {
+        let content = if self.login_token.is_some() {
+            self.code_view()
+        } else {
+            self.password_view()
+        };
+        container(
+            content
+                .spacing(10)
+                .width(Length::Fixed(400.))
+                .align_items(iced::Alignment::Center),
+        )
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x()
+        .center_y()
+        .into()
+    }
+
+    fn password_view(&self) -> iced::widget::Column<LoginScreenMessage> {
         let sign_up_text = text("Вход").size(36);
         let username_text_input = text_input("Имя пользователя", &self.username_input)
             .on_input(LoginScreenMessage::UsernameInputChanged)
@@ -87,13 +168,6 @@ impl LoginScreen {
             .password()
             .padding(10);
 
-        let center_button = |content| {
-            button(text(content).horizontal_alignment(alignment::Horizontal::Center))
-                .width(Length::Fill)
-                .style(Button::Custom(Box::new(ButtonStyle::Blue)))
-                .padding(10)
-        };
-
         let button_row = if self.logging_in {
             row![center_button("Войти"), center_button("Зарегистрироваться"),]
         } else {
@@ -103,21 +177,40 @@ impl LoginScreen {
             ]
         }
         .spacing(10);
-        container(
-            column![
-                sign_up_text,
-                username_text_input,
-                password_text_input,
-                button_row
+        column![
+            sign_up_text,
+            username_text_input,
+            password_text_input,
+            button_row
+        ]
+    }
+
+    fn code_view(&self) -> iced::widget::Column<LoginScreenMessage> {
+        let mut code_text_input = text_input("Код", &self.code_input)
+            .on_input(LoginScreenMessage::CodeInputChanged)
+            .padding(10);
+        let mut confirm_button = center_button("Подтвердить");
+        if !self.logging_in {
+            code_text_input = code_text_input.on_submit(LoginScreenMessage::SubmitCode);
+            confirm_button = confirm_button.on_press(LoginScreenMessage::SubmitCode);
+        }
+
+        column![
+            text("Подтверждение входа").size(36),
+            text("Введите код из приложения-аутентификатора или один из кодов восстановления"),
+            code_text_input,
+            row![
+                confirm_button,
+                center_button("Назад").on_press(LoginScreenMessage::CancelCode),
             ]
             .spacing(10)
-            .width(Length::Fixed(400.))
-            .align_items(iced::Alignment::Center),
-        )
-        .width(Length::Fill)
-        .height(Length::Fill)
-        .center_x()
-        .center_y()
-        .into()
+        ]
     }
 }
+
+fn center_button<'a>(content: &str) -> iced::widget::Button<'a, LoginScreenMessage> {
+    button(text(content).horizontal_alignment(alignment::Horizontal::Center))
+        .width(Length::Fill)
+        .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+        .padding(10)
+}
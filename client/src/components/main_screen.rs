@@ -1,14 +1,15 @@
 use iced::widget::column;
 use iced::Length;
 use iced_aw::modal;
-use structs::requests::{ChatWithMembers, Session};
+use structs::requests::{ChatWithMembers, PublishIdentityKey, Session, WsMessageData};
 
-use crate::server;
+use crate::{e2ee, server, ws_client::WsEvent};
 
 use super::{
     chat::{Chat, ChatMessage},
     chat_list::{ChatList, ChatListMessage},
     header::{Header, HeaderMessage},
+    letter_list::LetterListMessage,
     settings::{Settings, SettingsMessage},
 };
 
@@ -22,6 +23,7 @@ pub struct MainScreen {
 #[derive(Debug, Clone, PartialEq)]
 pub enum MainScreenMessage {
     ChatsLoaded(Vec<ChatWithMembers>),
+    IdentityKeyPublished,
     ChatList(ChatListMessage),
     Header(HeaderMessage),
     Error(String),
@@ -34,10 +36,13 @@ impl MainScreen {
         session: Session,
         client: reqwest::Client,
     ) -> (Self, iced::Command<MainScreenMessage>) {
+        // Before the chats load, their keys are derived with it
+        let identity_key = e2ee::open(&session.user_id);
         let (header, load_header_pfp) = Header::new(session.clone(), client.clone());
+        let (chat_list, load_contacts) = ChatList::new(client.clone(), session.clone());
         let screen = Self {
             session: session.clone(),
-            chat_list: ChatList::new(client.clone(), session.clone()),
+            chat_list,
             header,
             settings: None,
         };
@@ -46,13 +51,26 @@ impl MainScreen {
             iced::Command::batch(vec![
                 iced::Command::perform(
                     server::server_get::<Vec<ChatWithMembers>>(
-                        client,
+                        client.clone(),
                         "chats".into(),
                         Some(session.session_id.clone()),
                     ),
                     move |chats| MainScreenMessage::ChatsLoaded(chats.unwrap()),
                 ),
+                iced::Command::perform(
+                    server::server_post::<()>(
+                        client,
+                        "identity_key",
+                        PublishIdentityKey { identity_key },
+                        Some(session.session_id.clone()),
+                    ),
+                    |res| match res {
+                        Ok(()) => MainScreenMessage::IdentityKeyPublished,
+                        Err(err) => MainScreenMessage::Error(err.to_string()),
+                    },
+                ),
                 load_header_pfp.map(MainScreenMessage::Header),
+                load_contacts.map(MainScreenMessage::ChatList),
             ]),
         )
     }
@@ -68,25 +86,34 @@ impl MainScreen {
             MainScreenMessage::Header(msg) => {
                 self.header.update(msg).map(MainScreenMessage::Header)
             }
-            MainScreenMessage::ChatList(msg) => self.chat_list.update(msg).map(|msg| {
-                if let ChatListMessage::Error(err) = msg {
-                    MainScreenMessage::Error(err)
-                } else {
-                    MainScreenMessage::ChatList(msg)
-                }
-            }),
+            MainScreenMessage::ChatList(msg) => {
+                // Own profile changes made on another device are shown in the header too
+                let header_update = match &msg {
+                    ChatListMessage::LetterListMessage(LetterListMessage::WsEvent(
+                        WsEvent::Message(WsMessageData::ProfileUpdated(status)),
+                    )) if status.id == self.session.user_id => {
+                        self.session.username = status.username.clone();
+                        self.header
+                            .update(HeaderMessage::StatusLoaded(Some(status.clone())))
+                            .map(MainScreenMessage::Header)
+                    }
+                    _ => iced::Command::none(),
+                };
+                iced::Command::batch(vec![
+                    header_update,
+                    self.chat_list.update(msg).map(|msg| {
+                        if let ChatListMessage::Error(err) = msg {
+                            MainScreenMessage::Error(err)
+                        } else {
+                            MainScreenMessage::ChatList(msg)
+                        }
+                    }),
+                ])
+            }
             MainScreenMessage::ChatsLoaded(loaded_chats) => {
                 let chats: Vec<(iced::Command<ChatMessage>, String)> = loaded_chats
                     .into_iter()
-                    .map(|chat| {
-                        Chat::new(
-                            &mut self.chat_list,
-                            self.session.clone().user_id,
-                            chat.id,
-                            chat.members,
-                            chat.last_updated,
-                        )
-                    })
+                    .map(|chat| Chat::new(&mut self.chat_list, self.session.clone().user_id, chat))
                     .collect();
                 iced::Command::batch(chats.into_iter().map(|(cmd, chat_id)| {
                     cmd.map(move |msg| {
@@ -94,12 +121,25 @@ impl MainScreen {
                     })
                 }))
             }
+            MainScreenMessage::IdentityKeyPublished => iced::Command::none(),
             MainScreenMessage::Error(_) => unreachable!(),
             MainScreenMessage::Settings(SettingsMessage::ChangesApplied)
             | MainScreenMessage::SettingsClosed => {
                 self.settings = None;
                 iced::Command::none()
             }
+            MainScreenMessage::Settings(SettingsMessage::UsernameChanged(username)) => {
+                self.session.username = username.clone();
+                self.header.session.username = username.clone();
+                self.chat_list.session.username = username.clone();
+                if let Some(ref mut settings) = self.settings {
+                    settings
+                        .update(SettingsMessage::UsernameChanged(username))
+                        .map(MainScreenMessage::Settings)
+                } else {
+                    iced::Command::none()
+                }
+            }
             MainScreenMessage::Settings(msg) => {
                 if let Some(ref mut settings) = self.settings {
                     settings.update(msg).map(|msg| {
@@ -4,6 +4,13 @@ use iced::{
     widget::{button, container::Appearance, scrollable, text},
     Color, Font, Theme,
 };
+use structs::{
+    markdown::to_plain_text,
+    requests::{ChatMember, MessageKind, WsChatMessage},
+    DateTime, Duration, Local, Utc,
+};
+
+use crate::theme;
 
 const ICON_FONT: Font = Font::with_name("Segoe Fluent Icons");
 
@@ -30,6 +37,55 @@ fn truncate_message(message: String, length: usize) -> String {
     }
 }
 
+/// Text of a message for previews, voice messages without a caption are named instead
+fn message_text(message: &WsChatMessage) -> String {
+    match &message.kind {
+        MessageKind::Voice(_) if message.message.is_empty() => "🎤 Голосовое сообщение".into(),
+        _ => to_plain_text(&message.message),
+    }
+}
+
+/// Shown name of a chat member, members that are gone are shown as a deleted account
+fn member_name(members: &[ChatMember], user_id: &str) -> String {
+    members
+        .iter()
+        .find(|member| member.id == user_id)
+        .map(|member| member.name().to_owned())
+        .unwrap_or_else(|| "Удалённый аккаунт".into())
+}
+
+/// Short age of a timestamp for lists: minutes for fresh ones, then time, "yesterday" or date
+fn format_relative(time: DateTime<Utc>) -> String {
+    let age = Utc::now() - time;
+    let local: DateTime<Local> = time.into();
+    let today = Local::now().date_naive();
+    if age < Duration::minutes(1) {
+        "сейчас".into()
+    } else if age < Duration::hours(1) {
+        format!("{} мин", age.num_minutes())
+    } else if local.date_naive() == today {
+        local.format("%H:%M").to_string()
+    } else if today.pred_opt() == Some(local.date_naive()) {
+        "вчера".into()
+    } else {
+        local.format("%d/%m/%Y").to_string()
+    }
+}
+
+/// Time left until a disappearing message is deleted, in its largest unit
+fn format_remaining(until: DateTime<Utc>) -> String {
+    let left = until - Utc::now();
+    if left < Duration::minutes(1) {
+        "< 1 мин".into()
+    } else if left < Duration::hours(1) {
+        format!("{} мин", left.num_minutes())
+    } else if left < Duration::days(1) {
+        format!("{} ч", left.num_hours())
+    } else {
+        format!("{} д", left.num_days())
+    }
+}
+
 pub(crate) enum ButtonStyle {
     Hover,
     Simple,
@@ -47,11 +103,13 @@ impl button::StyleSheet for ButtonStyle {
                 border_width: 2.0,
                 border_color: style.palette().primary,
                 background: Some(style.palette().background.into()),
+                text_color: style.palette().text,
                 ..button::Appearance::default()
             },
             ButtonStyle::Simple => button::Appearance {
                 border_radius: 8.0.into(),
                 background: Some(style.palette().background.into()),
+                text_color: style.palette().text,
                 ..button::Appearance::default()
             },
             ButtonStyle::Blue => button::Appearance {
@@ -76,24 +134,23 @@ impl scrollable::StyleSheet for ScrollableStyle {
     type Style = Theme;
 
     fn active(&self, style: &Self::Style) -> scrollable::Scrollbar {
-        let _ = style;
         scrollable::Scrollbar {
-            border_color: Color::WHITE,
+            border_color: Color::TRANSPARENT,
             background: None,
             border_radius: 0.0.into(),
             border_width: 0.0,
             scroller: scrollable::Scroller {
-                color: Color::from_rgba8(0, 0, 0, 0.2),
+                color: theme::scroller(style),
                 border_radius: 8.0.into(),
                 border_width: 0.0,
-                border_color: Color::WHITE,
+                border_color: Color::TRANSPARENT,
             },
         }
     }
 
     fn hovered(&self, style: &Self::Style, mouse_over_scrollbar: bool) -> scrollable::Scrollbar {
         scrollable::Scrollbar {
-            border_color: Color::WHITE,
+            border_color: Color::TRANSPARENT,
             background: None,
             border_radius: 0.0.into(),
             border_width: 0.0,
@@ -101,11 +158,11 @@ impl scrollable::StyleSheet for ScrollableStyle {
                 color: if mouse_over_scrollbar {
                     style.palette().primary
                 } else {
-                    Color::from_rgba8(0, 0, 0, 0.2)
+                    theme::scroller(style)
                 },
                 border_radius: 8.0.into(),
                 border_width: 0.0,
-                border_color: Color::WHITE,
+                border_color: Color::TRANSPARENT,
             },
         }
     }
@@ -116,7 +173,90 @@ fn style_outline(theme: &Theme) -> Appearance {
         border_width: 2.0,
         border_color: theme.palette().primary,
         border_radius: 8.0.into(),
-        background: Some(Color::from_rgb8(240, 240, 240).into()),
+        background: Some(theme::surface(theme).into()),
+        text_color: Some(theme.palette().text),
+        ..Appearance::default()
+    }
+}
+
+fn style_muted(theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme::muted(theme.palette().text)),
+        ..Appearance::default()
+    }
+}
+
+fn style_error(theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme.palette().danger),
+        ..Appearance::default()
+    }
+}
+
+fn style_online(theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme.palette().success),
+        ..Appearance::default()
+    }
+}
+
+fn style_own_muted(_theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme::muted(Color::WHITE)),
+        ..Appearance::default()
+    }
+}
+
+fn style_link(theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme.palette().primary),
+        ..Appearance::default()
+    }
+}
+
+/// Own messages are drawn on the primary color, so links there can't use it
+fn style_own_link(theme: &Theme) -> Appearance {
+    Appearance {
+        text_color: Some(theme::own_link(theme)),
+        ..Appearance::default()
+    }
+}
+
+fn style_waveform(theme: &Theme) -> Appearance {
+    Appearance {
+        background: Some(theme.palette().primary.into()),
+        border_radius: 1.0.into(),
+        ..Appearance::default()
+    }
+}
+
+fn style_own_waveform(_theme: &Theme) -> Appearance {
+    Appearance {
+        background: Some(Color::WHITE.into()),
+        border_radius: 1.0.into(),
+        ..Appearance::default()
+    }
+}
+
+/// Card with the link preview under a message
+fn style_preview(theme: &Theme) -> Appearance {
+    Appearance {
+        background: Some(theme::inset(theme).into()),
+        border_radius: 6.0.into(),
+        ..Appearance::default()
+    }
+}
+
+fn style_code(theme: &Theme) -> Appearance {
+    Appearance {
+        background: Some(
+            Color {
+                a: 0.14,
+                ..theme::inset(theme)
+            }
+            .into(),
+        ),
+        border_radius: 4.0.into(),
         ..Appearance::default()
     }
 }
@@ -128,5 +268,7 @@ pub mod letter;
 pub mod letter_list;
 pub mod login_screen;
 pub mod main_screen;
+pub mod rich_text;
 pub mod settings;
+pub mod user_search;
 pub mod web_image;
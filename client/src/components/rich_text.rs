@@ -0,0 +1,96 @@
+use iced::{
+    font,
+    widget::{column, container, mouse_area, text},
+    Element, Font, Length,
+};
+use iced_aw::Wrap;
+use structs::markdown::{self, Block, Span};
+
+use super::{style_code, style_link, style_own_link};
+
+pub const TEXT_FONT: Font = Font::with_name("Inter");
+
+fn span_font(span: &Span) -> Font {
+    if span.code {
+        return Font::MONOSPACE;
+    }
+    Font {
+        weight: if span.bold {
+            font::Weight::Bold
+        } else {
+            font::Weight::Normal
+        },
+        style: if span.italic {
+            font::Style::Italic
+        } else {
+            font::Style::Normal
+        },
+        ..TEXT_FONT
+    }
+}
+
+/// Formatted message text, clicked links are turned into messages by `on_link`
+pub fn view<'a, Msg: Clone + 'a>(
+    source: &str,
+    is_own: bool,
+    on_link: impl Fn(String) -> Msg,
+) -> Element<'a, Msg> {
+    column(
+        markdown::parse(source)
+            .into_iter()
+            .map(|block| match block {
+                Block::Paragraph(spans) => paragraph(spans, is_own, &on_link),
+                Block::Code(code) => container(text(code).font(Font::MONOSPACE).size(14))
+                    .padding(8)
+                    .width(Length::Fill)
+                    .style(style_code)
+                    .into(),
+            })
+            .collect(),
+    )
+    .spacing(5)
+    .into()
+}
+
+fn paragraph<'a, Msg: Clone + 'a>(
+    spans: Vec<Span>,
+    is_own: bool,
+    on_link: &impl Fn(String) -> Msg,
+) -> Element<'a, Msg> {
+    // Every word is a widget of its own so that lines can wrap between them
+    let mut lines: Vec<Vec<Element<'a, Msg>>> = vec![Vec::new()];
+    for span in spans {
+        for (index, line) in span.text.split('\n').enumerate() {
+            if index > 0 {
+                lines.push(Vec::new());
+            }
+            for word in line.split_inclusive(' ') {
+                let word = text(word).font(span_font(&span));
+                lines.last_mut().unwrap().push(match &span.link {
+                    Some(url) => mouse_area(container(word).style(if is_own {
+                        style_own_link
+                    } else {
+                        style_link
+                    }))
+                    .on_press(on_link(url.clone()))
+                    .into(),
+                    None => word.into(),
+                });
+            }
+        }
+    }
+
+    column(
+        lines
+            .into_iter()
+            .map(|line| {
+                if line.is_empty() {
+                    text(" ").into()
+                } else {
+                    Wrap::with_elements(line).into()
+                }
+            })
+            .collect(),
+    )
+    .into()
+}
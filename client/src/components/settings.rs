@@ -1,32 +1,110 @@
 use iced::{
     theme::Button,
-    widget::{button, column, container, row, text, text_input},
-    Length,
+    widget::{button, column, container, pick_list, qr_code, row, text, text_input, QRCode, Space},
+    Element, Length,
 };
 use native_dialog::FileDialog;
+use std::fmt::Display;
 
 use reqwest::{multipart, Body};
-use structs::requests::{Session, UpdateProfile};
+use structs::requests::{
+    Audience, BlockRequest, ChangePassword, ChangeUsername, ConfirmTotp, DeleteAccount,
+    DisableTotp, PrivacySettings, RecoveryCodes, Session, TotpSetup, TotpStatus, UpdateProfile,
+    UserStatus,
+};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
-use crate::server::{self, get_profile_picture, server_post};
+use crate::{
+    config::ClientConfig,
+    server::{self, get_user_status, server_get, server_post},
+    theme::ThemeMode,
+};
 
 use super::{style_outline, ButtonStyle};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AudienceOption(Audience);
+
+impl AudienceOption {
+    const ALL: [AudienceOption; 3] = [
+        AudienceOption(Audience::Everyone),
+        AudienceOption(Audience::Contacts),
+        AudienceOption(Audience::Nobody),
+    ];
+}
+
+impl Display for AudienceOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self.0 {
+            Audience::Everyone => "Все",
+            Audience::Contacts => "Контакты",
+            Audience::Nobody => "Никто",
+        })
+    }
+}
+
 pub struct Settings {
     client: reqwest::Client,
     session: Session,
     profile_picture: String,
+    display_name_input: String,
+    bio_input: String,
+    status_text_input: String,
+    theme_mode: ThemeMode,
+    username_input: String,
+    old_password_input: String,
+    new_password_input: String,
+    delete_password_input: String,
+    privacy: PrivacySettings,
+    blocked: Vec<UserStatus>,
+    totp_status: TotpStatus,
+    /// Started but not confirmed yet
+    totp_setup: Option<(TotpSetup, qr_code::State)>,
+    totp_code_input: String,
+    /// Shown once right after two-factor login is turned on
+    recovery_codes: Vec<String>,
+    totp_password_input: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum SettingsMessage {
     ProfilePictureSelecting,
+    StatusLoaded(Option<UserStatus>),
     ProfilePictureLoaded(Option<String>),
+    DisplayNameInputChanged(String),
+    BioInputChanged(String),
+    StatusTextInputChanged(String),
     ProfilePictureChanged(String),
     Error(String),
     ApplyChanges,
     ChangesApplied,
+    ThemeSelected(ThemeMode),
+    UsernameInputChanged(String),
+    ChangeUsername,
+    UsernameChanged(String),
+    OldPasswordInputChanged(String),
+    NewPasswordInputChanged(String),
+    ChangePassword,
+    PasswordChanged,
+    DeletePasswordInputChanged(String),
+    DeleteAccount,
+    AccountDeleted,
+    PrivacyLoaded(PrivacySettings),
+    PrivacyChanged(PrivacySettings),
+    PrivacySaved,
+    BlockedLoaded(Vec<UserStatus>),
+    Unblock(String),
+    Unblocked(String),
+    TotpStatusLoaded(TotpStatus),
+    SetUpTotp,
+    TotpSetupLoaded(TotpSetup),
+    TotpCodeInputChanged(String),
+    ConfirmTotp,
+    TotpConfirmed(RecoveryCodes),
+    RecoveryCodesSaved,
+    TotpPasswordInputChanged(String),
+    DisableTotp,
+    TotpDisabled,
 }
 
 impl Settings {
@@ -34,17 +112,64 @@ impl Settings {
         client: reqwest::Client,
         session: Session,
     ) -> (Self, iced::Command<SettingsMessage>) {
-        let username = session.user_id.clone();
+        let user_id = session.user_id.clone();
+        let session_id = session.session_id.clone();
         (
             Self {
                 client: client.clone(),
+                username_input: session.username.clone(),
                 session: session,
                 profile_picture: "".into(),
+                display_name_input: String::new(),
+                bio_input: String::new(),
+                status_text_input: String::new(),
+                theme_mode: ClientConfig::load().theme,
+                old_password_input: String::new(),
+                new_password_input: String::new(),
+                delete_password_input: String::new(),
+                privacy: PrivacySettings::default(),
+                blocked: Vec::new(),
+                totp_status: TotpStatus::default(),
+                totp_setup: None,
+                totp_code_input: String::new(),
+                recovery_codes: Vec::new(),
+                totp_password_input: String::new(),
             },
-            iced::Command::perform(
-                get_profile_picture(client, username),
-                SettingsMessage::ProfilePictureLoaded,
-            ),
+            iced::Command::batch([
+                iced::Command::perform(
+                    get_user_status(client.clone(), session_id.clone(), user_id),
+                    SettingsMessage::StatusLoaded,
+                ),
+                iced::Command::perform(
+                    server_get::<PrivacySettings>(
+                        client.clone(),
+                        "privacy".into(),
+                        Some(session_id.clone()),
+                    ),
+                    |res| match res {
+                        Ok(privacy) => SettingsMessage::PrivacyLoaded(privacy),
+                        Err(err) => SettingsMessage::Error(err.to_string()),
+                    },
+                ),
+                iced::Command::perform(
+                    server_get::<TotpStatus>(
+                        client.clone(),
+                        "totp".into(),
+                        Some(session_id.clone()),
+                    ),
+                    |res| match res {
+                        Ok(status) => SettingsMessage::TotpStatusLoaded(status),
+                        Err(err) => SettingsMessage::Error(err.to_string()),
+                    },
+                ),
+                iced::Command::perform(
+                    server_get::<Vec<UserStatus>>(client, "blocked".into(), Some(session_id)),
+                    |res| match res {
+                        Ok(blocked) => SettingsMessage::BlockedLoaded(blocked),
+                        Err(err) => SettingsMessage::Error(err.to_string()),
+                    },
+                ),
+            ]),
         )
     }
 
@@ -93,6 +218,27 @@ impl Settings {
                     SettingsMessage::ProfilePictureLoaded,
                 )
             }
+            SettingsMessage::StatusLoaded(status) => {
+                if let Some(status) = status {
+                    self.profile_picture = status.profile_picture.unwrap_or_default();
+                    self.display_name_input = status.display_name.unwrap_or_default();
+                    self.bio_input = status.bio.unwrap_or_default();
+                    self.status_text_input = status.status_text.unwrap_or_default();
+                }
+                iced::Command::none()
+            }
+            SettingsMessage::DisplayNameInputChanged(display_name) => {
+                self.display_name_input = display_name;
+                iced::Command::none()
+            }
+            SettingsMessage::BioInputChanged(bio) => {
+                self.bio_input = bio;
+                iced::Command::none()
+            }
+            SettingsMessage::StatusTextInputChanged(status_text) => {
+                self.status_text_input = status_text;
+                iced::Command::none()
+            }
             SettingsMessage::ProfilePictureLoaded(pfp) => {
                 if let Some(pfp) = pfp {
                     self.profile_picture = pfp;
@@ -109,6 +255,10 @@ impl Settings {
                         } else {
                             Some(self.profile_picture.clone())
                         },
+                        // The server clears blank fields
+                        display_name: Some(self.display_name_input.clone()),
+                        bio: Some(self.bio_input.clone()),
+                        status_text: Some(self.status_text_input.clone()),
                     },
                     Some(self.session.session_id.clone()),
                 ),
@@ -123,6 +273,208 @@ impl Settings {
                 self.profile_picture = pfp;
                 iced::Command::none()
             }
+            SettingsMessage::ThemeSelected(mode) => {
+                self.theme_mode = mode;
+                iced::Command::none()
+            }
+            SettingsMessage::UsernameInputChanged(username) => {
+                self.username_input = username;
+                iced::Command::none()
+            }
+            SettingsMessage::ChangeUsername => {
+                let username = self.username_input.clone();
+                iced::Command::perform(
+                    server_post::<()>(
+                        self.client.clone(),
+                        "change_username",
+                        ChangeUsername {
+                            username: username.clone(),
+                        },
+                        Some(self.session.session_id.clone()),
+                    ),
+                    |res| match res {
+                        Ok(_) => SettingsMessage::UsernameChanged(username),
+                        Err(err) => SettingsMessage::Error(err.to_string()),
+                    },
+                )
+            }
+            SettingsMessage::UsernameChanged(username) => {
+                self.session.username = username;
+                iced::Command::none()
+            }
+            SettingsMessage::OldPasswordInputChanged(password) => {
+                self.old_password_input = password;
+                iced::Command::none()
+            }
+            SettingsMessage::NewPasswordInputChanged(password) => {
+                self.new_password_input = password;
+                iced::Command::none()
+            }
+            SettingsMessage::ChangePassword => iced::Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "change_password",
+                    ChangePassword {
+                        old_password: self.old_password_input.clone(),
+                        new_password: self.new_password_input.clone(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(_) => SettingsMessage::PasswordChanged,
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::PasswordChanged => {
+                self.old_password_input.clear();
+                self.new_password_input.clear();
+                iced::Command::none()
+            }
+            SettingsMessage::DeletePasswordInputChanged(password) => {
+                self.delete_password_input = password;
+                iced::Command::none()
+            }
+            SettingsMessage::DeleteAccount => iced::Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "delete_account",
+                    DeleteAccount {
+                        password: self.delete_password_input.clone(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(_) => SettingsMessage::AccountDeleted,
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::AccountDeleted => unreachable!(),
+            SettingsMessage::PrivacyLoaded(privacy) => {
+                self.privacy = privacy;
+                iced::Command::none()
+            }
+            SettingsMessage::PrivacyChanged(privacy) => {
+                self.privacy = privacy.clone();
+                iced::Command::perform(
+                    server_post::<()>(
+                        self.client.clone(),
+                        "privacy",
+                        privacy,
+                        Some(self.session.session_id.clone()),
+                    ),
+                    |res| match res {
+                        Ok(_) => SettingsMessage::PrivacySaved,
+                        Err(err) => SettingsMessage::Error(err.to_string()),
+                    },
+                )
+            }
+            SettingsMessage::PrivacySaved => iced::Command::none(),
+            SettingsMessage::BlockedLoaded(blocked) => {
+                self.blocked = blocked;
+                iced::Command::none()
+            }
+            SettingsMessage::Unblock(user_id) => iced::Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "unblock",
+                    BlockRequest {
+                        user_id: user_id.clone(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(_) => SettingsMessage::Unblocked(user_id),
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::Unblocked(user_id) => {
+                self.blocked.retain(|status| status.id != user_id);
+                iced::Command::none()
+            }
+            SettingsMessage::TotpStatusLoaded(status) => {
+                self.totp_status = status;
+                iced::Command::none()
+            }
+            SettingsMessage::SetUpTotp => iced::Command::perform(
+                server_post::<TotpSetup>(
+                    self.client.clone(),
+                    "totp/setup",
+                    (),
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(setup) => SettingsMessage::TotpSetupLoaded(setup),
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::TotpSetupLoaded(setup) => {
+                match qr_code::State::new(&setup.provisioning_uri) {
+                    Ok(qr_code) => {
+                        self.totp_setup = Some((setup, qr_code));
+                        self.totp_code_input.clear();
+                        iced::Command::none()
+                    }
+                    Err(err) => iced::Command::perform(
+                        async move { err.to_string() },
+                        SettingsMessage::Error,
+                    ),
+                }
+            }
+            SettingsMessage::TotpCodeInputChanged(code) => {
+                self.totp_code_input = code;
+                iced::Command::none()
+            }
+            SettingsMessage::ConfirmTotp => iced::Command::perform(
+                server_post::<RecoveryCodes>(
+                    self.client.clone(),
+                    "totp/confirm",
+                    ConfirmTotp {
+                        code: self.totp_code_input.clone(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(codes) => SettingsMessage::TotpConfirmed(codes),
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::TotpConfirmed(codes) => {
+                self.totp_setup = None;
+                self.totp_code_input.clear();
+                self.totp_status = TotpStatus {
+                    enabled: true,
+                    recovery_codes_left: codes.codes.len(),
+                };
+                self.recovery_codes = codes.codes;
+                iced::Command::none()
+            }
+            SettingsMessage::RecoveryCodesSaved => {
+                self.recovery_codes.clear();
+                iced::Command::none()
+            }
+            SettingsMessage::TotpPasswordInputChanged(password) => {
+                self.totp_password_input = password;
+                iced::Command::none()
+            }
+            SettingsMessage::DisableTotp => iced::Command::perform(
+                server_post::<()>(
+                    self.client.clone(),
+                    "totp/disable",
+                    DisableTotp {
+                        password: self.totp_password_input.clone(),
+                    },
+                    Some(self.session.session_id.clone()),
+                ),
+                |res| match res {
+                    Ok(_) => SettingsMessage::TotpDisabled,
+                    Err(err) => SettingsMessage::Error(err.to_string()),
+                },
+            ),
+            SettingsMessage::TotpDisabled => {
+                self.totp_password_input.clear();
+                self.totp_status = TotpStatus::default();
+                iced::Command::none()
+            }
         }
     }
 
@@ -141,6 +493,149 @@ impl Settings {
                 .width(Length::Fill)
                 .padding(10)
                 .spacing(10),
+                column![
+                    text("Профиль"),
+                    text_input("Отображаемое имя", &self.display_name_input)
+                        .on_input(SettingsMessage::DisplayNameInputChanged)
+                        .on_submit(SettingsMessage::ApplyChanges),
+                    text_input("Статус", &self.status_text_input)
+                        .on_input(SettingsMessage::StatusTextInputChanged)
+                        .on_submit(SettingsMessage::ApplyChanges),
+                    text_input("О себе", &self.bio_input)
+                        .on_input(SettingsMessage::BioInputChanged)
+                        .on_submit(SettingsMessage::ApplyChanges),
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
+                column![
+                    text("Тема"),
+                    row(ThemeMode::ALL
+                        .into_iter()
+                        .map(|mode| {
+                            button(text(mode.to_string()))
+                                .padding([8, 12])
+                                .style(Button::Custom(Box::new(if mode == self.theme_mode {
+                                    ButtonStyle::Blue
+                                } else {
+                                    ButtonStyle::Hover
+                                })))
+                                .on_press(SettingsMessage::ThemeSelected(mode))
+                                .into()
+                        })
+                        .collect())
+                    .spacing(10)
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
+                column![
+                    text("Имя пользователя"),
+                    row![
+                        text_input("Имя пользователя", &self.username_input)
+                            .on_input(SettingsMessage::UsernameInputChanged)
+                            .on_submit(SettingsMessage::ChangeUsername),
+                        button("Изменить")
+                            .on_press(SettingsMessage::ChangeUsername)
+                            .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                    ]
+                    .spacing(10)
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
+                column![
+                    text("Пароль"),
+                    text_input("Текущий пароль", &self.old_password_input)
+                        .on_input(SettingsMessage::OldPasswordInputChanged)
+                        .password(),
+                    row![
+                        text_input("Новый пароль", &self.new_password_input)
+                            .on_input(SettingsMessage::NewPasswordInputChanged)
+                            .on_submit(SettingsMessage::ChangePassword)
+                            .password(),
+                        button("Сменить")
+                            .on_press(SettingsMessage::ChangePassword)
+                            .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                    ]
+                    .spacing(10)
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
+                column![
+                    text("Конфиденциальность"),
+                    row![
+                        text("Кто может начать чат"),
+                        Space::with_width(Length::Fill),
+                        pick_list(
+                            AudienceOption::ALL.to_vec(),
+                            Some(AudienceOption(self.privacy.who_can_start_chats)),
+                            {
+                                let privacy = self.privacy.clone();
+                                move |option: AudienceOption| {
+                                    SettingsMessage::PrivacyChanged(PrivacySettings {
+                                        who_can_start_chats: option.0,
+                                        ..privacy.clone()
+                                    })
+                                }
+                            }
+                        )
+                    ]
+                    .align_items(iced::Alignment::Center),
+                    row![
+                        text("Кто видит статус и фото"),
+                        Space::with_width(Length::Fill),
+                        pick_list(
+                            AudienceOption::ALL.to_vec(),
+                            Some(AudienceOption(self.privacy.who_can_see_status)),
+                            {
+                                let privacy = self.privacy.clone();
+                                move |option: AudienceOption| {
+                                    SettingsMessage::PrivacyChanged(PrivacySettings {
+                                        who_can_see_status: option.0,
+                                        ..privacy.clone()
+                                    })
+                                }
+                            }
+                        )
+                    ]
+                    .align_items(iced::Alignment::Center),
+                    column(
+                        self.blocked
+                            .iter()
+                            .map(|status| {
+                                row![
+                                    text(
+                                        status.display_name.as_deref().unwrap_or(&status.username)
+                                    ),
+                                    Space::with_width(Length::Fill),
+                                    button("Разблокировать")
+                                        .on_press(SettingsMessage::Unblock(status.id.clone()))
+                                        .style(Button::Custom(Box::new(ButtonStyle::Hover)))
+                                ]
+                                .align_items(iced::Alignment::Center)
+                                .into()
+                            })
+                            .collect()
+                    )
+                    .spacing(5)
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
+                column![text("Двухфакторная аутентификация"), self.two_factor_view()]
+                    .align_items(iced::Alignment::Center)
+                    .spacing(10),
+                column![
+                    text("Удаление аккаунта"),
+                    row![
+                        text_input("Пароль", &self.delete_password_input)
+                            .on_input(SettingsMessage::DeletePasswordInputChanged)
+                            .password(),
+                        button("Удалить")
+                            .on_press(SettingsMessage::DeleteAccount)
+                            .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                    ]
+                    .spacing(10)
+                ]
+                .align_items(iced::Alignment::Center)
+                .spacing(10),
                 button("Сохранить")
                     .padding([8, 12])
                     .style(Button::Custom(Box::new(ButtonStyle::Blue)))
@@ -154,4 +649,71 @@ impl Settings {
         .style(style_outline)
         .into()
     }
+
+    fn two_factor_view(&self) -> Element<SettingsMessage> {
+        if !self.recovery_codes.is_empty() {
+            return column![
+                text("Сохраните коды восстановления, больше они не будут показаны"),
+                column(
+                    self.recovery_codes
+                        .iter()
+                        .map(|code| text(code).font(iced::Font::MONOSPACE).into())
+                        .collect()
+                )
+                .spacing(5),
+                button("Коды сохранены")
+                    .on_press(SettingsMessage::RecoveryCodesSaved)
+                    .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+            ]
+            .align_items(iced::Alignment::Center)
+            .spacing(10)
+            .into();
+        }
+
+        if self.totp_status.enabled {
+            return column![
+                text(format!(
+                    "Включена, кодов восстановления осталось: {}",
+                    self.totp_status.recovery_codes_left
+                )),
+                row![
+                    text_input("Пароль", &self.totp_password_input)
+                        .on_input(SettingsMessage::TotpPasswordInputChanged)
+                        .on_submit(SettingsMessage::DisableTotp)
+                        .password(),
+                    button("Отключить")
+                        .on_press(SettingsMessage::DisableTotp)
+                        .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                ]
+                .spacing(10)
+            ]
+            .align_items(iced::Alignment::Center)
+            .spacing(10)
+            .into();
+        }
+
+        match &self.totp_setup {
+            Some((setup, qr_code)) => column![
+                text("Отсканируйте код в приложении-аутентификаторе или введите ключ вручную"),
+                QRCode::new(qr_code).cell_size(4),
+                text(&setup.secret).font(iced::Font::MONOSPACE),
+                row![
+                    text_input("Код из приложения", &self.totp_code_input)
+                        .on_input(SettingsMessage::TotpCodeInputChanged)
+                        .on_submit(SettingsMessage::ConfirmTotp),
+                    button("Подтвердить")
+                        .on_press(SettingsMessage::ConfirmTotp)
+                        .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                ]
+                .spacing(10)
+            ]
+            .align_items(iced::Alignment::Center)
+            .spacing(10)
+            .into(),
+            None => button("Включить")
+                .on_press(SettingsMessage::SetUpTotp)
+                .style(Button::Custom(Box::new(ButtonStyle::Blue)))
+                .into(),
+        }
+    }
 }
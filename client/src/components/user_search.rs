@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use iced::{
+    alignment,
+    theme::Button,
+    widget::{button, column, container, row, text, text_input, Space},
+    Command, Element, Length,
+};
+use structs::requests::{Contact, ContactRequest, ContactState, Session, UserStatus};
+
+use crate::server::{server_get, server_post};
+
+use super::{
+    icon_button, style_muted, style_online,
+    web_image::{WebImage, WebImageMessage},
+    ButtonStyle,
+};
+
+/// Username input that suggests matching users, shows contacts while it is empty
+pub struct UserSearch {
+    client: reqwest::Client,
+    session: Session,
+    pub query: String,
+    results: Vec<UserStatus>,
+    contacts: Vec<Contact>,
+    avatars: HashMap<String, WebImage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserSearchMessage {
+    QueryChanged(String),
+    ResultsLoaded(String, Vec<UserStatus>),
+    ReloadContacts,
+    ContactsLoaded(Vec<Contact>),
+    Avatar(String, WebImageMessage),
+    AddContact(String),
+    RemoveContact(String),
+    ContactSaved,
+    /// Start a chat with the typed username
+    Submit,
+    /// Start a chat with a suggested user
+    UserSelected(String),
+    Error(String),
+}
+
+impl UserSearch {
+    pub fn new(client: reqwest::Client, session: Session) -> (Self, Command<UserSearchMessage>) {
+        let mut user_search = Self {
+            client,
+            session,
+            query: String::new(),
+            results: Vec::new(),
+            contacts: Vec::new(),
+            avatars: HashMap::new(),
+        };
+        let load_contacts = user_search.update(UserSearchMessage::ReloadContacts);
+        (user_search, load_contacts)
+    }
+
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.results.clear();
+    }
+
+    /// Keeps names, avatars and presence of the shown users fresh
+    pub fn profile_updated(&mut self, status: &UserStatus) -> Command<UserSearchMessage> {
+        let users = self
+            .results
+            .iter_mut()
+            .chain(self.contacts.iter_mut().map(|contact| &mut contact.user))
+            .filter(|user| user.id == status.id);
+        let mut picture_changed = false;
+        for user in users {
+            picture_changed |= user.profile_picture != status.profile_picture;
+            *user = status.clone();
+        }
+        if picture_changed {
+            self.avatars.remove(&status.id);
+        }
+        self.load_avatars()
+    }
+
+    fn load_avatars(&mut self) -> Command<UserSearchMessage> {
+        let missing: Vec<(String, Option<String>)> = self
+            .results
+            .iter()
+            .chain(self.contacts.iter().map(|contact| &contact.user))
+            .filter(|user| !self.avatars.contains_key(&user.id))
+            .map(|user| (user.id.clone(), user.profile_picture.clone()))
+            .collect();
+
+        Command::batch(missing.into_iter().map(|(id, profile_picture)| {
+            let avatar = self
+                .avatars
+                .entry(id.clone())
+                .or_insert_with(|| WebImage::new(self.client.clone()));
+            match profile_picture {
+                Some(url) => avatar
+                    .load_image(url)
+                    .map(move |msg| UserSearchMessage::Avatar(id.clone(), msg)),
+                None => Command::none(),
+            }
+        }))
+    }
+
+    fn contact_state(&self, user_id: &str) -> Option<ContactState> {
+        self.contacts
+            .iter()
+            .find(|contact| contact.user.id == user_id)
+            .map(|contact| contact.state)
+    }
+
+    fn save_contact(&self, route: &'static str, user_id: String) -> Command<UserSearchMessage> {
+        Command::perform(
+            server_post::<()>(
+                self.client.clone(),
+                route,
+                ContactRequest { user_id },
+                Some(self.session.session_id.clone()),
+            ),
+            // The contact list is reloaded once the server confirms over the socket
+            |result| match result {
+                Ok(_) => UserSearchMessage::ContactSaved,
+                Err(err) => UserSearchMessage::Error(err.to_string()),
+            },
+        )
+    }
+
+    pub fn update(&mut self, message: UserSearchMessage) -> Command<UserSearchMessage> {
+        match message {
+            UserSearchMessage::QueryChanged(query) => {
+                self.query = query.clone();
+                if query.trim().is_empty() {
+                    self.results.clear();
+                    return Command::none();
+                }
+                Command::perform(
+                    server_get::<Vec<UserStatus>>(
+                        self.client.clone(),
+                        format!("users/search?q={}", urlencoding::encode(&query)),
+                        Some(self.session.session_id.clone()),
+                    ),
+                    // A failed search just shows no suggestions
+                    move |results| {
+                        UserSearchMessage::ResultsLoaded(query, results.unwrap_or_default())
+                    },
+                )
+            }
+            UserSearchMessage::ResultsLoaded(query, results) => {
+                // Responses for older queries can arrive late
+                if query != self.query {
+                    return Command::none();
+                }
+                self.results = results;
+                self.load_avatars()
+            }
+            UserSearchMessage::ReloadContacts => Command::perform(
+                server_get::<Vec<Contact>>(
+                    self.client.clone(),
+                    "contacts".into(),
+                    Some(self.session.session_id.clone()),
+                ),
+                |contacts| match contacts {
+                    Ok(contacts) => UserSearchMessage::ContactsLoaded(contacts),
+                    Err(err) => UserSearchMessage::Error(err.to_string()),
+                },
+            ),
+            UserSearchMessage::ContactsLoaded(contacts) => {
+                self.contacts = contacts;
+                self.load_avatars()
+            }
+            UserSearchMessage::Avatar(id, msg) => match self.avatars.get_mut(&id) {
+                Some(avatar) => avatar
+                    .update(msg)
+                    .map(move |msg| UserSearchMessage::Avatar(id.clone(), msg)),
+                None => Command::none(),
+            },
+            UserSearchMessage::AddContact(user_id) => self.save_contact("add_contact", user_id),
+            UserSearchMessage::RemoveContact(user_id) => {
+                self.save_contact("remove_contact", user_id)
+            }
+            UserSearchMessage::ContactSaved => Command::none(),
+            UserSearchMessage::Submit
+            | UserSearchMessage::UserSelected(_)
+            | UserSearchMessage::Error(_) => unreachable!(),
+        }
+    }
+
+    fn user_view(&self, user: &UserStatus) -> Element<UserSearchMessage> {
+        let avatar: Element<UserSearchMessage> = match self.avatars.get(&user.id) {
+            Some(avatar) => avatar.view().width(28).height(28).into(),
+            None => Space::new(28, 28).into(),
+        };
+        let presence = container(text("●").size(10)).style(if user.online {
+            style_online
+        } else {
+            style_muted
+        });
+
+        let user_button = button(
+            row![
+                avatar,
+                presence,
+                column![
+                    text(user.display_name.as_ref().unwrap_or(&user.username)),
+                    container(text(format!("@{}", user.username)).size(11)).style(style_muted)
+                ]
+            ]
+            .spacing(8)
+            .align_items(alignment::Alignment::Center),
+        )
+        .style(Button::Custom(Box::new(ButtonStyle::Simple)))
+        .on_press(UserSearchMessage::UserSelected(user.username.clone()))
+        .width(Length::Fill);
+
+        let add = || icon_button('').on_press(UserSearchMessage::AddContact(user.id.clone()));
+        let remove = || {
+            icon_button('')
+                .style(Button::Custom(Box::new(ButtonStyle::Red)))
+                .on_press(UserSearchMessage::RemoveContact(user.id.clone()))
+        };
+        let actions = match self.contact_state(&user.id) {
+            None => row![add()],
+            Some(ContactState::Incoming) => row![
+                icon_button('').on_press(UserSearchMessage::AddContact(user.id.clone())),
+                remove()
+            ],
+            Some(ContactState::Outgoing) | Some(ContactState::Accepted) => row![remove()],
+        };
+
+        row![user_button, actions.spacing(5)]
+            .spacing(5)
+            .align_items(alignment::Alignment::Center)
+            .into()
+    }
+
+    pub fn view(&self) -> Element<UserSearchMessage> {
+        let input = row![
+            text_input("Имя пользователя", &self.query)
+                .padding(8)
+                .on_input(UserSearchMessage::QueryChanged)
+                .on_submit(UserSearchMessage::Submit),
+            icon_button('').on_press(UserSearchMessage::Submit),
+        ]
+        .spacing(5);
+
+        let mut suggestions = column![].spacing(2);
+        if self.query.trim().is_empty() {
+            let requests = self
+                .contacts
+                .iter()
+                .filter(|contact| contact.state == ContactState::Incoming)
+                .collect::<Vec<_>>();
+            if !requests.is_empty() {
+                suggestions = suggestions.push(text("Запросы в контакты").size(12));
+                for contact in requests {
+                    suggestions = suggestions.push(self.user_view(&contact.user));
+                }
+            }
+
+            let accepted = self
+                .contacts
+                .iter()
+                .filter(|contact| contact.state == ContactState::Accepted)
+                .collect::<Vec<_>>();
+            if !accepted.is_empty() {
+                suggestions = suggestions.push(text("Контакты").size(12));
+                for contact in accepted {
+                    suggestions = suggestions.push(self.user_view(&contact.user));
+                }
+            }
+        } else {
+            for user in &self.results {
+                suggestions = suggestions.push(self.user_view(user));
+            }
+        }
+
+        column![input, suggestions].spacing(5).into()
+    }
+}
@@ -2,6 +2,7 @@ use iced::widget::{image::Handle, Image};
 
 pub struct WebImage {
     image: Handle,
+    bytes: Option<Vec<u8>>,
     client: reqwest::Client,
 }
 
@@ -14,6 +15,7 @@ impl WebImage {
     pub fn new(client: reqwest::Client) -> Self {
         Self {
             image: Handle::from_memory(include_bytes!("../../default_avatar.png")),
+            bytes: None,
             client,
         }
     }
@@ -34,13 +36,19 @@ impl WebImage {
         match message {
             WebImageMessage::ImageLoaded(bytes) => {
                 if let Some(bytes) = bytes {
-                    self.image = Handle::from_memory(bytes);
+                    self.image = Handle::from_memory(bytes.clone());
+                    self.bytes = Some(bytes);
                 }
                 iced::Command::none()
             }
         }
     }
 
+    /// Raw bytes of the loaded image, `None` while the default avatar is shown
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.bytes.as_deref()
+    }
+
     pub fn view(&self) -> Image<Handle> {
         Image::new(self.image.clone())
     }
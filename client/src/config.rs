@@ -0,0 +1,36 @@
+use std::{fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::theme::ThemeMode;
+
+/// Client-side preferences persisted between launches
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub theme: ThemeMode,
+}
+
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("taco").join("config.json"))
+}
+
+impl ClientConfig {
+    /// Falls back to defaults if the file is missing or unreadable
+    pub fn load() -> Self {
+        config_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|config| serde_json::from_str(&config).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let Some(path) = config_path() else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, serde_json::to_string_pretty(self).unwrap());
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::HashMap, fs, io::Write, path::PathBuf, sync::Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use structs::{
+    archive::ChatArchive,
+    requests::{ChatKey, CreateChatKey, WsChatMessage, WsMessageData},
+};
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+
+/// Shown instead of messages encrypted with a key this device doesn't have
+pub const UNREADABLE: &str = "🔒 Не удалось расшифровать сообщение";
+
+const NONCE_LENGTH: usize = 12;
+
+/// Private keys of a user, they never leave this device
+#[derive(Deserialize, Serialize)]
+struct Keystore {
+    #[serde(skip)]
+    user_id: String,
+    identity: String,
+    /// Derived keys by chat key id
+    chat_keys: HashMap<String, String>,
+    /// Identity keys of other users as they were when their safety number was compared
+    verified: HashMap<String, String>,
+}
+
+/// Of the logged in user
+static KEYSTORE: Mutex<Option<Keystore>> = Mutex::new(None);
+
+fn keystore_path(user_id: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("taco").join(format!("keys-{user_id}.json")))
+}
+
+impl Keystore {
+    fn identity(&self) -> StaticSecret {
+        let bytes: [u8; 32] = STANDARD
+            .decode(&self.identity)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .expect("identity key is stored as 32 bytes");
+        StaticSecret::from(bytes)
+    }
+
+    fn chat_key(&self, key_id: &str) -> Option<Key> {
+        let bytes = STANDARD.decode(self.chat_keys.get(key_id)?).ok()?;
+        (bytes.len() == 32).then(|| *Key::from_slice(&bytes))
+    }
+
+    fn save(&self) {
+        let Some(path) = keystore_path(&self.user_id) else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        // Written next to the keystore and renamed over it, so the keys are never half written or
+        // readable by other users, not even for a moment
+        let temporary = path.with_extension("json.tmp");
+        let _ = fs::remove_file(&temporary);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let written = options.open(&temporary).and_then(|mut file| {
+            file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes())?;
+            file.sync_all()
+        });
+        if written.and_then(|_| fs::rename(&temporary, path)).is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+}
+
+/// Loads the keys of the user, generating an identity key on the first login on this device.
+/// Returns the public identity key
+pub fn open(user_id: &str) -> String {
+    let keystore = keystore_path(user_id)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|keystore| serde_json::from_str::<Keystore>(&keystore).ok())
+        .map(|keystore| Keystore {
+            user_id: user_id.into(),
+            ..keystore
+        })
+        .unwrap_or_else(|| {
+            let keystore = Keystore {
+                user_id: user_id.into(),
+                identity: STANDARD.encode(StaticSecret::random_from_rng(OsRng).to_bytes()),
+                chat_keys: HashMap::new(),
+                verified: HashMap::new(),
+            };
+            keystore.save();
+            keystore
+        });
+    let identity_key = encode_public(&PublicKey::from(&keystore.identity()));
+    *KEYSTORE.lock().unwrap() = Some(keystore);
+    identity_key
+}
+
+fn encode_public(key: &PublicKey) -> String {
+    STANDARD.encode(key.as_bytes())
+}
+
+fn decode_public(key: &str) -> Option<PublicKey> {
+    let bytes: [u8; 32] = STANDARD.decode(key).ok()?.try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+/// Own public identity key
+pub fn identity_key() -> Option<String> {
+    KEYSTORE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|keystore| encode_public(&PublicKey::from(&keystore.identity())))
+}
+
+/// HKDF over both Diffie-Hellman outputs, bound to the chat and the public keys used.
+/// `None` if a key was a low order point
+fn derive(dh1: SharedSecret, dh2: SharedSecret, chat_key: &CreateChatKey) -> Option<Key> {
+    if !dh1.was_contributory() || !dh2.was_contributory() {
+        return None;
+    }
+    let mut input = [0; 64];
+    input[..32].copy_from_slice(dh1.as_bytes());
+    input[32..].copy_from_slice(dh2.as_bytes());
+    let info = format!(
+        "taco chat key\n{}\n{}\n{}\n{}",
+        chat_key.chat_id, chat_key.initiator_key, chat_key.responder_key, chat_key.ephemeral_key
+    );
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(None, &input)
+        .expand(info.as_bytes(), &mut key)
+        .expect("32 bytes is a valid HKDF output");
+    Some(key)
+}
+
+/// New key agreement with the member whose identity key is `responder_key`, the chat key is
+/// kept by `remember` once the server has given it an id
+pub fn agree(chat_id: &str, responder_key: &str) -> Option<(CreateChatKey, Key)> {
+    let keystore = KEYSTORE.lock().unwrap();
+    let identity = keystore.as_ref()?.identity();
+    let responder = decode_public(responder_key)?;
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let request = CreateChatKey {
+        chat_id: chat_id.into(),
+        initiator_key: encode_public(&PublicKey::from(&identity)),
+        responder_key: responder_key.into(),
+        ephemeral_key: encode_public(&PublicKey::from(&ephemeral)),
+    };
+    let key = derive(
+        identity.diffie_hellman(&responder),
+        ephemeral.diffie_hellman(&responder),
+        &request,
+    )?;
+    Some((request, key))
+}
+
+pub fn remember(key_id: &str, key: Key) {
+    if let Some(keystore) = KEYSTORE.lock().unwrap().as_mut() {
+        keystore
+            .chat_keys
+            .insert(key_id.into(), STANDARD.encode(key));
+        keystore.save();
+    }
+}
+
+/// Derives the chat key if this user is its responder. Keys agreed for an identity key this
+/// device doesn't have are skipped, their messages stay unreadable
+pub fn learn(chat_key: &ChatKey) {
+    let mut keystore = KEYSTORE.lock().unwrap();
+    let Some(keystore) = keystore.as_mut() else {
+        return;
+    };
+    if keystore.chat_keys.contains_key(&chat_key.id) || chat_key.initiator_id == keystore.user_id {
+        return;
+    }
+    let identity = keystore.identity();
+    if encode_public(&PublicKey::from(&identity)) != chat_key.responder_key {
+        return;
+    }
+    let (Some(initiator), Some(ephemeral)) = (
+        decode_public(&chat_key.initiator_key),
+        decode_public(&chat_key.ephemeral_key),
+    ) else {
+        return;
+    };
+    let request = CreateChatKey {
+        chat_id: chat_key.chat_id.clone(),
+        initiator_key: chat_key.initiator_key.clone(),
+        responder_key: chat_key.responder_key.clone(),
+        ephemeral_key: chat_key.ephemeral_key.clone(),
+    };
+    if let Some(key) = derive(
+        identity.diffie_hellman(&initiator),
+        identity.diffie_hellman(&ephemeral),
+        &request,
+    ) {
+        keystore
+            .chat_keys
+            .insert(chat_key.id.clone(), STANDARD.encode(key));
+        keystore.save();
+    }
+}
+
+/// Whether messages can be encrypted with the chat key on this device
+pub fn has_key(key_id: &str) -> bool {
+    KEYSTORE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|keystore| keystore.chat_key(key_id).is_some())
+}
+
+/// Base64 of the nonce followed by the ciphertext, the sender is authenticated along with it
+pub fn encrypt(key_id: &str, sender_id: &str, text: &str) -> Option<String> {
+    let key = KEYSTORE.lock().unwrap().as_ref()?.chat_key(key_id)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(&key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: text.as_bytes(),
+                aad: sender_id.as_bytes(),
+            },
+        )
+        .ok()?;
+    Some(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn decrypt_content(key_id: &str, sender_id: &str, content: &str) -> Option<String> {
+    let key = KEYSTORE.lock().unwrap().as_ref()?.chat_key(key_id)?;
+    let content = STANDARD.decode(content).ok()?;
+    if content.len() < NONCE_LENGTH {
+        return None;
+    }
+    let (nonce, ciphertext) = content.split_at(NONCE_LENGTH);
+    let text = ChaCha20Poly1305::new(&key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: sender_id.as_bytes(),
+            },
+        )
+        .ok()?;
+    String::from_utf8(text).ok()
+}
+
+/// Replaces the content of an encrypted message with its text
+pub fn decrypt(message: &mut WsChatMessage) {
+    if let Some(key_id) = &message.key_id {
+        message.message = decrypt_content(key_id, &message.sender_id, &message.message)
+            .unwrap_or_else(|| UNREADABLE.into());
+    }
+}
+
+pub fn decrypt_all(mut messages: Vec<WsChatMessage>) -> Vec<WsChatMessage> {
+    messages.iter_mut().for_each(decrypt);
+    messages
+}
+
+/// Decrypts what this device has the keys for. The rest stays ciphertext, it's readable again
+/// wherever the archive is imported for whoever holds the keys
+pub fn decrypt_archive(archive: &mut ChatArchive) {
+    for message in &mut archive.messages {
+        if let Some(text) = message
+            .key_id
+            .as_deref()
+            .and_then(|key_id| decrypt_content(key_id, &message.sender_id, &message.message))
+        {
+            message.message = text;
+            message.key_id = None;
+        }
+    }
+}
+
+/// Called on everything the server pushes before the UI sees it
+pub fn receive(data: &mut WsMessageData) {
+    match data {
+        WsMessageData::ChatKey(chat_key) => learn(chat_key),
+        WsMessageData::ChatMessage(message) => decrypt(message),
+        _ => {}
+    }
+}
+
+/// Same on both sides of the chat: 12 groups of 5 digits, compared in person or over a call
+pub fn safety_number(
+    user_id: &str,
+    identity_key: &str,
+    other_user_id: &str,
+    other_identity_key: &str,
+) -> String {
+    let mut parties = [(user_id, identity_key), (other_user_id, other_identity_key)];
+    parties.sort();
+    let mut hasher = Sha512::new();
+    for (user_id, identity_key) in parties {
+        hasher.update(user_id.as_bytes());
+        hasher.update([0]);
+        hasher.update(identity_key.as_bytes());
+        hasher.update([0]);
+    }
+    let digest = hasher.finalize();
+    digest
+        .chunks_exact(5)
+        .map(|chunk| {
+            let number = chunk
+                .iter()
+                .fold(0u64, |number, byte| number << 8 | u64::from(*byte));
+            format!("{:05}", number % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|groups| groups.join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Unverified,
+    Verified,
+    /// The user was verified with an identity key they've since replaced
+    Changed,
+}
+
+pub fn verification(user_id: &str, identity_key: &str) -> Verification {
+    match KEYSTORE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|keystore| keystore.verified.get(user_id).cloned())
+    {
+        None => Verification::Unverified,
+        Some(verified) if verified == identity_key => Verification::Verified,
+        Some(_) => Verification::Changed,
+    }
+}
+
+pub fn mark_verified(user_id: &str, identity_key: &str) {
+    if let Some(keystore) = KEYSTORE.lock().unwrap().as_mut() {
+        keystore
+            .verified
+            .insert(user_id.into(), identity_key.into());
+        keystore.save();
+    }
+}
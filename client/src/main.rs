@@ -4,19 +4,30 @@ use iced::{
     alignment, font,
     widget::{container, text},
     window::icon,
-    Application, Color, Command, Element, Font, Length, Settings,
+    Application, Command, Element, Font, Length, Settings, Theme,
 };
 use iced_aw::modal;
 
 use components::{
+    chat_list::ChatListMessage,
     header::HeaderMessage,
+    letter_list::LetterListMessage,
     login_screen::{LoginScreen, LoginScreenMessage},
     main_screen::{MainScreen, MainScreenMessage},
+    settings::SettingsMessage,
 };
+use config::ClientConfig;
 use server::server_post;
+use structs::requests::WsMessageData;
+use ws_client::WsEvent;
 
 mod components;
+mod config;
+mod e2ee;
+mod notifications;
 mod server;
+mod theme;
+mod voice;
 mod ws_client;
 
 #[tokio::main]
@@ -39,6 +50,8 @@ struct Taco {
     state: AppState,
     client: reqwest::Client,
     error: Option<String>,
+    config: ClientConfig,
+    theme: Theme,
 }
 
 enum AppState {
@@ -66,11 +79,14 @@ impl Application for Taco {
     type Flags = reqwest::Client;
 
     fn new(client: reqwest::Client) -> (Self, Command<AppMessage>) {
+        let config = ClientConfig::load();
         (
             Taco {
                 state: AppState::Guest(LoginScreen::new()),
                 client,
                 error: None,
+                theme: config.theme.theme(),
+                config,
             },
             Command::batch(vec![
                 font::load(include_bytes!("../fonts/inter.ttf").as_slice()),
@@ -105,15 +121,44 @@ impl Application for Taco {
                 AppState::LoggedIn(ref mut main_screen) => {
                     if let AppMessage::MainScreen(msg) = message {
                         match msg {
-                            MainScreenMessage::Header(HeaderMessage::LogOut) => Command::perform(
-                                server_post::<()>(
-                                    self.client.clone(),
-                                    "logout",
-                                    (),
-                                    Some(main_screen.session.session_id.clone()),
-                                ),
-                                |_| AppMessage::LoggedOut,
-                            ),
+                            MainScreenMessage::Header(HeaderMessage::LogOut) => {
+                                let client = self.client.clone();
+                                let session_id = main_screen.session.session_id.clone();
+                                Command::perform(
+                                    async move {
+                                        let _ = server_post::<()>(
+                                            client,
+                                            "logout",
+                                            (),
+                                            Some(session_id.clone()),
+                                        )
+                                        .await;
+                                        server::forget_tokens(&session_id);
+                                    },
+                                    |_| AppMessage::LoggedOut,
+                                )
+                            }
+                            MainScreenMessage::ChatList(ChatListMessage::LetterListMessage(
+                                LetterListMessage::WsEvent(WsEvent::Message(
+                                    WsMessageData::AccountDisabled,
+                                )),
+                            )) => {
+                                server::forget_tokens(&main_screen.session.session_id);
+                                self.state = AppState::Guest(LoginScreen::new());
+                                self.error = Some("Аккаунт заблокирован администратором".into());
+                                Command::none()
+                            }
+                            MainScreenMessage::Settings(SettingsMessage::AccountDeleted) => {
+                                server::forget_tokens(&main_screen.session.session_id);
+                                self.state = AppState::Guest(LoginScreen::new());
+                                Command::none()
+                            }
+                            MainScreenMessage::Settings(SettingsMessage::ThemeSelected(mode)) => {
+                                self.config.theme = mode;
+                                self.config.save();
+                                self.theme = mode.theme();
+                                main_screen.update(msg).map(AppMessage::MainScreen)
+                            }
                             _ => main_screen.update(msg).map(|msg| {
                                 if let MainScreenMessage::Error(err) = msg {
                                     AppMessage::Error(err)
@@ -130,6 +175,10 @@ impl Application for Taco {
                     if let AppMessage::LoginScreen(msg) = message {
                         match msg {
                             LoginScreenMessage::LoggedIn(session) => {
+                                server::remember_tokens(
+                                    session.session_id.clone(),
+                                    session.tokens.clone(),
+                                );
                                 let (screen, cmd) = MainScreen::new(session, self.client.clone());
                                 self.state = AppState::LoggedIn(screen);
                                 cmd.map(AppMessage::MainScreen)
@@ -154,10 +203,13 @@ impl Application for Taco {
         let overlay = self.error.as_ref().map(|err| {
             container(
                 text(err)
-                    .style(Color::from_rgba8(255, 0, 0, 1.0))
                     .width(Length::Fill)
                     .horizontal_alignment(alignment::Horizontal::Center),
             )
+            .style(|theme: &Theme| container::Appearance {
+                text_color: Some(theme.palette().danger),
+                ..Default::default()
+            })
             .width(Length::Fill)
             .padding([0, 50])
         });
@@ -180,7 +232,7 @@ impl Application for Taco {
     }
 
     fn theme(&self) -> Self::Theme {
-        Self::Theme::default()
+        self.theme.clone()
     }
 
     fn style(&self) -> <Self::Theme as iced::application::StyleSheet>::Style {
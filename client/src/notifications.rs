@@ -0,0 +1,60 @@
+/// Each notification waiting for a click holds a thread of the blocking pool, the ones past
+/// this many are shown without waiting
+#[cfg(target_os = "linux")]
+const MAX_WAITING: usize = 4;
+
+#[cfg(target_os = "linux")]
+static WAITING: tokio::sync::Semaphore = tokio::sync::Semaphore::const_new(MAX_WAITING);
+
+/// A desktop notification about an incoming message
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub sender: String,
+    pub preview: String,
+    pub avatar: Option<Vec<u8>>,
+}
+
+/// Shows the notification through `org.freedesktop.Notifications`, returns `true` once it is clicked
+#[cfg(target_os = "linux")]
+pub async fn show(notification: Notification) -> bool {
+    use std::hash::{Hash, Hasher};
+
+    let permit = WAITING.try_acquire().ok();
+    tokio::task::spawn_blocking(move || {
+        // The notification server only accepts a path to the image, so the avatar is cached to disk
+        let avatar_path = notification.avatar.and_then(|avatar| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            notification.sender.hash(&mut hasher);
+            let path = std::env::temp_dir().join(format!("taco-avatar-{:x}", hasher.finish()));
+            std::fs::write(&path, avatar).ok()?;
+            Some(path)
+        });
+
+        let mut desktop_notification = notify_rust::Notification::new();
+        desktop_notification
+            .appname("Taco")
+            .summary(&notification.sender)
+            .body(&notification.preview)
+            .action("default", "Открыть");
+        if let Some(path) = &avatar_path {
+            desktop_notification.image_path(&path.to_string_lossy());
+        }
+
+        let Ok(handle) = desktop_notification.show() else {
+            return false;
+        };
+        let Some(_permit) = permit else {
+            return false;
+        };
+        let mut clicked = false;
+        handle.wait_for_action(|action| clicked = action == "default");
+        clicked
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn show(_notification: Notification) -> bool {
+    false
+}
@@ -1,11 +1,17 @@
 use reqwest;
-use reqwest::header::HeaderMap;
+use reqwest::header::RETRY_AFTER;
+use reqwest::multipart;
 use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt::Display;
-use structs::requests::UserStatus;
+use std::sync::{Mutex, OnceLock};
+use structs::requests::{RefreshRequest, Tokens, UserStatus};
+use structs::{Duration, Utc};
+
+use crate::voice::VoiceRecording;
 
 pub fn server_url() -> String {
     let args: Vec<String> = env::args().collect();
@@ -19,6 +25,8 @@ pub(crate) enum ServerRequestError {
     InvalidDataError(serde_json::Error),
     InvalidResponseError(serde_json::Error),
     Status(StatusCode, Option<String>),
+    /// Too many requests, the server asks to retry after this many seconds
+    RateLimited(u64),
 }
 
 impl Display for ServerRequestError {
@@ -37,44 +45,159 @@ impl Display for ServerRequestError {
                         .unwrap_or("Неизвестная ошибка.")
                 )
             }
+            ServerRequestError::RateLimited(seconds) => {
+                write!(f, "Слишком много попыток, повторите через {seconds} с.")
+            }
         }
     }
 }
 
-pub(crate) async fn server_post<T: DeserializeOwned>(
-    client: reqwest::Client,
-    route: &'static str,
-    data: impl Serialize,
-    session: Option<String>,
-) -> Result<T, ServerRequestError> {
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", "application/json".parse().unwrap());
-
-    if let Some(session) = session {
-        headers.insert(
-            "Authorization",
-            format!("Bearer {session}").parse().unwrap(),
-        );
+async fn check_status(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ServerRequestError> {
+    match response.status() {
+        StatusCode::OK => Ok(response),
+        StatusCode::TOO_MANY_REQUESTS => Err(ServerRequestError::RateLimited(
+            response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1),
+        )),
+        status => Err(ServerRequestError::Status(
+            status,
+            response.text().await.ok(),
+        )),
     }
+}
+
+/// Tokens of the logged in sessions by session id, requests only name the session they are
+/// made for, so every copy of it uses the latest tokens
+static TOKENS: Mutex<BTreeMap<String, Tokens>> = Mutex::new(BTreeMap::new());
+/// Held while refreshing, a refresh token works only once
+static REFRESHING: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+pub(crate) fn remember_tokens(session_id: String, tokens: Tokens) {
+    TOKENS.lock().unwrap().insert(session_id, tokens);
+}
 
+pub(crate) fn forget_tokens(session_id: &str) {
+    TOKENS.lock().unwrap().remove(session_id);
+}
+
+fn stored_tokens(session_id: &str) -> Result<Tokens, ServerRequestError> {
+    TOKENS
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned()
+        .ok_or(ServerRequestError::Status(
+            StatusCode::UNAUTHORIZED,
+            Some("Сессия истекла".into()),
+        ))
+}
+
+/// Gets new tokens unless `rejected` was already replaced by another request
+async fn refresh(
+    client: &reqwest::Client,
+    session_id: &str,
+    rejected: &str,
+) -> Result<String, ServerRequestError> {
+    let _refreshing = REFRESHING
+        .get_or_init(|| tokio::sync::Mutex::new(()))
+        .lock()
+        .await;
+    let tokens = stored_tokens(session_id)?;
+    if tokens.access_token != rejected {
+        return Ok(tokens.access_token);
+    }
+    // Not through `server_post`, which may end up here again
+    let body = serde_json::to_string(&RefreshRequest {
+        refresh_token: tokens.refresh_token,
+    })
+    .map_err(ServerRequestError::InvalidResponseError)?;
     let response = client
-        .post(&format!("{}/{route}", format!("http://{}", server_url())))
-        .headers(headers)
-        .body(
-            serde_json::to_value(data)
-                .map_err(ServerRequestError::InvalidResponseError)?
-                .to_string(),
-        )
+        .post(format!("http://{}/refresh", server_url()))
+        .header("Content-Type", "application/json")
+        .body(body)
         .send()
         .await
         .map_err(ServerRequestError::ReqwestError)?;
-    let response = match response.status() {
-        StatusCode::OK => Ok(response),
-        status => Err(ServerRequestError::Status(
-            status,
-            response.text().await.ok(),
-        )),
-    }?;
+    let response_data = check_status(response)
+        .await?
+        .text()
+        .await
+        .map_err(ServerRequestError::ReqwestError)?;
+    let tokens: Tokens =
+        serde_json::from_str(&response_data).map_err(ServerRequestError::InvalidDataError)?;
+    let access_token = tokens.access_token.clone();
+    remember_tokens(session_id.into(), tokens);
+    Ok(access_token)
+}
+
+/// Access token of the session, refreshed first if it's about to expire
+pub(crate) async fn access_token(
+    client: &reqwest::Client,
+    session_id: &str,
+) -> Result<String, ServerRequestError> {
+    let tokens = stored_tokens(session_id)?;
+    if tokens.access_expires_at - Utc::now() < Duration::seconds(30) {
+        refresh(client, session_id, &tokens.access_token).await
+    } else {
+        Ok(tokens.access_token)
+    }
+}
+
+/// Sends the request as the session, refreshing the tokens and retrying once if they were
+/// rejected, e.g. after the server restarted with a new secret
+async fn send(
+    client: &reqwest::Client,
+    session: Option<String>,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ServerRequestError> {
+    let Some(session_id) = session else {
+        return request()
+            .send()
+            .await
+            .map_err(ServerRequestError::ReqwestError);
+    };
+    let token = access_token(client, &session_id).await?;
+    let response = request()
+        .bearer_auth(&token)
+        .send()
+        .await
+        .map_err(ServerRequestError::ReqwestError)?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let token = refresh(client, &session_id, &token).await?;
+    request()
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(ServerRequestError::ReqwestError)
+}
+
+/// `session` is the id of the session to authenticate as
+pub(crate) async fn server_post<T: DeserializeOwned>(
+    client: reqwest::Client,
+    route: &'static str,
+    data: impl Serialize,
+    session: Option<String>,
+) -> Result<T, ServerRequestError> {
+    let body = serde_json::to_value(data)
+        .map_err(ServerRequestError::InvalidResponseError)?
+        .to_string();
+    let url = format!("http://{}/{route}", server_url());
+    let response = send(&client, session, || {
+        client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+    })
+    .await?;
+    let response = check_status(response).await?;
     let response_data = response
         .text()
         .await
@@ -89,40 +212,68 @@ pub(crate) async fn server_get<T: DeserializeOwned>(
     route: String,
     session: Option<String>,
 ) -> Result<T, ServerRequestError> {
-    let mut headers = HeaderMap::new();
+    let url = format!("http://{}/{route}", server_url());
+    let response = send(&client, session, || client.get(&url)).await?;
+    let response = check_status(response).await?;
+    let response_data = response
+        .text()
+        .await
+        .map_err(ServerRequestError::ReqwestError)?;
+    let response_value =
+        serde_json::from_str(&response_data).map_err(ServerRequestError::InvalidDataError)?;
+    Ok(response_value)
+}
 
-    if let Some(session) = session {
-        headers.insert(
-            "Authorization",
-            format!("Bearer {session}").parse().unwrap(),
+/// Uploads a recording, returns the id to send it with
+pub(crate) async fn upload_voice(
+    client: reqwest::Client,
+    session: String,
+    recording: VoiceRecording,
+) -> Result<String, ServerRequestError> {
+    let url = format!("http://{}/upload_voice", server_url());
+    let response = send(&client, Some(session), || {
+        let file = multipart::Part::bytes(recording.ogg.clone())
+            .file_name("voice.ogg")
+            .mime_str("audio/ogg")
+            .unwrap();
+        let form = multipart::Form::new().part("file", file).part(
+            "waveform",
+            multipart::Part::bytes(recording.waveform.clone()),
         );
-    }
+        client.post(&url).multipart(form)
+    })
+    .await?;
+    check_status(response)
+        .await?
+        .text()
+        .await
+        .map_err(ServerRequestError::ReqwestError)
+}
 
+/// Downloads a file the server keeps under `/content`
+pub(crate) async fn get_content(
+    client: reqwest::Client,
+    name: String,
+) -> Result<Vec<u8>, ServerRequestError> {
     let response = client
-        .get(&format!("{}/{route}", format!("http://{}", server_url())))
-        .headers(headers)
+        .get(format!("http://{}/content/{name}", server_url()))
         .send()
         .await
         .map_err(ServerRequestError::ReqwestError)?;
-    let response = match response.status() {
-        StatusCode::OK => Ok(response),
-        status => Err(ServerRequestError::Status(
-            status,
-            response.text().await.ok(),
-        )),
-    }?;
-    let response_data = response
-        .text()
+    let bytes = check_status(response)
+        .await?
+        .bytes()
         .await
         .map_err(ServerRequestError::ReqwestError)?;
-    let response_value =
-        serde_json::from_str(&response_data).map_err(ServerRequestError::InvalidDataError)?;
-    Ok(response_value)
+    Ok(bytes.to_vec())
 }
 
-pub async fn get_profile_picture(client: reqwest::Client, user: String) -> Option<String> {
-    server_get::<UserStatus>(client, format!("status/{user}"), None)
+pub async fn get_user_status(
+    client: reqwest::Client,
+    session: String,
+    user: String,
+) -> Option<UserStatus> {
+    server_get::<UserStatus>(client, format!("status/{user}"), Some(session))
         .await
         .ok()
-        .and_then(|status| status.profile_picture)
 }
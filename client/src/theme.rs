@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+use iced::{theme::Palette, Color, Theme};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ThemeMode {
+    Light,
+    Dark,
+    #[default]
+    System,
+}
+
+impl ThemeMode {
+    pub const ALL: [ThemeMode; 3] = [ThemeMode::Light, ThemeMode::Dark, ThemeMode::System];
+
+    /// Resolves the mode into a concrete theme, asking the OS when following the system
+    pub fn theme(self) -> Theme {
+        let dark = match self {
+            ThemeMode::Light => false,
+            ThemeMode::Dark => true,
+            ThemeMode::System => dark_light::detect() == dark_light::Mode::Dark,
+        };
+        Theme::custom(if dark {
+            dark_palette()
+        } else {
+            light_palette()
+        })
+    }
+}
+
+impl Display for ThemeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ThemeMode::Light => "Светлая",
+            ThemeMode::Dark => "Тёмная",
+            ThemeMode::System => "Как в системе",
+        })
+    }
+}
+
+fn light_palette() -> Palette {
+    Palette::LIGHT
+}
+
+fn dark_palette() -> Palette {
+    Palette {
+        background: Color::from_rgb8(0x20, 0x22, 0x25),
+        text: Color::from_rgb8(0xE8, 0xE8, 0xE8),
+        primary: Palette::LIGHT.primary,
+        success: Palette::DARK.success,
+        danger: Palette::DARK.danger,
+    }
+}
+
+fn mix(a: Color, b: Color, factor: f32) -> Color {
+    Color {
+        r: a.r + (b.r - a.r) * factor,
+        g: a.g + (b.g - a.g) * factor,
+        b: a.b + (b.b - a.b) * factor,
+        a: a.a,
+    }
+}
+
+/// Background of panels and cards, slightly off the window background
+pub fn surface(theme: &Theme) -> Color {
+    let palette = theme.palette();
+    mix(palette.background, palette.text, 0.06)
+}
+
+/// Thin borders and separators
+pub fn outline(theme: &Theme) -> Color {
+    Color {
+        a: 0.4,
+        ..theme.palette().text
+    }
+}
+
+/// Idle scrollbar handle
+pub fn scroller(theme: &Theme) -> Color {
+    Color {
+        a: 0.2,
+        ..theme.palette().text
+    }
+}
+
+/// Cards and code inside message bubbles, translucent so they also show on own messages
+pub fn inset(theme: &Theme) -> Color {
+    Color {
+        a: 0.08,
+        ..theme.palette().text
+    }
+}
+
+/// Links in own messages, which are drawn on the primary color
+pub fn own_link(theme: &Theme) -> Color {
+    mix(Color::WHITE, theme.palette().primary, 0.2)
+}
+
+/// Secondary text such as timestamps, derived from the color it is drawn next to
+pub fn muted(color: Color) -> Color {
+    Color { a: 0.5, ..color }
+}
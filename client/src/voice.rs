@@ -0,0 +1,366 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Opus works at 48 kHz, recordings are resampled to it
+pub const SAMPLE_RATE: u32 = 48_000;
+/// 20 ms, what Opus is tuned for
+#[cfg(target_os = "linux")]
+const FRAME_SIZE: usize = 960;
+#[cfg(target_os = "linux")]
+const WAVEFORM_LENGTH: usize = 64;
+
+/// Encoded recording, ready for `/upload_voice`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceRecording {
+    pub ogg: Vec<u8>,
+    pub waveform: Vec<u8>,
+    pub duration: Duration,
+}
+
+/// Microphone capture on a thread of its own, audio streams can't be moved between threads
+#[derive(Clone)]
+pub struct Recorder {
+    samples: Arc<Mutex<Vec<f32>>>,
+    /// Set by the audio stream when capturing breaks off
+    error: Arc<Mutex<Option<String>>>,
+    stop: Arc<AtomicBool>,
+    sample_rate: u32,
+    started_at: Instant,
+}
+
+impl Recorder {
+    #[cfg(target_os = "linux")]
+    pub fn start() -> Result<Self, String> {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let error = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_sender, ready_receiver) = std::sync::mpsc::channel();
+        {
+            let samples = samples.clone();
+            let error = error.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let stream = match open_input(samples, error) {
+                    Ok((stream, sample_rate)) => {
+                        let _ = ready_sender.send(Ok(sample_rate));
+                        stream
+                    }
+                    Err(err) => {
+                        let _ = ready_sender.send(Err(err));
+                        return;
+                    }
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                drop(stream);
+            });
+        }
+        let sample_rate = ready_receiver
+            .recv()
+            .map_err(|_| "Микрофон недоступен".to_string())??;
+
+        Ok(Self {
+            samples,
+            error,
+            stop,
+            sample_rate,
+            started_at: Instant::now(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn start() -> Result<Self, String> {
+        Err("Запись голосовых сообщений доступна только в Linux".into())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn cancel(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops recording and returns the samples at `SAMPLE_RATE`
+    pub fn finish(&self) -> Result<Vec<f32>, String> {
+        self.cancel();
+        if let Some(err) = self.error.lock().unwrap().take() {
+            return Err(format!("Запись прервалась: {err}"));
+        }
+        let samples = std::mem::take(&mut *self.samples.lock().unwrap());
+        Ok(resample(&samples, self.sample_rate, SAMPLE_RATE))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_input(
+    samples: Arc<Mutex<Vec<f32>>>,
+    error: Arc<Mutex<Option<String>>>,
+) -> Result<(cpal::Stream, u32), String> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or("Микрофон не найден")?;
+    let config = device
+        .default_input_config()
+        .map_err(|err| err.to_string())?;
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels() as usize;
+    let stream_config = config.clone().into();
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => {
+            build_input::<f32>(&device, &stream_config, channels, samples, error)
+        }
+        cpal::SampleFormat::I16 => {
+            build_input::<i16>(&device, &stream_config, channels, samples, error)
+        }
+        cpal::SampleFormat::U16 => {
+            build_input::<u16>(&device, &stream_config, channels, samples, error)
+        }
+        format => return Err(format!("Неподдерживаемый формат звука: {format:?}")),
+    }
+    .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok((stream, sample_rate))
+}
+
+#[cfg(target_os = "linux")]
+fn build_input<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    channels: usize,
+    samples: Arc<Mutex<Vec<f32>>>,
+    error: Arc<Mutex<Option<String>>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    use cpal::{traits::DeviceTrait, Sample};
+
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // Channels are mixed down to mono
+            samples
+                .lock()
+                .unwrap()
+                .extend(data.chunks(channels).map(|frame| {
+                    frame
+                        .iter()
+                        .map(|&sample| sample.to_sample::<f32>())
+                        .sum::<f32>()
+                        / channels as f32
+                }));
+        },
+        // The first error is the one worth showing, later ones follow from it
+        move |err| {
+            error.lock().unwrap().get_or_insert(err.to_string());
+        },
+        None,
+    )
+}
+
+/// Linear interpolation is plenty for speech
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let length = (samples.len() as f64 / ratio) as usize;
+    (0..length)
+        .map(|index| {
+            let position = index as f64 * ratio;
+            let before = position as usize;
+            let after = (before + 1).min(samples.len() - 1);
+            let fraction = (position - before as f64) as f32;
+            samples[before] * (1.0 - fraction) + samples[after] * fraction
+        })
+        .collect()
+}
+
+/// Peak level of each slice of the recording, the loudest one is 255
+#[cfg(target_os = "linux")]
+fn waveform(samples: &[f32]) -> Vec<u8> {
+    let slice_length = samples.len().div_ceil(WAVEFORM_LENGTH).max(1);
+    let peaks: Vec<f32> = samples
+        .chunks(slice_length)
+        .map(|slice| {
+            slice
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        })
+        .collect();
+    let loudest = peaks.iter().copied().fold(f32::EPSILON, f32::max);
+    peaks
+        .into_iter()
+        .map(|peak| (peak / loudest * 255.0) as u8)
+        .collect()
+}
+
+/// `OpusHead` identification header for a mono stream
+#[cfg(target_os = "linux")]
+fn opus_head(pre_skip: u16) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(1);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    // No output gain, single stream mapping
+    head.extend_from_slice(&[0, 0, 0]);
+    head
+}
+
+#[cfg(target_os = "linux")]
+fn opus_tags() -> Vec<u8> {
+    let vendor = b"taco";
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+#[cfg(target_os = "linux")]
+fn encode_ogg(samples: &[f32]) -> Result<Vec<u8>, String> {
+    use ogg::PacketWriteEndInfo;
+
+    const SERIAL: u32 = 1;
+
+    let mut encoder =
+        opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Voip)
+            .map_err(|err| err.to_string())?;
+    encoder
+        .set_bitrate(opus::Bitrate::Bits(24_000))
+        .map_err(|err| err.to_string())?;
+    let pre_skip = encoder.get_lookahead().map_err(|err| err.to_string())? as u16;
+
+    let mut writer = ogg::PacketWriter::new(Vec::new());
+    writer
+        .write_packet(opus_head(pre_skip), SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_packet(opus_tags(), SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(|err| err.to_string())?;
+
+    let frame_count = samples.len().div_ceil(FRAME_SIZE);
+    for (index, frame) in samples.chunks(FRAME_SIZE).enumerate() {
+        let mut frame = frame.to_vec();
+        frame.resize(FRAME_SIZE, 0.0);
+        let packet = encoder
+            .encode_vec_float(&frame, 4000)
+            .map_err(|err| err.to_string())?;
+        let is_last = index + 1 == frame_count;
+        // The last granule position trims the padding of the final frame
+        let encoded = if is_last {
+            samples.len()
+        } else {
+            (index + 1) * FRAME_SIZE
+        };
+        writer
+            .write_packet(
+                packet,
+                SERIAL,
+                if is_last {
+                    PacketWriteEndInfo::EndStream
+                } else {
+                    PacketWriteEndInfo::NormalPacket
+                },
+                (encoded + pre_skip as usize) as u64,
+            )
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(writer.into_inner())
+}
+
+#[cfg(target_os = "linux")]
+pub async fn encode(samples: Vec<f32>) -> Result<VoiceRecording, String> {
+    if samples.is_empty() {
+        return Err("Запись пуста".into());
+    }
+    tokio::task::spawn_blocking(move || {
+        Ok(VoiceRecording {
+            ogg: encode_ogg(&samples)?,
+            waveform: waveform(&samples),
+            duration: Duration::from_secs_f64(samples.len() as f64 / SAMPLE_RATE as f64),
+        })
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn encode(_samples: Vec<f32>) -> Result<VoiceRecording, String> {
+    Err("Запись голосовых сообщений доступна только в Linux".into())
+}
+
+#[cfg(target_os = "linux")]
+fn decode_ogg(ogg: Vec<u8>) -> Result<Vec<i16>, String> {
+    // 120 ms, the longest Opus frame
+    const MAX_FRAME_SIZE: usize = 5760;
+
+    let mut reader = ogg::PacketReader::new(std::io::Cursor::new(ogg));
+    let mut decoder =
+        opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono).map_err(|err| err.to_string())?;
+    let mut pcm = Vec::new();
+    let mut frame = vec![0; MAX_FRAME_SIZE];
+    let mut pre_skip = 0;
+    let mut index = 0;
+    while let Some(packet) = reader.read_packet().map_err(|err| err.to_string())? {
+        match index {
+            0 => {
+                let head = packet.data;
+                if head.len() < 19 || !head.starts_with(b"OpusHead") {
+                    return Err("Повреждённая запись".into());
+                }
+                pre_skip = u16::from_le_bytes([head[10], head[11]]) as usize;
+            }
+            // Tags
+            1 => {}
+            _ => {
+                let length = decoder
+                    .decode(&packet.data, &mut frame, false)
+                    .map_err(|err| err.to_string())?;
+                pcm.extend_from_slice(&frame[..length]);
+            }
+        }
+        index += 1;
+    }
+    Ok(pcm.split_off(pre_skip.min(pcm.len())))
+}
+
+/// Plays the recording until it ends or `stop` is set
+#[cfg(target_os = "linux")]
+pub async fn play(ogg: Vec<u8>, stop: Arc<AtomicBool>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let pcm = decode_ogg(ogg)?;
+        let (_stream, handle) =
+            rodio::OutputStream::try_default().map_err(|err| err.to_string())?;
+        let sink = rodio::Sink::try_new(&handle).map_err(|err| err.to_string())?;
+        sink.append(rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, pcm));
+        while !sink.empty() && !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn play(_ogg: Vec<u8>, _stop: Arc<AtomicBool>) -> Result<(), String> {
+    Err("Голосовые сообщения воспроизводятся только в Linux".into())
+}
+
+/// `m:ss`, for recordings and their playback
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
@@ -1,3 +1,4 @@
+use crate::{e2ee, server};
 use iced::{futures::StreamExt, subscription, Subscription};
 use structs::requests::WsMessageData;
 use tokio::net::TcpStream;
@@ -24,11 +25,15 @@ pub fn connect(session: String) -> Subscription<WsEvent> {
         |mut s| async {
             match &mut s {
                 State::Starting(session) => {
-                    let ws = format!("ws://{}/ws", super::server::server_url());
+                    // Only the upgrade is authenticated, the socket outlives the token
+                    let token = server::access_token(&reqwest::Client::new(), session)
+                        .await
+                        .unwrap();
+                    let ws = format!("ws://{}/ws", server::server_url());
                     let mut request = ws.into_client_request().unwrap();
                     request.headers_mut().insert(
                         "Authorization",
-                        HeaderValue::from_str(&format!("Bearer {}", session.clone())).unwrap(),
+                        HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
                     );
                     let (stream, _) = tokio_tungstenite::connect_async(request).await.unwrap();
                     (WsEvent::Ready, State::Ready(stream))
@@ -38,10 +43,9 @@ pub fn connect(session: String) -> Subscription<WsEvent> {
 
                     let msg = ws.select_next_some().await;
                     if let Ok(msg) = msg {
-                        (
-                            WsEvent::Message(serde_json::from_str(msg.to_text().unwrap()).unwrap()),
-                            s,
-                        )
+                        let mut data = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+                        e2ee::receive(&mut data);
+                        (WsEvent::Message(data), s)
                     } else {
                         (WsEvent::Ready, s)
                     }
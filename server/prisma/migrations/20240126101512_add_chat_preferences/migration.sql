@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE "ChatPreference" (
+    "chat_id" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL,
+    "pinned" BOOLEAN NOT NULL DEFAULT false,
+    "muted_until" DATETIME,
+    "archived" BOOLEAN NOT NULL DEFAULT false,
+
+    PRIMARY KEY ("chat_id", "user_id"),
+    CONSTRAINT "ChatPreference_chat_id_fkey" FOREIGN KEY ("chat_id") REFERENCES "Chat" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "ChatPreference_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,25 @@
+/*
+  Warnings:
+
+  - Added the required column `username` to the `User` table. Existing users keep their id and get it as the username.
+  - A unique constraint covering the columns `[username]` on the table `User` will be added.
+
+*/
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_User" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "username" TEXT NOT NULL,
+    "password" TEXT NOT NULL,
+    "profile_picture" TEXT,
+    "online" BOOLEAN NOT NULL DEFAULT false
+);
+INSERT INTO "new_User" ("id", "username", "online", "password", "profile_picture") SELECT "id", "id", "online", "password", "profile_picture" FROM "User";
+DROP TABLE "User";
+ALTER TABLE "new_User" RENAME TO "User";
+CREATE UNIQUE INDEX "User_username_key" ON "User"("username");
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
+
+-- Messages of deleted accounts are reassigned to this user, nobody can log in as it
+INSERT INTO "User" ("id", "username", "password") VALUES ('00000000-0000-0000-0000-000000000000', '', '');
@@ -0,0 +1,4 @@
+-- AlterTable
+ALTER TABLE "User" ADD COLUMN "bio" TEXT;
+ALTER TABLE "User" ADD COLUMN "display_name" TEXT;
+ALTER TABLE "User" ADD COLUMN "status_text" TEXT;
@@ -0,0 +1,11 @@
+-- CreateTable
+CREATE TABLE "Contact" (
+    "owner_id" TEXT NOT NULL,
+    "contact_id" TEXT NOT NULL,
+    "accepted" BOOLEAN NOT NULL DEFAULT false,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY ("owner_id", "contact_id"),
+    CONSTRAINT "Contact_owner_id_fkey" FOREIGN KEY ("owner_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "Contact_contact_id_fkey" FOREIGN KEY ("contact_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,14 @@
+-- AlterTable
+ALTER TABLE "User" ADD COLUMN "who_can_see_status" TEXT NOT NULL DEFAULT 'everyone';
+ALTER TABLE "User" ADD COLUMN "who_can_start_chats" TEXT NOT NULL DEFAULT 'everyone';
+
+-- CreateTable
+CREATE TABLE "Block" (
+    "blocker_id" TEXT NOT NULL,
+    "blocked_id" TEXT NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY ("blocker_id", "blocked_id"),
+    CONSTRAINT "Block_blocker_id_fkey" FOREIGN KEY ("blocker_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "Block_blocked_id_fkey" FOREIGN KEY ("blocked_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
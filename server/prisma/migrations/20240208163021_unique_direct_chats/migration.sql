@@ -0,0 +1,46 @@
+/*
+  Warnings:
+
+  - A unique constraint covering the columns `[direct_key]` on the table `Chat` will be added. Duplicate direct chats are merged into the most recently updated one.
+
+*/
+-- Direct chats are keyed by the ids of both members
+CREATE TEMP TABLE "DirectChat" AS
+SELECT "A" AS "chat_id", MIN("B") || ':' || MAX("B") AS "direct_key"
+FROM "_ChatToUser"
+GROUP BY "A"
+HAVING COUNT(*) = 2;
+
+CREATE TEMP TABLE "MergedChat" AS
+SELECT "DirectChat"."chat_id", (
+    SELECT "Kept"."chat_id"
+    FROM "DirectChat" AS "Kept"
+    JOIN "Chat" ON "Chat"."id" = "Kept"."chat_id"
+    WHERE "Kept"."direct_key" = "DirectChat"."direct_key"
+    ORDER BY "Chat"."last_updated" DESC, "Chat"."id"
+    LIMIT 1
+) AS "kept_id"
+FROM "DirectChat";
+DELETE FROM "MergedChat" WHERE "chat_id" = "kept_id";
+
+-- Move messages and preferences of duplicates to the kept chat
+UPDATE "Message"
+SET "chat_id" = (SELECT "kept_id" FROM "MergedChat" WHERE "MergedChat"."chat_id" = "Message"."chat_id")
+WHERE "chat_id" IN (SELECT "chat_id" FROM "MergedChat");
+INSERT OR IGNORE INTO "ChatPreference" ("chat_id", "user_id", "pinned", "muted_until", "archived")
+SELECT "MergedChat"."kept_id", "ChatPreference"."user_id", "ChatPreference"."pinned", "ChatPreference"."muted_until", "ChatPreference"."archived"
+FROM "ChatPreference"
+JOIN "MergedChat" ON "MergedChat"."chat_id" = "ChatPreference"."chat_id";
+DELETE FROM "ChatPreference" WHERE "chat_id" IN (SELECT "chat_id" FROM "MergedChat");
+DELETE FROM "_ChatToUser" WHERE "A" IN (SELECT "chat_id" FROM "MergedChat");
+DELETE FROM "Chat" WHERE "id" IN (SELECT "chat_id" FROM "MergedChat");
+
+-- AlterTable
+ALTER TABLE "Chat" ADD COLUMN "direct_key" TEXT;
+UPDATE "Chat" SET "direct_key" = (SELECT "direct_key" FROM "DirectChat" WHERE "DirectChat"."chat_id" = "Chat"."id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Chat_direct_key_key" ON "Chat"("direct_key");
+
+DROP TABLE "MergedChat";
+DROP TABLE "DirectChat";
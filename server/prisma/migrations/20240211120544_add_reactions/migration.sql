@@ -0,0 +1,11 @@
+-- CreateTable
+CREATE TABLE "Reaction" (
+    "message_id" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL,
+    "emoji" TEXT NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY ("message_id", "user_id", "emoji"),
+    CONSTRAINT "Reaction_message_id_fkey" FOREIGN KEY ("message_id") REFERENCES "Message" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "Reaction_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
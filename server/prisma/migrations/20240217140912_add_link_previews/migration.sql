@@ -0,0 +1,11 @@
+-- AlterTable
+ALTER TABLE "Message" ADD COLUMN "link_url" TEXT;
+
+-- CreateTable
+CREATE TABLE "LinkPreview" (
+    "url" TEXT NOT NULL PRIMARY KEY,
+    "title" TEXT,
+    "description" TEXT,
+    "image" TEXT,
+    "fetched_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
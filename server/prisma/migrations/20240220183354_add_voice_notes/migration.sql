@@ -0,0 +1,33 @@
+-- CreateTable
+CREATE TABLE "VoiceNote" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "uploader_id" TEXT NOT NULL,
+    "duration_ms" INTEGER NOT NULL,
+    "waveform" BLOB NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "VoiceNote_uploader_id_fkey" FOREIGN KEY ("uploader_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_Message" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "chat_id" TEXT NOT NULL,
+    "content" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL,
+    "reply_id" TEXT,
+    "forwarded_from_id" TEXT,
+    "link_url" TEXT,
+    "voice_note_id" TEXT,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "Message_chat_id_fkey" FOREIGN KEY ("chat_id") REFERENCES "Chat" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "Message_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE RESTRICT ON UPDATE CASCADE,
+    CONSTRAINT "Message_reply_id_fkey" FOREIGN KEY ("reply_id") REFERENCES "Message" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Message_forwarded_from_id_fkey" FOREIGN KEY ("forwarded_from_id") REFERENCES "Message" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Message_voice_note_id_fkey" FOREIGN KEY ("voice_note_id") REFERENCES "VoiceNote" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+INSERT INTO "new_Message" ("chat_id", "content", "created_at", "forwarded_from_id", "id", "link_url", "reply_id", "user_id") SELECT "chat_id", "content", "created_at", "forwarded_from_id", "id", "link_url", "reply_id", "user_id" FROM "Message";
+DROP TABLE "Message";
+ALTER TABLE "new_Message" RENAME TO "Message";
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
@@ -0,0 +1,8 @@
+-- AlterTable
+ALTER TABLE "Chat" ADD COLUMN "message_ttl" INTEGER;
+
+-- AlterTable
+ALTER TABLE "Message" ADD COLUMN "expires_at" DATETIME;
+
+-- CreateIndex
+CREATE INDEX "Message_expires_at_idx" ON "Message"("expires_at");
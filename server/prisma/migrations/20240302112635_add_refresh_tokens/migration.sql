@@ -0,0 +1,24 @@
+/*
+  Warnings:
+
+  - Added the required column `refresh_token` to the `Session` table without a default value. Existing sessions can't be refreshed, so they are dropped and everyone logs in again.
+
+*/
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_Session" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "refresh_token" TEXT NOT NULL,
+    "previous_refresh_token" TEXT,
+    "expires_at" DATETIME NOT NULL,
+    "user_id" TEXT NOT NULL,
+    CONSTRAINT "Session_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+DROP TABLE "Session";
+ALTER TABLE "new_Session" RENAME TO "Session";
+CREATE UNIQUE INDEX "Session_id_key" ON "Session"("id");
+CREATE UNIQUE INDEX "Session_refresh_token_key" ON "Session"("refresh_token");
+CREATE UNIQUE INDEX "Session_previous_refresh_token_key" ON "Session"("previous_refresh_token");
+CREATE INDEX "Session_expires_at_idx" ON "Session"("expires_at");
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
@@ -0,0 +1,26 @@
+-- AlterTable
+ALTER TABLE "User" ADD COLUMN "pending_totp_secret" TEXT;
+ALTER TABLE "User" ADD COLUMN "totp_last_step" INTEGER;
+ALTER TABLE "User" ADD COLUMN "totp_secret" TEXT;
+
+-- CreateTable
+CREATE TABLE "RecoveryCode" (
+    "user_id" TEXT NOT NULL,
+    "code_hash" TEXT NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY ("user_id", "code_hash"),
+    CONSTRAINT "RecoveryCode_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateTable
+CREATE TABLE "PendingLogin" (
+    "token_hash" TEXT NOT NULL PRIMARY KEY,
+    "user_id" TEXT NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "expires_at" DATETIME NOT NULL,
+    CONSTRAINT "PendingLogin_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE INDEX "PendingLogin_expires_at_idx" ON "PendingLogin"("expires_at");
@@ -0,0 +1,45 @@
+-- AlterTable
+ALTER TABLE "User" ADD COLUMN "identity_key" TEXT;
+
+-- CreateTable
+CREATE TABLE "ChatKey" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "chat_id" TEXT NOT NULL,
+    "initiator_id" TEXT NOT NULL,
+    "initiator_key" TEXT NOT NULL,
+    "responder_key" TEXT NOT NULL,
+    "ephemeral_key" TEXT NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "ChatKey_chat_id_fkey" FOREIGN KEY ("chat_id") REFERENCES "Chat" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_Message" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "chat_id" TEXT NOT NULL,
+    "content" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL,
+    "reply_id" TEXT,
+    "forwarded_from_id" TEXT,
+    "link_url" TEXT,
+    "voice_note_id" TEXT,
+    "key_id" TEXT,
+    "expires_at" DATETIME,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "Message_chat_id_fkey" FOREIGN KEY ("chat_id") REFERENCES "Chat" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "Message_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "User" ("id") ON DELETE RESTRICT ON UPDATE CASCADE,
+    CONSTRAINT "Message_reply_id_fkey" FOREIGN KEY ("reply_id") REFERENCES "Message" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Message_forwarded_from_id_fkey" FOREIGN KEY ("forwarded_from_id") REFERENCES "Message" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Message_voice_note_id_fkey" FOREIGN KEY ("voice_note_id") REFERENCES "VoiceNote" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Message_key_id_fkey" FOREIGN KEY ("key_id") REFERENCES "ChatKey" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+INSERT INTO "new_Message" ("chat_id", "content", "created_at", "expires_at", "forwarded_from_id", "id", "link_url", "reply_id", "user_id", "voice_note_id") SELECT "chat_id", "content", "created_at", "expires_at", "forwarded_from_id", "id", "link_url", "reply_id", "user_id", "voice_note_id" FROM "Message";
+DROP TABLE "Message";
+ALTER TABLE "new_Message" RENAME TO "Message";
+CREATE INDEX "Message_expires_at_idx" ON "Message"("expires_at");
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
+
+-- CreateIndex
+CREATE INDEX "ChatKey_chat_id_idx" ON "ChatKey"("chat_id");
@@ -0,0 +1,11 @@
+-- AlterTable
+ALTER TABLE "Message" ADD COLUMN "forwarded_created_at" DATETIME;
+ALTER TABLE "Message" ADD COLUMN "forwarded_sender_id" TEXT;
+ALTER TABLE "Message" ADD COLUMN "forwarded_sender_name" TEXT;
+
+-- Copy the originals of existing forwards, the ones already deleted are lost
+UPDATE "Message" SET
+    "forwarded_sender_id" = (SELECT "original"."user_id" FROM "Message" AS "original" WHERE "original"."id" = "Message"."forwarded_from_id"),
+    "forwarded_sender_name" = (SELECT COALESCE("User"."display_name", "User"."username") FROM "Message" AS "original" JOIN "User" ON "User"."id" = "original"."user_id" WHERE "original"."id" = "Message"."forwarded_from_id"),
+    "forwarded_created_at" = (SELECT "original"."created_at" FROM "Message" AS "original" WHERE "original"."id" = "Message"."forwarded_from_id")
+WHERE "forwarded_from_id" IS NOT NULL;
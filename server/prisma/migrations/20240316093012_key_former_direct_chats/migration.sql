@@ -0,0 +1,32 @@
+-- Direct chats one member has left, the other member is the only one besides them who wrote
+-- there. Chats the leaver never wrote to can't be told apart and stay without a key
+CREATE TEMP TABLE "FormerDirectChat" AS
+SELECT "Member"."A" AS "chat_id", MIN("Member"."B", MIN("Message"."user_id")) || ':' || MAX("Member"."B", MIN("Message"."user_id")) AS "direct_key"
+FROM "_ChatToUser" AS "Member"
+JOIN "Chat" ON "Chat"."id" = "Member"."A"
+JOIN "Message" ON "Message"."chat_id" = "Member"."A"
+WHERE "Chat"."direct_key" IS NULL
+  AND (SELECT COUNT(*) FROM "_ChatToUser" WHERE "_ChatToUser"."A" = "Member"."A") = 1
+  AND "Member"."B" != '00000000-0000-0000-0000-000000000000'
+  AND "Message"."user_id" NOT IN ("Member"."B", '00000000-0000-0000-0000-000000000000')
+GROUP BY "Member"."A", "Member"."B"
+HAVING COUNT(DISTINCT "Message"."user_id") = 1;
+
+-- A pair that already has a direct chat keeps it, otherwise the most recently updated one is keyed
+UPDATE "Chat"
+SET "direct_key" = (SELECT "direct_key" FROM "FormerDirectChat" WHERE "FormerDirectChat"."chat_id" = "Chat"."id")
+WHERE "id" IN (
+    SELECT "Former"."chat_id"
+    FROM "FormerDirectChat" AS "Former"
+    WHERE NOT EXISTS (SELECT 1 FROM "Chat" AS "Keyed" WHERE "Keyed"."direct_key" = "Former"."direct_key")
+      AND "Former"."chat_id" = (
+        SELECT "Same"."chat_id"
+        FROM "FormerDirectChat" AS "Same"
+        JOIN "Chat" AS "SameChat" ON "SameChat"."id" = "Same"."chat_id"
+        WHERE "Same"."direct_key" = "Former"."direct_key"
+        ORDER BY "SameChat"."last_updated" DESC, "SameChat"."id"
+        LIMIT 1
+      )
+);
+
+DROP TABLE "FormerDirectChat";
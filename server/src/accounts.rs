@@ -0,0 +1,10 @@
+//! Account rules shared by the server and `taco-admin`
+
+pub(crate) const MIN_USERNAME_LENGTH: usize = 3;
+pub(crate) const MAX_USERNAME_LENGTH: usize = 20;
+pub(crate) const MIN_PASSWORD_LENGTH: usize = 4;
+
+/// How passwords and tokens are stored
+pub(crate) fn hash<T: AsRef<str>>(s: T) -> String {
+    sha256::digest(s.as_ref())
+}
@@ -0,0 +1,407 @@
+use std::{collections::HashSet, sync::RwLock};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use prisma_client_rust::Direction;
+use structs::requests::{
+    AdminUser, CreateReport, DeleteMessage, Report, ResolveReport, ServerStats, SetUserDisabled,
+    WsMessageData,
+};
+
+use crate::{
+    auth::DELETED_USER_ID,
+    prisma::{
+        self, chat, message,
+        read_filters::{BoolFilter, DateTimeFilter, StringFilter},
+        report, session, user,
+    },
+    user::delete_for_everyone,
+    AppState, Session, WsMessage,
+};
+
+const MAX_REASON_LENGTH: usize = 500;
+
+/// Mirror of `User.disabled`, checked for every request so a ban doesn't wait for access tokens
+/// to expire
+pub(crate) struct DisabledUsers(RwLock<HashSet<String>>);
+
+impl DisabledUsers {
+    pub(crate) async fn load(client: &prisma::PrismaClient) -> Self {
+        let users = client
+            .user()
+            .find_many(vec![user::WhereParam::Disabled(BoolFilter::Equals(true))])
+            .select(user::select!({ id }))
+            .exec()
+            .await
+            .unwrap();
+        Self(RwLock::new(users.into_iter().map(|user| user.id).collect()))
+    }
+
+    pub(crate) fn contains(&self, user_id: &str) -> bool {
+        self.0.read().unwrap().contains(user_id)
+    }
+
+    fn set(&self, user_id: &str, disabled: bool) {
+        let mut users = self.0.write().unwrap();
+        if disabled {
+            users.insert(user_id.to_owned());
+        } else {
+            users.remove(user_id);
+        }
+    }
+}
+
+/// Only extracted from requests of admins, everyone else is turned away
+pub(crate) struct Admin;
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(req, state).await?;
+        let is_admin = state
+            .client
+            .user()
+            .find_unique(user::UniqueWhereParam::IdEquals(session.user_id.clone()))
+            .select(user::select!({ is_admin }))
+            .exec()
+            .await
+            .unwrap()
+            .is_some_and(|user| user.is_admin);
+        if !is_admin {
+            return Err((StatusCode::FORBIDDEN, "Недостаточно прав"));
+        }
+        Ok(Self)
+    }
+}
+
+/// `server <command>`, for what can't be done over the API. Returns what to print
+pub(crate) async fn run_command(
+    client: &prisma::PrismaClient,
+    args: &[String],
+) -> Result<String, String> {
+    const USAGE: &str = "Usage: server grant-admin <username> | revoke-admin <username>";
+
+    let (is_admin, username) = match args {
+        [command, username] if command == "grant-admin" => (true, username),
+        [command, username] if command == "revoke-admin" => (false, username),
+        _ => return Err(USAGE.into()),
+    };
+    let updated = client
+        .user()
+        .update_many(
+            vec![user::WhereParam::Username(StringFilter::Equals(
+                username.clone(),
+            ))],
+            vec![user::SetParam::SetIsAdmin(is_admin)],
+        )
+        .exec()
+        .await
+        .map_err(|err| err.to_string())?;
+    match (updated, is_admin) {
+        (0, _) => Err(format!("No user named {username}")),
+        (_, true) => Ok(format!("{username} is an admin now")),
+        (_, false) => Ok(format!("{username} is no longer an admin")),
+    }
+}
+
+async fn report(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<CreateReport>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Укажите причину жалобы!"));
+    }
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Причина слишком длинная!"));
+    }
+    if request.user_id == session.user_id || request.user_id == DELETED_USER_ID {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "На этого пользователя нельзя пожаловаться!",
+        ));
+    }
+
+    // Only messages from a chat the reporter is in
+    let message_content = match &request.message_id {
+        Some(message_id) => Some(
+            client
+                .message()
+                .find_first(vec![
+                    message::WhereParam::Id(StringFilter::Equals(message_id.clone())),
+                    message::WhereParam::UserId(StringFilter::Equals(request.user_id.clone())),
+                    message::WhereParam::ChatIs(vec![chat::WhereParam::MembersSome(vec![
+                        user::WhereParam::Id(StringFilter::Equals(session.user_id.clone())),
+                    ])]),
+                ])
+                .select(message::select!({ content }))
+                .exec()
+                .await
+                .unwrap()
+                .ok_or((StatusCode::NOT_FOUND, "Сообщение не найдено!"))?
+                .content,
+        ),
+        None => None,
+    };
+
+    client
+        .report()
+        .create(
+            user::UniqueWhereParam::IdEquals(session.user_id),
+            user::UniqueWhereParam::IdEquals(request.user_id),
+            reason.to_owned(),
+            vec![
+                report::SetParam::SetMessageId(request.message_id),
+                report::SetParam::SetMessageContent(message_content),
+            ],
+        )
+        .exec()
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Пользователь не найден"))?;
+    Ok(Json(()))
+}
+
+async fn list_users(
+    State(AppState { client, .. }): State<AppState>,
+    _: Admin,
+) -> Json<Vec<AdminUser>> {
+    let users = client
+        .user()
+        .find_many(vec![user::WhereParam::Not(vec![user::WhereParam::Id(
+            StringFilter::Equals(DELETED_USER_ID.into()),
+        )])])
+        .order_by(user::username::order(Direction::Asc))
+        .select(user::select!({ id username display_name is_admin disabled online }))
+        .exec()
+        .await
+        .unwrap();
+    Json(
+        users
+            .into_iter()
+            .map(|user| AdminUser {
+                id: user.id,
+                username: user.username,
+                display_name: user.display_name,
+                is_admin: user.is_admin,
+                disabled: user.disabled,
+                online: user.online,
+            })
+            .collect(),
+    )
+}
+
+/// Disabling ends every session of the user, closes their sockets and drops the messages they
+/// scheduled
+async fn set_disabled(
+    State(AppState {
+        client,
+        message_sender,
+        disabled_users,
+        ..
+    }): State<AppState>,
+    _: Admin,
+    Json(request): Json<SetUserDisabled>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(request.user_id))
+        .select(user::select!({ id is_admin }))
+        .exec()
+        .await
+        .unwrap()
+        .filter(|user| user.id != DELETED_USER_ID)
+        .ok_or((StatusCode::NOT_FOUND, "Пользователь не найден"))?;
+    if request.disabled && user.is_admin {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Администратора нельзя заблокировать!",
+        ));
+    }
+
+    if !request.disabled {
+        client
+            .user()
+            .update(
+                user::UniqueWhereParam::IdEquals(user.id.clone()),
+                vec![user::SetParam::SetDisabled(false)],
+            )
+            .exec()
+            .await
+            .unwrap();
+        disabled_users.set(&user.id, false);
+        return Ok(Json(()));
+    }
+
+    disabled_users.set(&user.id, true);
+    client
+        ._batch((
+            client.user().update(
+                user::UniqueWhereParam::IdEquals(user.id.clone()),
+                vec![user::SetParam::SetDisabled(true)],
+            ),
+            client
+                .session()
+                .delete_many(vec![session::WhereParam::UserId(StringFilter::Equals(
+                    user.id.clone(),
+                ))]),
+            client
+                .pending_login()
+                .delete_many(vec![prisma::pending_login::WhereParam::UserId(
+                    StringFilter::Equals(user.id.clone()),
+                )]),
+            client.scheduled_message().delete_many(vec![
+                prisma::scheduled_message::WhereParam::UserId(StringFilter::Equals(
+                    user.id.clone(),
+                )),
+            ]),
+        ))
+        .await
+        .unwrap();
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from([user.id]),
+            data: WsMessageData::AccountDisabled,
+        })
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn delete_message(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    _: Admin,
+    Json(message): Json<DeleteMessage>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    delete_for_everyone(&client, &message_sender, message.id).await?;
+    Ok(Json(()))
+}
+
+/// Newest first
+async fn list_reports(
+    State(AppState { client, .. }): State<AppState>,
+    _: Admin,
+) -> Json<Vec<Report>> {
+    let reports = client
+        .report()
+        .find_many(vec![])
+        .order_by(report::created_at::order(Direction::Desc))
+        .include(report::include!({
+            reporter: select { username }
+            reported: select { username }
+        }))
+        .exec()
+        .await
+        .unwrap();
+    Json(
+        reports
+            .into_iter()
+            .map(|report| Report {
+                id: report.id,
+                reporter_id: report.reporter_id,
+                reporter_username: report.reporter.username,
+                reported_id: report.reported_id,
+                reported_username: report.reported.username,
+                message_id: report.message_id,
+                message_content: report.message_content,
+                reason: report.reason,
+                resolved: report.resolved,
+                created_at: report.created_at.into(),
+            })
+            .collect(),
+    )
+}
+
+async fn resolve_report(
+    State(AppState { client, .. }): State<AppState>,
+    _: Admin,
+    Json(request): Json<ResolveReport>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    client
+        .report()
+        .update(
+            report::UniqueWhereParam::IdEquals(request.id),
+            vec![report::SetParam::SetResolved(true)],
+        )
+        .exec()
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Жалоба не найдена"))?;
+    Ok(Json(()))
+}
+
+async fn stats(
+    State(AppState {
+        client,
+        connections,
+        ..
+    }): State<AppState>,
+    _: Admin,
+) -> Json<ServerStats> {
+    let online_users = connections.lock().unwrap().len() as u64;
+    let users = client
+        .user()
+        .count(vec![user::WhereParam::Not(vec![user::WhereParam::Id(
+            StringFilter::Equals(DELETED_USER_ID.into()),
+        )])])
+        .exec()
+        .await
+        .unwrap();
+    let disabled_users = client
+        .user()
+        .count(vec![user::WhereParam::Disabled(BoolFilter::Equals(true))])
+        .exec()
+        .await
+        .unwrap();
+    let chats = client.chat().count(vec![]).exec().await.unwrap();
+    let messages = client.message().count(vec![]).exec().await.unwrap();
+    let active_sessions = client
+        .session()
+        .count(vec![session::WhereParam::ExpiresAt(DateTimeFilter::Gte(
+            Utc::now().into(),
+        ))])
+        .exec()
+        .await
+        .unwrap();
+    let open_reports = client
+        .report()
+        .count(vec![report::WhereParam::Resolved(BoolFilter::Equals(
+            false,
+        ))])
+        .exec()
+        .await
+        .unwrap();
+    Json(ServerStats {
+        users: users as u64,
+        disabled_users: disabled_users as u64,
+        online_users,
+        chats: chats as u64,
+        messages: messages as u64,
+        active_sessions: active_sessions as u64,
+        open_reports: open_reports as u64,
+    })
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/report", post(report))
+        .route("/admin/users", get(list_users))
+        .route("/admin/set_disabled", post(set_disabled))
+        .route("/admin/delete_message", post(delete_message))
+        .route("/admin/reports", get(list_reports))
+        .route("/admin/resolve_report", post(resolve_report))
+        .route("/admin/stats", get(stats))
+}
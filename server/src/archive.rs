@@ -0,0 +1,519 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    io::{self, ErrorKind},
+    path::{Path as FilePath, PathBuf},
+};
+
+use axum::{
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use prisma_client_rust::{prisma_errors::query_engine::UniqueKeyViolation, Direction, QueryError};
+use serde::Deserialize;
+use structs::{
+    archive::{referenced_uploads, ChatArchive, Upload, ARCHIVE_VERSION},
+    requests::{ChatMember, MessageKind, WsCreateChat, WsMessageData},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::{
+    accounts::hash,
+    admin::Admin,
+    e2ee, option_vec,
+    prisma::{
+        self, chat, chat_key, link_preview, message, read_filters::StringFilter, user, voice_note,
+    },
+    user::chat_messages,
+    AppState, Session, WsMessage,
+};
+
+/// Archives carry their uploads, voice messages make them far larger than other requests
+const IMPORT_SIZE_LIMIT: usize = 512 * 1024 * 1024;
+
+/// Imports write every message of the chat in one transaction
+const IMPORT_TIMEOUT_MS: u64 = 60_000;
+
+const CORRUPTED: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "Повреждённый архив!");
+const UPLOADS_NOT_SAVED: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "Не удалось сохранить файлы из архива!",
+);
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    /// Encrypted messages can't be shown, clients render their own HTML after decrypting
+    Html,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Encrypted messages are exported as the server has them, the client decrypts them
+async fn export_chat(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Path(chat_id): Path<String>,
+    Query(ExportQuery { format }): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let chat = client
+        .chat()
+        .find_first(vec![
+            chat::WhereParam::Id(StringFilter::Equals(chat_id)),
+            chat::WhereParam::MembersSome(vec![user::WhereParam::Id(StringFilter::Equals(
+                session.user_id,
+            ))]),
+        ])
+        .select(chat::select!({
+            id
+            direct_key
+            message_ttl
+            members: select {
+                id
+            }
+            keys(vec![]).order_by(chat_key::created_at::order(Direction::Asc))
+        }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден"))?;
+    let messages = chat_messages(&client, chat.id.clone()).await;
+
+    let member_ids: Vec<String> = chat.members.into_iter().map(|member| member.id).collect();
+    let mut user_ids: HashSet<String> = member_ids.iter().cloned().collect();
+    for message in &messages {
+        user_ids.insert(message.sender_id.clone());
+        for reaction in &message.reactions {
+            user_ids.extend(reaction.user_ids.iter().cloned());
+        }
+    }
+    let users = client
+        .user()
+        .find_many(vec![user::WhereParam::Id(StringFilter::InVec(
+            user_ids.into_iter().collect(),
+        ))])
+        .select(user::select!({ id username display_name }))
+        .exec()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| ChatMember {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+        })
+        .collect();
+
+    // Files that went missing from `content` are left out
+    let names: BTreeSet<String> = messages.iter().flat_map(referenced_uploads).collect();
+    let mut uploads = Vec::new();
+    for name in names {
+        if let Ok(bytes) = tokio::fs::read(FilePath::new("content").join(&name)).await {
+            uploads.push(Upload::new(name, &bytes));
+        }
+    }
+
+    let archive = ChatArchive {
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now(),
+        chat_id: chat.id,
+        direct_key: chat.direct_key,
+        message_ttl: chat.message_ttl.map(|ttl| ttl as u32),
+        member_ids,
+        users,
+        chat_keys: chat.keys.into_iter().map(e2ee::to_chat_key).collect(),
+        messages,
+        uploads,
+    };
+    Ok(match format {
+        ExportFormat::Json => Json(archive).into_response(),
+        ExportFormat::Html => Html(archive.to_html()).into_response(),
+    })
+}
+
+/// Checks that don't need the database
+fn validate_archive(archive: &ChatArchive) -> Result<(), (StatusCode, &'static str)> {
+    if archive.version > ARCHIVE_VERSION {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Архив создан более новой версией сервера!",
+        ));
+    }
+    if !archive.is_consistent() {
+        return Err(CORRUPTED);
+    }
+    Ok(())
+}
+
+/// Writes the uploads the messages refer to under temporary names, returns those along with the
+/// paths the uploads belong at. Nothing is left behind if one can't be written
+async fn stage_uploads(archive: &ChatArchive) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let referenced: HashSet<String> = archive
+        .messages
+        .iter()
+        .flat_map(referenced_uploads)
+        .collect();
+    let mut staged = Vec::new();
+    for upload in &archive.uploads {
+        let (true, Some(bytes)) = (referenced.contains(&upload.name), upload.bytes()) else {
+            continue;
+        };
+        // Upload names never start with a dot, so these can't collide with one
+        let temporary = FilePath::new("content").join(format!(".import-{}", uuid::Uuid::new_v4()));
+        let written = async {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temporary)
+                .await?;
+            file.write_all(&bytes).await
+        }
+        .await;
+        if let Err(err) = written {
+            let _ = tokio::fs::remove_file(&temporary).await;
+            for (temporary, _) in &staged {
+                let _ = tokio::fs::remove_file(temporary).await;
+            }
+            return Err(err);
+        }
+        staged.push((temporary, FilePath::new("content").join(&upload.name)));
+    }
+    Ok(staged)
+}
+
+/// Runs in a transaction, an import that fails halfway leaves nothing behind
+async fn store_archive(
+    client: &prisma::PrismaClient,
+    archive: &ChatArchive,
+    missing_users: &[&ChatMember],
+) -> Result<(), QueryError> {
+    for user in missing_users {
+        client
+            .user()
+            .create(
+                user.username.clone(),
+                hash(uuid::Uuid::new_v4().to_string()),
+                vec![
+                    user::SetParam::SetId(user.id.clone()),
+                    user::SetParam::SetDisplayName(user.display_name.clone()),
+                ],
+            )
+            .exec()
+            .await?;
+    }
+
+    client
+        .chat()
+        .create(vec![
+            chat::SetParam::SetId(archive.chat_id.clone()),
+            chat::SetParam::SetDirectKey(archive.direct_key.clone()),
+            chat::SetParam::SetMessageTtl(archive.message_ttl.map(|ttl| ttl as i32)),
+            chat::SetParam::ConnectMembers(
+                archive
+                    .member_ids
+                    .iter()
+                    .map(|id| user::UniqueWhereParam::IdEquals(id.clone()))
+                    .collect(),
+            ),
+        ])
+        .exec()
+        .await?;
+    for key in &archive.chat_keys {
+        client
+            .chat_key()
+            .create(
+                chat::UniqueWhereParam::IdEquals(archive.chat_id.clone()),
+                key.initiator_id.clone(),
+                key.initiator_key.clone(),
+                key.responder_key.clone(),
+                key.ephemeral_key.clone(),
+                vec![
+                    chat_key::SetParam::SetId(key.id.clone()),
+                    chat_key::SetParam::SetCreatedAt(key.created_at.into()),
+                ],
+            )
+            .exec()
+            .await?;
+    }
+
+    let mut imported: HashSet<&str> = HashSet::new();
+    for message in &archive.messages {
+        // Forwards share the note of their original
+        let voice_note_id = match &message.kind {
+            MessageKind::Voice(voice_note) => {
+                let existing = client
+                    .voice_note()
+                    .find_unique(voice_note::UniqueWhereParam::IdEquals(
+                        voice_note.id.clone(),
+                    ))
+                    .select(voice_note::select!({ id }))
+                    .exec()
+                    .await?;
+                if existing.is_none() {
+                    client
+                        .voice_note()
+                        .create(
+                            user::UniqueWhereParam::IdEquals(message.sender_id.clone()),
+                            voice_note.duration_ms as i32,
+                            voice_note.waveform.clone(),
+                            vec![voice_note::SetParam::SetId(voice_note.id.clone())],
+                        )
+                        .exec()
+                        .await?;
+                }
+                Some(voice_note.id.clone())
+            }
+            MessageKind::Text => None,
+        };
+        // A preview the server already has is newer than the archived one
+        if let Some(preview) = &message.link_preview {
+            client
+                .link_preview()
+                .upsert(
+                    link_preview::UniqueWhereParam::UrlEquals(preview.url.clone()),
+                    (
+                        preview.url.clone(),
+                        vec![
+                            link_preview::SetParam::SetTitle(preview.title.clone()),
+                            link_preview::SetParam::SetDescription(preview.description.clone()),
+                            link_preview::SetParam::SetImage(preview.image.clone()),
+                        ],
+                    ),
+                    vec![],
+                )
+                .exec()
+                .await?;
+        }
+
+        client
+            .message()
+            .create(
+                chat::UniqueWhereParam::IdEquals(archive.chat_id.clone()),
+                message.message.clone(),
+                user::UniqueWhereParam::IdEquals(message.sender_id.clone()),
+                option_vec![
+                    Some(message::SetParam::SetId(message.message_id.clone())),
+                    Some(message::SetParam::SetCreatedAt(message.created_at.into())),
+                    message
+                        .reply_to
+                        .as_ref()
+                        .filter(|id| imported.contains(id.as_str()))
+                        .map(|id| message::SetParam::ConnectReplyTo(
+                            message::UniqueWhereParam::IdEquals(id.clone())
+                        )),
+                    message
+                        .forwarded_from
+                        .as_ref()
+                        .and_then(|original| original.message_id.as_ref())
+                        .filter(|id| imported.contains(id.as_str()))
+                        .map(|id| message::SetParam::ConnectForwardedFrom(
+                            message::UniqueWhereParam::IdEquals(id.clone())
+                        )),
+                    message.forwarded_from.as_ref().map(|original| {
+                        message::SetParam::SetForwardedSenderId(Some(original.sender_id.clone()))
+                    }),
+                    message.forwarded_from.as_ref().map(|original| {
+                        message::SetParam::SetForwardedSenderName(Some(
+                            original.sender_name.clone(),
+                        ))
+                    }),
+                    message.forwarded_from.as_ref().map(|original| {
+                        message::SetParam::SetForwardedCreatedAt(Some(original.created_at.into()))
+                    }),
+                    message
+                        .link_preview
+                        .as_ref()
+                        .map(|preview| message::SetParam::SetLinkUrl(Some(preview.url.clone()))),
+                    voice_note_id.map(|id| message::SetParam::ConnectVoiceNote(
+                        voice_note::UniqueWhereParam::IdEquals(id)
+                    )),
+                    message
+                        .expires_at
+                        .map(|expires_at| message::SetParam::SetExpiresAt(Some(expires_at.into()))),
+                    message
+                        .key_id
+                        .as_ref()
+                        .map(|id| message::SetParam::ConnectKey(
+                            chat_key::UniqueWhereParam::IdEquals(id.clone())
+                        )),
+                ],
+            )
+            .exec()
+            .await?;
+        for reaction in &message.reactions {
+            for user_id in &reaction.user_ids {
+                client
+                    .reaction()
+                    .create(
+                        message::UniqueWhereParam::IdEquals(message.message_id.clone()),
+                        user::UniqueWhereParam::IdEquals(user_id.clone()),
+                        reaction.emoji.clone(),
+                        vec![],
+                    )
+                    .exec()
+                    .await?;
+            }
+        }
+        imported.insert(message.message_id.as_str());
+    }
+
+    if let Some(last) = archive.messages.last() {
+        client
+            .chat()
+            .update(
+                chat::UniqueWhereParam::IdEquals(archive.chat_id.clone()),
+                vec![chat::SetParam::SetLastUpdated(last.created_at.into())],
+            )
+            .exec()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Restores an exported chat with the ids it had. Users this server doesn't have are created
+/// with a random password, an operator sets a new one with `taco-admin reset-password`.
+/// Replies and forwards keep pointing at their originals only if those are in the archive
+async fn import_chat(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    _: Admin,
+    Json(archive): Json<ChatArchive>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    validate_archive(&archive)?;
+
+    let existing_chat = client
+        .chat()
+        .find_unique(chat::UniqueWhereParam::IdEquals(archive.chat_id.clone()))
+        .select(chat::select!({ id }))
+        .exec()
+        .await
+        .unwrap();
+    if existing_chat.is_some() {
+        return Err((StatusCode::CONFLICT, "Этот чат уже есть на сервере!"));
+    }
+    if let Some(direct_key) = &archive.direct_key {
+        let existing_direct = client
+            .chat()
+            .find_unique(chat::UniqueWhereParam::DirectKeyEquals(direct_key.clone()))
+            .select(chat::select!({ id }))
+            .exec()
+            .await
+            .unwrap();
+        if existing_direct.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                "Личный чат этих пользователей уже есть на сервере!",
+            ));
+        }
+    }
+
+    let existing_ids: HashSet<String> = client
+        .user()
+        .find_many(vec![user::WhereParam::Id(StringFilter::InVec(
+            archive.users.iter().map(|user| user.id.clone()).collect(),
+        ))])
+        .select(user::select!({ id }))
+        .exec()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.id)
+        .collect();
+    let missing_users: Vec<&ChatMember> = archive
+        .users
+        .iter()
+        .filter(|user| !existing_ids.contains(&user.id))
+        .collect();
+    let taken_usernames = client
+        .user()
+        .count(vec![user::WhereParam::Username(StringFilter::InVec(
+            missing_users
+                .iter()
+                .map(|user| user.username.clone())
+                .collect(),
+        ))])
+        .exec()
+        .await
+        .unwrap();
+    if taken_usernames > 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "Имя пользователя из архива уже занято другим аккаунтом!",
+        ));
+    }
+
+    let staged = stage_uploads(&archive)
+        .await
+        .map_err(|_| UPLOADS_NOT_SAVED)?;
+    let stored = client
+        ._transaction()
+        .with_timeout(IMPORT_TIMEOUT_MS)
+        .run(|client| {
+            let archive = &archive;
+            async move { store_archive(&client, archive, &missing_users).await }
+        })
+        .await;
+    if let Err(err) = stored {
+        for (temporary, _) in &staged {
+            let _ = tokio::fs::remove_file(temporary).await;
+        }
+        return Err(match err {
+            err if err.is_prisma_error::<UniqueKeyViolation>() => (
+                StatusCode::CONFLICT,
+                "Данные из архива уже есть на сервере!",
+            ),
+            _ => CORRUPTED,
+        });
+    }
+
+    // Linking never replaces a file, the ones the server already has are kept
+    let mut linked = true;
+    for (temporary, path) in &staged {
+        match tokio::fs::hard_link(temporary, path).await {
+            Err(err) if err.kind() != ErrorKind::AlreadyExists => linked = false,
+            _ => {}
+        }
+        let _ = tokio::fs::remove_file(temporary).await;
+    }
+    if !linked {
+        return Err(UPLOADS_NOT_SAVED);
+    }
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: archive.member_ids.iter().cloned().collect(),
+            data: WsMessageData::CreateChat(WsCreateChat {
+                chat_id: archive.chat_id.clone(),
+                members: archive
+                    .users
+                    .iter()
+                    .filter(|user| archive.member_ids.contains(&user.id))
+                    .cloned()
+                    .collect(),
+            }),
+        })
+        .unwrap();
+    Ok(Json(()))
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/chats/:id/export", get(export_chat))
+        .route(
+            "/admin/import_chat",
+            post(import_chat).layer(DefaultBodyLimit::max(IMPORT_SIZE_LIMIT)),
+        )
+}
@@ -1,109 +1,182 @@
-use std::sync::Arc;
-
 use axum::{
     async_trait,
     extract::{FromRequestParts, State},
     http::{request::Parts, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
     routing::post,
     Json, Router,
 };
 use prisma_client_rust::prisma_errors::query_engine::UniqueKeyViolation;
-use serde::Serialize;
 use structs::{
-    requests::LoginInfo,
+    requests::{
+        ChangePassword, ChangeUsername, DeleteAccount, LoginInfo, LoginResult, RefreshRequest,
+        Session as LoggedIn, Tokens,
+    },
     {DateTime, Duration, FixedOffset, Utc},
 };
 
 use crate::{
-    prisma::{self, read_filters::StringFilter, session, user},
-    AppState,
+    accounts::{hash, MAX_USERNAME_LENGTH, MIN_PASSWORD_LENGTH, MIN_USERNAME_LENGTH},
+    prisma::{
+        self, message,
+        read_filters::{StringFilter, StringNullableFilter},
+        report, session, user, voice_note,
+    },
+    rate_limit::{self, too_many_requests},
+    tokens::TokenSigner,
+    two_factor, AppState,
 };
 
-#[derive(Clone, Serialize)]
+/// Taken from the access token of the request
+#[derive(Clone)]
 pub(crate) struct Session {
     pub(crate) session_id: String,
     pub(crate) user_id: String,
 }
 
-fn hash<T: AsRef<str>>(s: T) -> String {
-    sha256::digest(s.as_ref())
+const ACCOUNT_DISABLED: (StatusCode, &str) = (StatusCode::FORBIDDEN, "Аккаунт заблокирован");
+
+/// Messages, voice notes and reports of deleted accounts are reassigned to this user, created by
+/// the `add_username` migration
+pub(crate) const DELETED_USER_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+fn validate_username(username: &str) -> Result<(), (StatusCode, String)> {
+    if username.len() > MAX_USERNAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Имя пользователя слишком длинное!".into(),
+        ));
+    }
+    if username.len() < MIN_USERNAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Имя пользователя слишком короткое!".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), (StatusCode, String)> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Пароль слишком короткий!".into()));
+    }
+    Ok(())
+}
+
+fn map_username_error(err: prisma_client_rust::QueryError) -> (StatusCode, String) {
+    match err {
+        err if err.is_prisma_error::<UniqueKeyViolation>() => {
+            (StatusCode::CONFLICT, "Имя пользователя уже занято!".into())
+        }
+        err => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Prisma error: {err}"),
+        ),
+    }
+}
+
+/// Returns the user id if the password matches
+pub(crate) async fn check_password(
+    client: &prisma::PrismaClient,
+    user_id: String,
+    password: String,
+) -> Result<String, (StatusCode, String)> {
+    client
+        .user()
+        .find_first(vec![
+            user::WhereParam::Id(StringFilter::Equals(user_id)),
+            user::WhereParam::Password(StringFilter::Equals(hash(password))),
+        ])
+        .select(user::select!({ id }))
+        .exec()
+        .await
+        .unwrap()
+        .map(|user| user.id)
+        .ok_or((StatusCode::FORBIDDEN, "Неверный пароль!".into()))
 }
 
+const SESSION_DURATION_DAYS: i64 = 10;
+
 fn get_session_expiry() -> DateTime<FixedOffset> {
-    const SESSION_DURATION_DAYS: i64 = 10;
     (Utc::now() + Duration::days(SESSION_DURATION_DAYS)).into()
 }
 
-/// Returns session id
-async fn create_session(client: Arc<prisma::PrismaClient>, user_id: String) -> String {
+fn new_refresh_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Returns the session id and the first tokens of the session
+pub(crate) async fn create_session(
+    client: &prisma::PrismaClient,
+    tokens: &TokenSigner,
+    user_id: String,
+) -> (String, Tokens) {
+    let refresh_token = new_refresh_token();
     let session = client
         .session()
         .create(
+            hash(&refresh_token),
             get_session_expiry(),
-            user::UniqueWhereParam::IdEquals(user_id),
+            user::UniqueWhereParam::IdEquals(user_id.clone()),
             vec![],
         )
         .exec()
         .await
         .unwrap();
 
-    session.id
+    let (access_token, access_expires_at) = tokens.sign(&session.id, &user_id);
+    (
+        session.id,
+        Tokens {
+            access_token,
+            access_expires_at,
+            refresh_token,
+        },
+    )
 }
 
 async fn register(
-    State(AppState { client, .. }): State<AppState>,
+    State(AppState { client, tokens, .. }): State<AppState>,
     Json(info): Json<LoginInfo>,
-) -> Result<Json<Session>, (StatusCode, String)> {
-    const MAX_USERNAME_LENGTH: usize = 20;
-    const MIN_USERNAME_LENGTH: usize = 3;
-    const MIN_PASSWORD_LENGTH: usize = 4;
+) -> Result<Json<LoggedIn>, (StatusCode, String)> {
+    validate_username(&info.username)?;
+    validate_password(&info.password)?;
 
-    if info.username.len() > MAX_USERNAME_LENGTH {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Имя пользователя слишком длинное!".into(),
-        ));
-    }
-    if info.username.len() < MIN_USERNAME_LENGTH {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Имя пользователя слишком короткое!".into(),
-        ));
-    }
-    if info.password.len() < MIN_PASSWORD_LENGTH {
-        return Err((StatusCode::BAD_REQUEST, "Пароль слишком короткий!".into()));
-    }
-
-    client
+    let user = client
         .user()
-        .create(info.username.clone(), hash(info.password), vec![])
+        .create(info.username, hash(info.password), vec![])
         .exec()
         .await
-        .map_err(|err| match err {
-            err if err.is_prisma_error::<UniqueKeyViolation>() => {
-                (StatusCode::CONFLICT, "Имя пользователя уже занято!".into())
-            }
-            err => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Prisma error: {err}"),
-            ),
-        })?;
+        .map_err(map_username_error)?;
 
-    let session_id = create_session(client, info.username.clone()).await;
-    Ok(Json(Session {
-        user_id: info.username,
+    let (session_id, tokens) = create_session(&client, &tokens, user.id.clone()).await;
+    Ok(Json(LoggedIn {
+        user_id: user.id,
+        username: user.username,
         session_id,
+        tokens,
     }))
 }
 
 async fn log_in(
-    State(AppState { client, .. }): State<AppState>,
+    State(AppState {
+        client,
+        rate_limits,
+        tokens,
+        ..
+    }): State<AppState>,
     Json(info): Json<LoginInfo>,
-) -> Result<Json<Session>, (StatusCode, &'static str)> {
+) -> Result<Json<LoginResult>, Response> {
+    rate_limits
+        .login_lockouts
+        .check(&info.username)
+        .map_err(too_many_requests)?;
+
     let user = client
         .user()
         .find_first(vec![
-            user::WhereParam::Id(StringFilter::Equals(info.username)),
+            user::WhereParam::Username(StringFilter::Equals(info.username.clone())),
             user::WhereParam::Password(StringFilter::Equals(hash(info.password))),
         ])
         .exec()
@@ -111,54 +184,110 @@ async fn log_in(
         .unwrap();
 
     if let Some(user) = user {
-        let session_id = create_session(client, user.id.clone()).await;
-        Ok(Json(Session {
+        if user.disabled {
+            return Err(ACCOUNT_DISABLED.into_response());
+        }
+        if user.totp_secret.is_some() {
+            // The lockout is only reset once the code is right as well
+            let login_token = two_factor::start_login(&client, user.id).await;
+            return Ok(Json(LoginResult::TwoFactorRequired { login_token }));
+        }
+        rate_limits.login_lockouts.record_success(&info.username);
+        let (session_id, tokens) = create_session(&client, &tokens, user.id.clone()).await;
+        Ok(Json(LoginResult::LoggedIn(LoggedIn {
             user_id: user.id,
+            username: user.username,
             session_id,
-        }))
+            tokens,
+        })))
     } else {
+        rate_limits.login_lockouts.record_failure(&info.username);
         Err((
             StatusCode::NOT_FOUND,
             "Неверное имя пользователя или пароль!",
-        ))
+        )
+            .into_response())
     }
 }
 
-/// Returns None if the session was not found or is expired, otherwise renews the session and returns the user id
-async fn check_session(client: Arc<prisma::PrismaClient>, session_id: String) -> Option<String> {
-    let session = client
+/// Exchanges a refresh token for new tokens, the session is extended every time
+async fn refresh(
+    State(AppState {
+        client,
+        tokens,
+        session_metrics,
+        ..
+    }): State<AppState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<Tokens>, (StatusCode, &'static str)> {
+    const INVALID: (StatusCode, &str) = (StatusCode::UNAUTHORIZED, "Сессия истекла");
+
+    let token_hash = hash(&request.refresh_token);
+    let Some(session) = client
         .session()
-        .find_unique(session::UniqueWhereParam::IdEquals(session_id.clone()))
+        .find_unique(session::UniqueWhereParam::RefreshTokenEquals(
+            token_hash.clone(),
+        ))
         .exec()
         .await
-        .unwrap();
+        .unwrap()
+    else {
+        // A replaced token is only presented again by whoever copied it, the session is ended
+        // for both of them
+        client
+            .session()
+            .delete_many(vec![session::WhereParam::PreviousRefreshToken(
+                StringNullableFilter::Equals(Some(token_hash)),
+            )])
+            .exec()
+            .await
+            .unwrap();
+        return Err(INVALID);
+    };
 
-    if let Some(session) = session {
-        if session.expires_at < Utc::now() {
-            client
-                .session()
-                .delete(session::UniqueWhereParam::IdEquals(session_id))
-                .exec()
-                .await
-                .unwrap();
-
-            None
-        } else {
-            client
-                .session()
-                .update(
-                    session::UniqueWhereParam::IdEquals(session_id),
-                    vec![session::SetParam::SetExpiresAt(get_session_expiry())],
-                )
-                .exec()
-                .await
-                .unwrap();
+    if session.expires_at < Utc::now() {
+        // The cleanup job may have deleted it in the meantime
+        let purged = client
+            .session()
+            .delete_many(vec![session::WhereParam::Id(StringFilter::Equals(
+                session.id,
+            ))])
+            .exec()
+            .await
+            .unwrap();
+        session_metrics.record_purged(purged as u64);
+        return Err(INVALID);
+    }
 
-            Some(session.user_id)
-        }
-    } else {
-        None
+    let refresh_token = new_refresh_token();
+    // Matching the old token too, so two refreshes with it can't both succeed
+    let rotated = client
+        .session()
+        .update_many(
+            vec![
+                session::WhereParam::Id(StringFilter::Equals(session.id.clone())),
+                session::WhereParam::RefreshToken(StringFilter::Equals(token_hash.clone())),
+            ],
+            vec![
+                session::SetParam::SetRefreshToken(hash(&refresh_token)),
+                session::SetParam::SetPreviousRefreshToken(Some(token_hash)),
+                session::SetParam::SetExpiresAt(get_session_expiry()),
+            ],
+        )
+        .exec()
+        .await
+        .unwrap();
+    if rotated == 0 {
+        return Err(INVALID);
     }
+    session_metrics.record_renewed();
+
+    let (access_token, access_expires_at) = tokens.sign(&session.id, &session.user_id);
+    Ok(Json(Tokens {
+        access_token,
+        access_expires_at,
+        refresh_token,
+    }))
 }
 
 #[async_trait]
@@ -167,7 +296,11 @@ impl FromRequestParts<AppState> for Session {
 
     async fn from_request_parts(
         req: &mut Parts,
-        AppState { client, .. }: &AppState,
+        AppState {
+            tokens,
+            disabled_users,
+            ..
+        }: &AppState,
     ) -> Result<Self, Self::Rejection> {
         let authorization = req
             .headers
@@ -182,14 +315,19 @@ impl FromRequestParts<AppState> for Session {
             })?;
 
         match authorization.split_once(' ') {
-            Some((name, session_id)) if name == "Bearer" => {
-                check_session(client.clone(), session_id.into())
-                    .await
-                    .ok_or((StatusCode::BAD_REQUEST, "Некорректная сессия"))
-                    .map(|user_id| Session {
-                        session_id: session_id.into(),
-                        user_id,
-                    })
+            // Clients refresh their tokens and retry on 401
+            Some((name, token)) if name == "Bearer" => {
+                let claims = tokens
+                    .verify(token)
+                    .ok_or((StatusCode::UNAUTHORIZED, "Сессия истекла"))?;
+                // Tokens issued before the ban are still signed
+                if disabled_users.contains(&claims.user_id) {
+                    return Err(ACCOUNT_DISABLED);
+                }
+                Ok(Session {
+                    session_id: claims.session_id,
+                    user_id: claims.user_id,
+                })
             }
             _ => Err((
                 StatusCode::BAD_REQUEST,
@@ -202,16 +340,125 @@ impl FromRequestParts<AppState> for Session {
 async fn log_out(State(AppState { client, .. }): State<AppState>, s: Session) -> Json<()> {
     client
         .session()
-        .delete(prisma::session::UniqueWhereParam::IdEquals(s.session_id))
+        .delete_many(vec![session::WhereParam::Id(StringFilter::Equals(
+            s.session_id,
+        ))])
         .exec()
         .await
         .unwrap();
     Json(())
 }
 
-pub(crate) fn router() -> Router<AppState> {
+/// Signs out every other device of the user
+async fn change_password(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(change): Json<ChangePassword>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let user_id = check_password(&client, session.user_id, change.old_password).await?;
+    validate_password(&change.new_password)?;
+
+    client
+        ._batch((
+            client.user().update(
+                user::UniqueWhereParam::IdEquals(user_id.clone()),
+                vec![user::SetParam::SetPassword(hash(change.new_password))],
+            ),
+            client.session().delete_many(vec![
+                session::WhereParam::UserId(StringFilter::Equals(user_id)),
+                session::WhereParam::Not(vec![session::WhereParam::Id(StringFilter::Equals(
+                    session.session_id,
+                ))]),
+            ]),
+        ))
+        .await
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn change_username(
+    State(AppState {
+        client,
+        message_sender,
+        blocks,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(change): Json<ChangeUsername>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    validate_username(&change.username)?;
+
+    client
+        .user()
+        .update(
+            user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+            vec![user::SetParam::SetUsername(change.username)],
+        )
+        .exec()
+        .await
+        .map_err(map_username_error)?;
+
+    crate::user::broadcast_profile(&client, &message_sender, &blocks, session.user_id).await;
+    Ok(Json(()))
+}
+
+/// Messages can't outlive their sender, so they are handed over to the deleted user placeholder
+/// along with the account's voice notes and reports
+async fn delete_account(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(delete): Json<DeleteAccount>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let user_id = check_password(&client, session.user_id, delete.password).await?;
+
+    client
+        ._batch((
+            client.message().update_many(
+                vec![message::WhereParam::UserId(StringFilter::Equals(
+                    user_id.clone(),
+                ))],
+                vec![message::SetParam::SetUserId(DELETED_USER_ID.into())],
+            ),
+            // Voice messages stay in the chats of others like the rest of the messages
+            client.voice_note().update_many(
+                vec![voice_note::WhereParam::UploaderId(StringFilter::Equals(
+                    user_id.clone(),
+                ))],
+                vec![voice_note::SetParam::SetUploaderId(DELETED_USER_ID.into())],
+            ),
+            // Admins still get to see what was reported, deleting the account doesn't hide it
+            client.report().update_many(
+                vec![report::WhereParam::ReportedId(StringFilter::Equals(
+                    user_id.clone(),
+                ))],
+                vec![report::SetParam::SetReportedId(DELETED_USER_ID.into())],
+            ),
+            client.report().update_many(
+                vec![report::WhereParam::ReporterId(StringFilter::Equals(
+                    user_id.clone(),
+                ))],
+                vec![report::SetParam::SetReporterId(DELETED_USER_ID.into())],
+            ),
+            client
+                .user()
+                .delete(user::UniqueWhereParam::IdEquals(user_id)),
+        ))
+        .await
+        .unwrap();
+    Ok(Json(()))
+}
+
+pub(crate) fn router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/login", post(log_in))
-        .route("/logout", post(log_out))
         .route("/register", post(register))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            rate_limit::limit_auth_by_ip,
+        ))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(log_out))
+        .route("/change_password", post(change_password))
+        .route("/change_username", post(change_username))
+        .route("/delete_account", post(delete_account))
 }
@@ -0,0 +1,452 @@
+//! Maintenance of the server database, run it from the directory the server runs in. Changes
+//! aren't pushed to connected clients, they see them after reloading
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::{Parser, Subcommand};
+use prisma_client_rust::{
+    prisma_errors::query_engine::UniqueKeyViolation, raw, Direction, QueryError,
+};
+use serde::Serialize;
+
+#[path = "../accounts.rs"]
+mod accounts;
+#[allow(warnings, unused)]
+#[path = "../prisma.rs"]
+mod prisma;
+
+use accounts::{hash, MAX_USERNAME_LENGTH, MIN_PASSWORD_LENGTH, MIN_USERNAME_LENGTH};
+use prisma::{
+    block, chat, contact, message, pending_login, read_filters::StringFilter, session, user,
+    voice_note,
+};
+
+#[derive(Parser)]
+#[command(name = "taco-admin", about = "Maintenance of the Taco server database")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Creates an account, a password is generated unless one is given
+    CreateUser {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Sets a new password and signs the user out everywhere
+    ResetPassword {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Lists the sessions of a user
+    Sessions { username: String },
+    /// Ends one session, its client is signed out once its access token expires
+    RevokeSession { session_id: String },
+    /// Ends every session of a user
+    RevokeSessions { username: String },
+    /// Deletes a chat with all of its messages and the voice notes only sent there
+    DeleteChat { chat_id: String },
+    /// Writes everything stored about a user as JSON, to stdout unless a file is given
+    ExportUser {
+        username: String,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Applies the migrations the database doesn't have yet
+    Migrate,
+    /// Rebuilds the database file to reclaim the space of deleted rows
+    Vacuum,
+}
+
+#[derive(Serialize)]
+struct UserExport {
+    id: String,
+    username: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    status_text: Option<String>,
+    profile_picture: Option<String>,
+    who_can_start_chats: String,
+    who_can_see_status: String,
+    two_factor_enabled: bool,
+    is_admin: bool,
+    disabled: bool,
+    contacts: Vec<ContactExport>,
+    blocked_user_ids: Vec<String>,
+    chats: Vec<ChatExport>,
+    sessions: Vec<SessionExport>,
+}
+
+#[derive(Serialize)]
+struct ContactExport {
+    user_id: String,
+    accepted: bool,
+    created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize)]
+struct ChatExport {
+    id: String,
+    member_ids: Vec<String>,
+    /// Only the ones the user sent
+    messages: Vec<MessageExport>,
+}
+
+#[derive(Serialize)]
+struct MessageExport {
+    id: String,
+    /// Ciphertext if `encrypted`
+    content: String,
+    encrypted: bool,
+    reply_id: Option<String>,
+    voice_note_id: Option<String>,
+    created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize)]
+struct SessionExport {
+    id: String,
+    expires_at: DateTime<FixedOffset>,
+}
+
+fn query_error(err: QueryError) -> String {
+    format!("Database error: {err}")
+}
+
+/// The given password or a random one, and whether it was generated
+fn choose_password(password: Option<String>) -> Result<(String, bool), String> {
+    match password {
+        Some(password) if password.len() < MIN_PASSWORD_LENGTH => Err(format!(
+            "Passwords are at least {MIN_PASSWORD_LENGTH} characters long"
+        )),
+        Some(password) => Ok((password, false)),
+        None => Ok((
+            uuid::Uuid::new_v4().simple().to_string()[..16].to_owned(),
+            true,
+        )),
+    }
+}
+
+async fn find_user(client: &prisma::PrismaClient, username: &str) -> Result<user::Data, String> {
+    client
+        .user()
+        .find_unique(user::UniqueWhereParam::UsernameEquals(username.to_owned()))
+        .exec()
+        .await
+        .map_err(query_error)?
+        .ok_or_else(|| format!("No user named {username}"))
+}
+
+async fn create_user(
+    client: &prisma::PrismaClient,
+    username: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len()) {
+        return Err(format!(
+            "Usernames are {MIN_USERNAME_LENGTH} to {MAX_USERNAME_LENGTH} characters long"
+        ));
+    }
+    let (password, generated) = choose_password(password)?;
+    let user = client
+        .user()
+        .create(username, hash(&password), vec![])
+        .exec()
+        .await
+        .map_err(|err| match err {
+            err if err.is_prisma_error::<UniqueKeyViolation>() => {
+                "The username is taken".to_owned()
+            }
+            err => query_error(err),
+        })?;
+    println!("Created {} ({})", user.username, user.id);
+    if generated {
+        println!("Password: {password}");
+    }
+    Ok(())
+}
+
+async fn reset_password(
+    client: &prisma::PrismaClient,
+    username: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    let user = find_user(client, &username).await?;
+    let (password, generated) = choose_password(password)?;
+    let (_, revoked, _) = client
+        ._batch((
+            client.user().update(
+                user::UniqueWhereParam::IdEquals(user.id.clone()),
+                vec![user::SetParam::SetPassword(hash(&password))],
+            ),
+            client
+                .session()
+                .delete_many(vec![session::WhereParam::UserId(StringFilter::Equals(
+                    user.id.clone(),
+                ))]),
+            client
+                .pending_login()
+                .delete_many(vec![pending_login::WhereParam::UserId(
+                    StringFilter::Equals(user.id),
+                )]),
+        ))
+        .await
+        .map_err(query_error)?;
+    println!("Password of {username} changed, {revoked} sessions revoked");
+    if generated {
+        println!("Password: {password}");
+    }
+    Ok(())
+}
+
+async fn list_sessions(client: &prisma::PrismaClient, username: String) -> Result<(), String> {
+    let user = find_user(client, &username).await?;
+    let sessions = client
+        .session()
+        .find_many(vec![session::WhereParam::UserId(StringFilter::Equals(
+            user.id,
+        ))])
+        .order_by(session::expires_at::order(Direction::Desc))
+        .exec()
+        .await
+        .map_err(query_error)?;
+    if sessions.is_empty() {
+        println!("{username} has no sessions");
+    }
+    for session in sessions {
+        let expired = if session.expires_at < Utc::now() {
+            " (expired)"
+        } else {
+            ""
+        };
+        println!(
+            "{}  expires {}{expired}",
+            session.id,
+            session.expires_at.format("%Y-%m-%d %H:%M")
+        );
+    }
+    Ok(())
+}
+
+async fn revoke_sessions(
+    client: &prisma::PrismaClient,
+    filter: session::WhereParam,
+) -> Result<(), String> {
+    let revoked = client
+        .session()
+        .delete_many(vec![filter])
+        .exec()
+        .await
+        .map_err(query_error)?;
+    println!("{revoked} sessions revoked");
+    Ok(())
+}
+
+/// Voice notes forwarded to other chats stay, the rest go along with their files
+async fn delete_chat(client: &prisma::PrismaClient, chat_id: String) -> Result<(), String> {
+    let in_chat = || {
+        vec![message::WhereParam::ChatId(StringFilter::Equals(
+            chat_id.clone(),
+        ))]
+    };
+    let voice_notes = client
+        .voice_note()
+        .find_many(vec![
+            voice_note::WhereParam::MessagesSome(in_chat()),
+            voice_note::WhereParam::MessagesEvery(in_chat()),
+        ])
+        .select(voice_note::select!({ id }))
+        .exec()
+        .await
+        .map_err(query_error)?;
+    let chat = client
+        .chat()
+        .delete(chat::UniqueWhereParam::IdEquals(chat_id.clone()))
+        .exec()
+        .await
+        .map_err(|_| format!("No chat with id {chat_id}"))?;
+
+    let mut deleted = 0;
+    for voice_note in voice_notes {
+        // Skipped if the running server forwarded it elsewhere in the meantime
+        let count = client
+            .voice_note()
+            .delete_many(vec![
+                voice_note::WhereParam::Id(StringFilter::Equals(voice_note.id.clone())),
+                voice_note::WhereParam::MessagesNone(vec![]),
+            ])
+            .exec()
+            .await
+            .map_err(query_error)?;
+        if count == 0 {
+            continue;
+        }
+        deleted += 1;
+        let path = Path::new("content").join(format!("voice-{}.ogg", voice_note.id));
+        if let Err(err) = fs::remove_file(&path) {
+            eprintln!("Can't delete {}: {err}", path.display());
+        }
+    }
+    println!("Deleted chat {} and {deleted} voice notes", chat.id);
+    Ok(())
+}
+
+async fn export_user(
+    client: &prisma::PrismaClient,
+    username: String,
+    output: Option<PathBuf>,
+) -> Result<(), String> {
+    let user = find_user(client, &username).await?;
+    let (contacts, blocks, chats, sessions) =
+        client
+            ._batch((
+                client
+                    .contact()
+                    .find_many(vec![contact::WhereParam::OwnerId(StringFilter::Equals(
+                        user.id.clone(),
+                    ))]),
+                client
+                    .block()
+                    .find_many(vec![block::WhereParam::BlockerId(StringFilter::Equals(
+                        user.id.clone(),
+                    ))]),
+                client
+                    .chat()
+                    .find_many(vec![chat::WhereParam::MembersSome(vec![
+                        user::WhereParam::Id(StringFilter::Equals(user.id.clone())),
+                    ])])
+                    .include(chat::include!({
+                        members: select { id }
+                        messages(vec![message::WhereParam::UserId(StringFilter::Equals(
+                            user.id.clone(),
+                        ))])
+                        .order_by(message::created_at::order(Direction::Asc))
+                    })),
+                client.session().find_many(vec![session::WhereParam::UserId(
+                    StringFilter::Equals(user.id.clone()),
+                )]),
+            ))
+            .await
+            .map_err(query_error)?;
+
+    let export = UserExport {
+        id: user.id,
+        username: user.username,
+        display_name: user.display_name,
+        bio: user.bio,
+        status_text: user.status_text,
+        profile_picture: user.profile_picture,
+        who_can_start_chats: user.who_can_start_chats,
+        who_can_see_status: user.who_can_see_status,
+        two_factor_enabled: user.totp_secret.is_some(),
+        is_admin: user.is_admin,
+        disabled: user.disabled,
+        contacts: contacts
+            .into_iter()
+            .map(|contact| ContactExport {
+                user_id: contact.contact_id,
+                accepted: contact.accepted,
+                created_at: contact.created_at,
+            })
+            .collect(),
+        blocked_user_ids: blocks.into_iter().map(|block| block.blocked_id).collect(),
+        chats: chats
+            .into_iter()
+            .map(|chat| ChatExport {
+                id: chat.id,
+                member_ids: chat.members.into_iter().map(|member| member.id).collect(),
+                messages: chat
+                    .messages
+                    .into_iter()
+                    .map(|message| MessageExport {
+                        id: message.id,
+                        content: message.content,
+                        encrypted: message.key_id.is_some(),
+                        reply_id: message.reply_id,
+                        voice_note_id: message.voice_note_id,
+                        created_at: message.created_at,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        sessions: sessions
+            .into_iter()
+            .map(|session| SessionExport {
+                id: session.id,
+                expires_at: session.expires_at,
+            })
+            .collect(),
+    };
+    let json = serde_json::to_string_pretty(&export).unwrap();
+    match output {
+        Some(path) => fs::write(&path, json)
+            .map_err(|err| format!("Can't write {}: {err}", path.display()))?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+async fn run(client: &prisma::PrismaClient, command: Command) -> Result<(), String> {
+    match command {
+        Command::CreateUser { username, password } => create_user(client, username, password).await,
+        Command::ResetPassword { username, password } => {
+            reset_password(client, username, password).await
+        }
+        Command::Sessions { username } => list_sessions(client, username).await,
+        Command::RevokeSession { session_id } => {
+            revoke_sessions(
+                client,
+                session::WhereParam::Id(StringFilter::Equals(session_id)),
+            )
+            .await
+        }
+        Command::RevokeSessions { username } => {
+            let user = find_user(client, &username).await?;
+            revoke_sessions(
+                client,
+                session::WhereParam::UserId(StringFilter::Equals(user.id)),
+            )
+            .await
+        }
+        Command::DeleteChat { chat_id } => delete_chat(client, chat_id).await,
+        Command::ExportUser { username, output } => export_user(client, username, output).await,
+        Command::Migrate => {
+            client
+                ._migrate_deploy()
+                .await
+                .map_err(|err| format!("Migration failed: {err}"))?;
+            println!("The database is up to date");
+            Ok(())
+        }
+        Command::Vacuum => {
+            client
+                ._execute_raw(raw!("VACUUM"))
+                .exec()
+                .await
+                .map_err(query_error)?;
+            println!("Vacuumed");
+            Ok(())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match prisma::new_client().await {
+        Ok(client) => run(&client, cli.command).await,
+        Err(err) => Err(format!("Can't open the database: {err}")),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{env, str::FromStr};
+
+use structs::requests::{DEFAULT_MAX_MESSAGE_LENGTH, DEFAULT_MAX_VOICE_DURATION_SECS};
+
+/// Server settings, read from the environment at startup
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Config {
+    /// `TACO_MAX_MESSAGE_LENGTH`, in characters
+    pub(crate) max_message_length: usize,
+    /// `TACO_MAX_VOICE_DURATION`, in seconds
+    pub(crate) max_voice_duration_secs: u32,
+    /// `TACO_SESSION_PURGE_INTERVAL`, in seconds
+    pub(crate) session_purge_interval_secs: u64,
+}
+
+const DEFAULT_SESSION_PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+impl Config {
+    pub(crate) fn from_env() -> Self {
+        Self {
+            max_message_length: var("TACO_MAX_MESSAGE_LENGTH")
+                .unwrap_or(DEFAULT_MAX_MESSAGE_LENGTH),
+            max_voice_duration_secs: var("TACO_MAX_VOICE_DURATION")
+                .unwrap_or(DEFAULT_MAX_VOICE_DURATION_SECS),
+            session_purge_interval_secs: var("TACO_SESSION_PURGE_INTERVAL")
+                .unwrap_or(DEFAULT_SESSION_PURGE_INTERVAL_SECS),
+        }
+    }
+}
+
+/// A set but malformed variable stops the server instead of being silently ignored
+fn var<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => panic!("{name} has an invalid value: {value:?}"),
+    }
+}
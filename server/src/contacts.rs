@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use prisma_client_rust::Direction;
+use serde::Deserialize;
+use structs::requests::{Contact, ContactRequest, ContactState, UserStatus, WsMessageData};
+
+use crate::{
+    auth::DELETED_USER_ID,
+    prisma::{
+        self, contact,
+        read_filters::{BoolFilter, StringFilter, StringNullableFilter},
+        user,
+    },
+    privacy::visible_status,
+    user::user_status,
+    AppState, Session, WsMessage,
+};
+
+fn between(a: &str, b: &str) -> Vec<contact::WhereParam> {
+    vec![contact::WhereParam::Or(vec![
+        contact::WhereParam::And(vec![
+            contact::WhereParam::OwnerId(StringFilter::Equals(a.into())),
+            contact::WhereParam::ContactId(StringFilter::Equals(b.into())),
+        ]),
+        contact::WhereParam::And(vec![
+            contact::WhereParam::OwnerId(StringFilter::Equals(b.into())),
+            contact::WhereParam::ContactId(StringFilter::Equals(a.into())),
+        ]),
+    ])]
+}
+
+/// Whether the users have added each other
+pub(crate) async fn are_contacts(client: &prisma::PrismaClient, a: &str, b: &str) -> bool {
+    let mut filters = between(a, b);
+    filters.push(contact::WhereParam::Accepted(BoolFilter::Equals(true)));
+    client
+        .contact()
+        .find_first(filters)
+        .exec()
+        .await
+        .unwrap()
+        .is_some()
+}
+
+/// Removes the contact along with pending requests in both directions
+pub(crate) async fn remove_contacts_between(client: &prisma::PrismaClient, a: &str, b: &str) {
+    client
+        .contact()
+        .delete_many(between(a, b))
+        .exec()
+        .await
+        .unwrap();
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+}
+
+/// Users whose username or display name starts with the query
+async fn search_users(
+    State(AppState { client, blocks, .. }): State<AppState>,
+    session: Session,
+    Query(SearchQuery { q }): Query<SearchQuery>,
+) -> Json<Vec<UserStatus>> {
+    const MAX_RESULTS: i64 = 10;
+
+    let query = q.trim();
+    if query.is_empty() {
+        return Json(vec![]);
+    }
+
+    let users = client
+        .user()
+        .find_many(vec![
+            user::WhereParam::Or(vec![
+                user::WhereParam::Username(StringFilter::StartsWith(query.into())),
+                user::WhereParam::DisplayName(StringNullableFilter::StartsWith(query.into())),
+            ]),
+            user::WhereParam::Not(vec![user::WhereParam::Id(StringFilter::InVec(vec![
+                session.user_id.clone(),
+                DELETED_USER_ID.into(),
+            ]))]),
+        ])
+        .order_by(user::username::order(Direction::Asc))
+        .take(MAX_RESULTS)
+        .select(user_status::select())
+        .exec()
+        .await
+        .unwrap();
+
+    let mut results = Vec::with_capacity(users.len());
+    for user in users {
+        if !blocks.between(&user.id, &session.user_id) {
+            results.push(visible_status(&client, &blocks, user, &session.user_id).await);
+        }
+    }
+    Json(results)
+}
+
+async fn get_contacts(
+    State(AppState { client, blocks, .. }): State<AppState>,
+    session: Session,
+) -> Json<Vec<Contact>> {
+    let contacts = client
+        .contact()
+        .find_many(vec![contact::WhereParam::Or(vec![
+            contact::WhereParam::OwnerId(StringFilter::Equals(session.user_id.clone())),
+            contact::WhereParam::ContactId(StringFilter::Equals(session.user_id.clone())),
+        ])])
+        .order_by(contact::created_at::order(Direction::Asc))
+        .exec()
+        .await
+        .unwrap();
+
+    let states: Vec<(String, ContactState)> = contacts
+        .into_iter()
+        .map(|contact| {
+            let is_owner = contact.owner_id == session.user_id;
+            let state = match (contact.accepted, is_owner) {
+                (true, _) => ContactState::Accepted,
+                (false, true) => ContactState::Outgoing,
+                (false, false) => ContactState::Incoming,
+            };
+            if is_owner {
+                (contact.contact_id, state)
+            } else {
+                (contact.owner_id, state)
+            }
+        })
+        .collect();
+
+    let mut users: HashMap<String, UserStatus> = HashMap::new();
+    for user in client
+        .user()
+        .find_many(vec![user::WhereParam::Id(StringFilter::InVec(
+            states.iter().map(|(id, _)| id.clone()).collect(),
+        ))])
+        .select(user_status::select())
+        .exec()
+        .await
+        .unwrap()
+    {
+        users.insert(
+            user.id.clone(),
+            visible_status(&client, &blocks, user, &session.user_id).await,
+        );
+    }
+
+    Json(
+        states
+            .into_iter()
+            .filter_map(|(id, state)| users.remove(&id).map(|user| Contact { user, state }))
+            .collect(),
+    )
+}
+
+async fn add_contact(
+    State(AppState {
+        client,
+        message_sender,
+        blocks,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<ContactRequest>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    if request.user_id == session.user_id {
+        return Err((StatusCode::BAD_REQUEST, "Нельзя добавить себя в контакты!"));
+    }
+    if blocks.between(&session.user_id, &request.user_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Нельзя добавить этого пользователя в контакты!",
+        ));
+    }
+
+    client
+        .user()
+        .find_first(vec![
+            user::WhereParam::Id(StringFilter::Equals(request.user_id.clone())),
+            user::WhereParam::Not(vec![user::WhereParam::Id(StringFilter::Equals(
+                DELETED_USER_ID.into(),
+            ))]),
+        ])
+        .select(user::select!({ id }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Такого пользователя не существует!"))?;
+
+    let incoming = client
+        .contact()
+        .find_unique(contact::UniqueWhereParam::OwnerIdContactIdEquals(
+            request.user_id.clone(),
+            session.user_id.clone(),
+        ))
+        .exec()
+        .await
+        .unwrap();
+
+    if incoming.is_some() {
+        client
+            .contact()
+            .update(
+                contact::UniqueWhereParam::OwnerIdContactIdEquals(
+                    request.user_id.clone(),
+                    session.user_id.clone(),
+                ),
+                vec![contact::SetParam::SetAccepted(true)],
+            )
+            .exec()
+            .await
+            .unwrap();
+    } else {
+        client
+            .contact()
+            .upsert(
+                contact::UniqueWhereParam::OwnerIdContactIdEquals(
+                    session.user_id.clone(),
+                    request.user_id.clone(),
+                ),
+                (
+                    user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                    user::UniqueWhereParam::IdEquals(request.user_id.clone()),
+                    vec![],
+                ),
+                vec![],
+            )
+            .exec()
+            .await
+            .unwrap();
+    }
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from([session.user_id, request.user_id]),
+            data: WsMessageData::ContactsChanged,
+        })
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn remove_contact(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<ContactRequest>,
+) -> Json<()> {
+    remove_contacts_between(&client, &session.user_id, &request.user_id).await;
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from([session.user_id, request.user_id]),
+            data: WsMessageData::ContactsChanged,
+        })
+        .unwrap();
+    Json(())
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/users/search", get(search_users))
+        .route("/contacts", get(get_contacts))
+        .route("/add_contact", post(add_contact))
+        .route("/remove_contact", post(remove_contact))
+}
@@ -0,0 +1,176 @@
+use std::{collections::HashSet, time::Duration};
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use chrono::{DateTime, FixedOffset, Utc};
+use prisma_client_rust::Direction;
+use structs::requests::{
+    SetChatTtl, WsChatTtl, WsDeleteMessage, WsMessageData, MAX_MESSAGE_TTL_SECS,
+    MIN_MESSAGE_TTL_SECS,
+};
+
+use crate::{
+    prisma::{
+        self, chat, message,
+        read_filters::{DateTimeNullableFilter, StringFilter},
+        user,
+    },
+    AppState, Session, WsMessage,
+};
+
+/// Longer than this the reaper doesn't sleep. Not shorter than `MIN_MESSAGE_TTL_SECS`, so a
+/// message sent while it sleeps never expires before it wakes up
+const MAX_SLEEP: Duration = Duration::from_secs(MIN_MESSAGE_TTL_SECS as u64);
+
+/// When a message sent to the chat right now should disappear
+pub(crate) async fn expiry(
+    client: &prisma::PrismaClient,
+    chat_id: &str,
+) -> Option<DateTime<FixedOffset>> {
+    let message_ttl = client
+        .chat()
+        .find_unique(chat::UniqueWhereParam::IdEquals(chat_id.to_owned()))
+        .select(chat::select!({ message_ttl }))
+        .exec()
+        .await
+        .unwrap()?
+        .message_ttl?;
+    Some((Utc::now() + chrono::Duration::seconds(message_ttl.into())).into())
+}
+
+async fn set_chat_ttl(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<SetChatTtl>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    if request
+        .message_ttl
+        .is_some_and(|ttl| !(MIN_MESSAGE_TTL_SECS..=MAX_MESSAGE_TTL_SECS).contains(&ttl))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Сообщения могут исчезать через время от минуты до года!",
+        ));
+    }
+
+    let chat = client
+        .chat()
+        .find_first(vec![
+            chat::WhereParam::Id(StringFilter::Equals(request.chat_id)),
+            chat::WhereParam::MembersSome(vec![user::WhereParam::Id(StringFilter::Equals(
+                session.user_id.clone(),
+            ))]),
+        ])
+        .select(chat::select!({ id members: select { id } }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден"))?;
+
+    client
+        .chat()
+        .update(
+            chat::UniqueWhereParam::IdEquals(chat.id.clone()),
+            vec![chat::SetParam::SetMessageTtl(
+                request.message_ttl.map(|ttl| ttl as i32),
+            )],
+        )
+        .exec()
+        .await
+        .unwrap();
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from_iter(chat.members.into_iter().map(|member| member.id)),
+            data: WsMessageData::ChatTtl(WsChatTtl {
+                chat_id: chat.id,
+                message_ttl: request.message_ttl,
+                changed_by: session.user_id,
+            }),
+        })
+        .unwrap();
+    Ok(Json(()))
+}
+
+/// Deletes expired messages for everyone, like `/delete_message` does
+async fn delete_expired(state: &AppState) {
+    let expired = state
+        .client
+        .message()
+        .find_many(vec![message::WhereParam::ExpiresAt(
+            DateTimeNullableFilter::Lte(Utc::now().into()),
+        )])
+        .include(message::include!({
+            chat: select {
+                members: select {
+                    id
+                }
+            }
+        }))
+        .exec()
+        .await
+        .unwrap();
+    if expired.is_empty() {
+        return;
+    }
+
+    state
+        .client
+        .message()
+        .delete_many(vec![message::WhereParam::Id(StringFilter::InVec(
+            expired.iter().map(|message| message.id.clone()).collect(),
+        ))])
+        .exec()
+        .await
+        .unwrap();
+
+    for message in expired {
+        state
+            .message_sender
+            .send(WsMessage {
+                recipient_ids: HashSet::from_iter(
+                    message.chat.members.into_iter().map(|member| member.id),
+                ),
+                data: WsMessageData::DeleteMessage(WsDeleteMessage {
+                    chat_id: message.chat_id,
+                    message_id: message.id,
+                }),
+            })
+            .unwrap();
+    }
+}
+
+/// Runs for the lifetime of the server
+pub(crate) async fn run(state: AppState) {
+    loop {
+        delete_expired(&state).await;
+
+        let next = state
+            .client
+            .message()
+            .find_first(vec![message::WhereParam::ExpiresAt(
+                DateTimeNullableFilter::Not(None),
+            )])
+            .order_by(message::expires_at::order(Direction::Asc))
+            .select(message::select!({ expires_at }))
+            .exec()
+            .await
+            .unwrap();
+        let sleep = next
+            .and_then(|next| next.expires_at)
+            .map_or(MAX_SLEEP, |expires_at| {
+                (expires_at.with_timezone(&Utc) - Utc::now())
+                    .to_std()
+                    .unwrap_or_default()
+                    .min(MAX_SLEEP)
+            });
+        tokio::time::sleep(sleep).await;
+    }
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new().route("/set_chat_ttl", post(set_chat_ttl))
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use structs::requests::{ChatKey, CreateChatKey, IdentityKey, PublishIdentityKey, WsMessageData};
+
+use crate::{
+    config::Config,
+    prisma::{self, chat, chat_key, read_filters::StringFilter, scheduled_message, user},
+    AppState, Session, WsMessage,
+};
+
+/// X25519 public keys are 32 bytes
+const PUBLIC_KEY_LENGTH: usize = 32;
+/// Nonce and tag of ChaCha20-Poly1305 around the text
+const CIPHERTEXT_OVERHEAD: usize = 12 + 16;
+
+fn is_public_key(key: &str) -> bool {
+    STANDARD
+        .decode(key)
+        .is_ok_and(|key| key.len() == PUBLIC_KEY_LENGTH)
+}
+
+pub(crate) fn to_chat_key(key: chat_key::Data) -> ChatKey {
+    ChatKey {
+        id: key.id,
+        chat_id: key.chat_id,
+        initiator_id: key.initiator_id,
+        initiator_key: key.initiator_key,
+        responder_key: key.responder_key,
+        ephemeral_key: key.ephemeral_key,
+        created_at: key.created_at.into(),
+    }
+}
+
+/// Encrypted content is stored as it was sent, only its size can be checked
+pub(crate) fn validate_ciphertext(
+    content: &str,
+    config: &Config,
+) -> Result<String, (StatusCode, &'static str)> {
+    let ciphertext = STANDARD.decode(content).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            "Повреждённое зашифрованное сообщение!",
+        )
+    })?;
+    if ciphertext.len() <= CIPHERTEXT_OVERHEAD {
+        return Err((StatusCode::BAD_REQUEST, "Сообщение не может быть пустым!"));
+    }
+    // A character takes up to 4 bytes of UTF-8
+    if ciphertext.len() > config.max_message_length * 4 + CIPHERTEXT_OVERHEAD {
+        return Err((StatusCode::BAD_REQUEST, "Сообщение слишком длинное!"));
+    }
+    Ok(content.to_owned())
+}
+
+pub(crate) async fn is_encrypted(client: &prisma::PrismaClient, chat_id: &str) -> bool {
+    client
+        .chat_key()
+        .count(vec![chat_key::WhereParam::ChatId(StringFilter::Equals(
+            chat_id.to_owned(),
+        ))])
+        .exec()
+        .await
+        .unwrap()
+        > 0
+}
+
+/// Encrypted chats only take messages encrypted with one of their keys, other chats only plain
+/// text
+pub(crate) async fn check_key(
+    client: &prisma::PrismaClient,
+    chat_id: &str,
+    key_id: Option<&str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    let keys = client
+        .chat_key()
+        .find_many(vec![chat_key::WhereParam::ChatId(StringFilter::Equals(
+            chat_id.to_owned(),
+        ))])
+        .select(chat_key::select!({ id }))
+        .exec()
+        .await
+        .unwrap();
+    match key_id {
+        Some(key_id) if !keys.iter().any(|key| key.id == key_id) => {
+            Err((StatusCode::BAD_REQUEST, "Ключ шифрования не найден!"))
+        }
+        None if !keys.is_empty() => Err((
+            StatusCode::BAD_REQUEST,
+            "Этот чат зашифрован, сообщение должно быть зашифровано!",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A new key makes chats encrypted with the old one unreadable on this side, the client
+/// agrees on new chat keys when that happens
+async fn publish_identity_key(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<PublishIdentityKey>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    if !is_public_key(&request.identity_key) {
+        return Err((StatusCode::BAD_REQUEST, "Неверный ключ!"));
+    }
+    client
+        .user()
+        .update(
+            user::UniqueWhereParam::IdEquals(session.user_id),
+            vec![user::SetParam::SetIdentityKey(Some(request.identity_key))],
+        )
+        .exec()
+        .await
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn get_identity_key(
+    State(AppState { client, .. }): State<AppState>,
+    _: Session,
+    Path(user_id): Path<String>,
+) -> Result<Json<IdentityKey>, (StatusCode, &'static str)> {
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(user_id))
+        .select(user::select!({ id identity_key }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Пользователь не найден"))?;
+    Ok(Json(IdentityKey {
+        user_id: user.id,
+        identity_key: user.identity_key,
+    }))
+}
+
+/// Turns encryption on for a direct chat, or replaces its key after an identity key changed
+async fn create_chat_key(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<CreateChatKey>,
+) -> Result<Json<ChatKey>, (StatusCode, &'static str)> {
+    if !is_public_key(&request.ephemeral_key) {
+        return Err((StatusCode::BAD_REQUEST, "Неверный ключ!"));
+    }
+
+    let chat = client
+        .chat()
+        .find_first(vec![
+            chat::WhereParam::Id(StringFilter::Equals(request.chat_id)),
+            chat::WhereParam::MembersSome(vec![user::WhereParam::Id(StringFilter::Equals(
+                session.user_id.clone(),
+            ))]),
+        ])
+        .select(chat::select!({
+            id
+            direct_key
+            members: select {
+                id
+                identity_key
+            }
+        }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден"))?;
+    if chat.direct_key.is_none() || chat.members.len() != 2 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Шифровать можно только личные чаты!",
+        ));
+    }
+
+    // The client may have agreed on keys a member has replaced since
+    let keys_are_current = chat.members.iter().all(|member| {
+        let expected = if member.id == session.user_id {
+            &request.initiator_key
+        } else {
+            &request.responder_key
+        };
+        member.identity_key.as_ref() == Some(expected)
+    });
+    if !keys_are_current {
+        return Err((
+            StatusCode::CONFLICT,
+            "Ключи участников чата изменились, попробуйте ещё раз",
+        ));
+    }
+
+    // Scheduled messages are plain text, they can't be sent to the chat once it's encrypted
+    let (chat_key, _) =
+        client
+            ._batch((
+                client.chat_key().create(
+                    chat::UniqueWhereParam::IdEquals(chat.id.clone()),
+                    session.user_id,
+                    request.initiator_key,
+                    request.responder_key,
+                    request.ephemeral_key,
+                    vec![],
+                ),
+                client.scheduled_message().delete_many(vec![
+                    scheduled_message::WhereParam::ChatId(StringFilter::Equals(chat.id)),
+                ]),
+            ))
+            .await
+            .unwrap();
+    let chat_key = to_chat_key(chat_key);
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from_iter(chat.members.into_iter().map(|member| member.id)),
+            data: WsMessageData::ChatKey(chat_key.clone()),
+        })
+        .unwrap();
+    Ok(Json(chat_key))
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/identity_key", post(publish_identity_key))
+        .route("/identity_key/:user_id", get(get_identity_key))
+        .route("/chat_keys", post(create_chat_key))
+}
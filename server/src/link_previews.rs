@@ -0,0 +1,829 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::Utc;
+use prisma_client_rust::prisma_errors::query_engine::UniqueKeyViolation;
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION},
+    redirect, Url,
+};
+use structs::{
+    markdown::{self, Block},
+    requests::{LinkPreview, WsLinkPreview, WsMessageData},
+};
+
+use crate::{
+    prisma::{
+        chat, link_preview,
+        read_filters::{DateTimeFilter, StringFilter},
+    },
+    AppState, WsMessage,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 3;
+/// Only the start of a page is read, OpenGraph tags are in `<head>`
+const MAX_PAGE_SIZE: usize = 512 * 1024;
+const MAX_IMAGE_SIZE: usize = 2 * 1024 * 1024;
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+/// Cached previews are fetched again after this long
+const CACHE_LIFETIME: chrono::Duration = chrono::Duration::days(1);
+
+/// First link of a message, the only one that gets a preview
+pub(crate) fn first_link(content: &str) -> Option<String> {
+    markdown::parse(content)
+        .into_iter()
+        .filter_map(|block| match block {
+            Block::Paragraph(spans) => Some(spans),
+            Block::Code(_) => None,
+        })
+        .flatten()
+        .find_map(|span| span.link)
+}
+
+/// Whether the address is reachable from the internet, previews must not reach into the server's own network
+pub(crate) fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Shared address space, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(ip));
+            }
+            // Forms that reach an IPv4 address through a gateway or a tunnel
+            let embedded = match ip.segments() {
+                // NAT64, 64:ff9b::/96, and the deprecated IPv4-compatible ::a.b.c.d
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] | [0, 0, 0, 0, 0, 0, high, low] => {
+                    Some((high, low))
+                }
+                // 6to4, 2002::/16
+                [0x2002, high, low, ..] => Some((high, low)),
+                _ => None,
+            };
+            if let Some((high, low)) = embedded {
+                let ip = Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+                return is_public_address(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link local, fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct PageMetadata {
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    /// Absolute URL of the image
+    pub(crate) image: Option<String>,
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..=end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Attributes of a tag like `<meta property="og:title" content="...">`, names lowercased
+fn tag_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            return attributes;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(value_start) = rest.strip_prefix('=') else {
+            attributes.push((name, String::new()));
+            continue;
+        };
+        let value_start = value_start.trim_start();
+        let (value, remaining) = match value_start.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value_start[1..];
+                match value.find(quote) {
+                    Some(end) => (&value[..end], &value[end + 1..]),
+                    None => (value, ""),
+                }
+            }
+            _ => {
+                let end = value_start
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(value_start.len());
+                (&value_start[..end], &value_start[end..])
+            }
+        };
+        attributes.push((name, decode_entities(value)));
+        rest = remaining;
+    }
+}
+
+fn clean_text(text: &str, max_length: usize) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then(|| text.chars().take(max_length).collect())
+}
+
+/// OpenGraph title, description and image, falling back to `<title>` and the description meta tag
+pub(crate) fn parse_metadata(html: &str, base: &Url) -> PageMetadata {
+    // ASCII lowercasing keeps byte offsets, so they can be used on the original
+    let lowercase = html.to_ascii_lowercase();
+    let mut og_title = None;
+    let mut og_description = None;
+    let mut og_image = None;
+    let mut description = None;
+
+    let mut offset = 0;
+    while let Some(start) = lowercase[offset..].find("<meta") {
+        let start = offset + start + "<meta".len();
+        let end = lowercase[start..]
+            .find('>')
+            .map_or(lowercase.len(), |end| start + end);
+        offset = end;
+
+        let attributes = tag_attributes(&html[start..end]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let Some(content) = attribute("content") else {
+            continue;
+        };
+        let key = attribute("property")
+            .or_else(|| attribute("name"))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let target = match key.as_str() {
+            "og:title" => &mut og_title,
+            "og:description" => &mut og_description,
+            "og:image" | "og:image:url" => &mut og_image,
+            "description" => &mut description,
+            _ => continue,
+        };
+        if target.is_none() {
+            *target = Some(content.to_owned());
+        }
+    }
+
+    let title = og_title.or_else(|| {
+        let start = lowercase.find("<title")?;
+        let start = start + lowercase[start..].find('>')? + 1;
+        let end = start + lowercase[start..].find("</title")?;
+        Some(decode_entities(&html[start..end]))
+    });
+
+    PageMetadata {
+        title: title.and_then(|title| clean_text(&title, MAX_TITLE_LENGTH)),
+        description: og_description
+            .or(description)
+            .and_then(|description| clean_text(&description, MAX_DESCRIPTION_LENGTH)),
+        image: og_image
+            .and_then(|image| base.join(image.trim()).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(String::from),
+    }
+}
+
+/// Fetches pages for previews without letting them reach private addresses
+pub(crate) struct PreviewFetcher {
+    allow_private_addresses: bool,
+    timeout: Duration,
+}
+
+impl PreviewFetcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            allow_private_addresses: false,
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Follows redirects itself so every hop is checked, connections go to the checked addresses
+    /// so the host can't resolve somewhere else in between
+    async fn get(&self, url: &str) -> Option<reqwest::Response> {
+        let mut url = Url::parse(url).ok()?;
+        for _ in 0..=MAX_REDIRECTS {
+            if !matches!(url.scheme(), "http" | "https") {
+                return None;
+            }
+            let host = url.host_str()?.trim_matches(['[', ']']).to_owned();
+            let port = url.port_or_known_default()?;
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .ok()?
+                .collect();
+            let allowed = self.allow_private_addresses
+                || addresses
+                    .iter()
+                    .all(|address| is_public_address(address.ip()));
+            if addresses.is_empty() || !allowed {
+                return None;
+            }
+
+            let client = reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                .connect_timeout(CONNECT_TIMEOUT.min(self.timeout))
+                .timeout(self.timeout)
+                .resolve(&host, addresses[0])
+                .user_agent("TacoLinkPreview/1.0")
+                .build()
+                .ok()?;
+            let response = client.get(url.clone()).send().await.ok()?;
+            if response.status().is_redirection() {
+                let location = response.headers().get(LOCATION)?.to_str().ok()?;
+                url = url.join(location).ok()?;
+                continue;
+            }
+            return response.status().is_success().then_some(response);
+        }
+        None
+    }
+
+    /// Reads at most `limit` bytes, returns whether the whole body fit
+    async fn read_limited(
+        mut response: reqwest::Response,
+        limit: usize,
+    ) -> Option<(Vec<u8>, bool)> {
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.ok()? {
+            if body.len() + chunk.len() > limit {
+                body.extend_from_slice(&chunk[..limit - body.len()]);
+                return Some((body, false));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Some((body, true))
+    }
+
+    fn content_type(response: &reqwest::Response) -> String {
+        response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    }
+
+    pub(crate) async fn fetch_page(&self, url: &str) -> Option<PageMetadata> {
+        let response = self.get(url).await?;
+        let page_url = response.url().clone();
+        let content_type = Self::content_type(&response);
+        if !(content_type.starts_with("text/html")
+            || content_type.starts_with("application/xhtml+xml"))
+        {
+            return None;
+        }
+        let (body, _) = Self::read_limited(response, MAX_PAGE_SIZE).await?;
+        Some(parse_metadata(&String::from_utf8_lossy(&body), &page_url))
+    }
+
+    pub(crate) async fn fetch_image(&self, url: &str) -> Option<Vec<u8>> {
+        let response = self.get(url).await?;
+        let content_type = Self::content_type(&response);
+        // SVG can carry scripts
+        if !content_type.starts_with("image/") || content_type.starts_with("image/svg") {
+            return None;
+        }
+        let declared_length = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+        if declared_length.is_some_and(|length| length > MAX_IMAGE_SIZE) {
+            return None;
+        }
+        let (body, complete) = Self::read_limited(response, MAX_IMAGE_SIZE).await?;
+        complete.then_some(body)
+    }
+}
+
+/// `None` for pages that had nothing to show, they are cached too so they aren't fetched again
+pub(crate) fn to_link_preview(preview: link_preview::Data) -> Option<LinkPreview> {
+    if preview.title.is_none() && preview.description.is_none() && preview.image.is_none() {
+        return None;
+    }
+    Some(LinkPreview {
+        url: preview.url,
+        title: preview.title,
+        description: preview.description,
+        image: preview.image,
+    })
+}
+
+async fn find_or_fetch(state: &AppState, url: String) -> Option<LinkPreview> {
+    let cached = state
+        .client
+        .link_preview()
+        .find_unique(link_preview::UniqueWhereParam::UrlEquals(url.clone()))
+        .exec()
+        .await
+        .unwrap();
+    if let Some(cached) = &cached {
+        if Utc::now() - cached.fetched_at.with_timezone(&Utc) < CACHE_LIFETIME {
+            return to_link_preview(cached.clone());
+        }
+    }
+
+    let metadata = state
+        .link_previews
+        .fetch_page(&url)
+        .await
+        .unwrap_or_default();
+    // Images are served from the server, clients don't connect to linked sites
+    let image = match metadata.image {
+        Some(image_url) => match state.link_previews.fetch_image(&image_url).await {
+            Some(bytes) => {
+                let name = format!("preview-{}", uuid::Uuid::new_v4());
+                tokio::fs::write(Path::new("content").join(&name), bytes)
+                    .await
+                    .ok()
+                    .map(|_| name)
+            }
+            None => None,
+        },
+        None => None,
+    };
+
+    let set_params = vec![
+        link_preview::SetParam::SetTitle(metadata.title),
+        link_preview::SetParam::SetDescription(metadata.description),
+        link_preview::SetParam::SetImage(image.clone()),
+        link_preview::SetParam::SetFetchedAt(Utc::now().into()),
+    ];
+    // Another message with the same link may have fetched it meanwhile, only the row this fetch
+    // started from is replaced so whichever image loses can be deleted
+    let stored = match &cached {
+        Some(cached) => {
+            let updated = state
+                .client
+                .link_preview()
+                .update_many(
+                    vec![
+                        link_preview::WhereParam::Url(StringFilter::Equals(url.clone())),
+                        link_preview::WhereParam::FetchedAt(DateTimeFilter::Equals(
+                            cached.fetched_at,
+                        )),
+                    ],
+                    set_params,
+                )
+                .exec()
+                .await
+                .unwrap();
+            updated == 1
+        }
+        None => match state
+            .client
+            .link_preview()
+            .create(url.clone(), set_params)
+            .exec()
+            .await
+        {
+            Ok(_) => true,
+            Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => false,
+            Err(err) => panic!("{err}"),
+        },
+    };
+
+    let old_image = if stored {
+        cached.and_then(|cached| cached.image)
+    } else {
+        image
+    };
+    if let Some(old_image) = old_image {
+        let _ = tokio::fs::remove_file(Path::new("content").join(old_image)).await;
+    }
+    let preview = state
+        .client
+        .link_preview()
+        .find_unique(link_preview::UniqueWhereParam::UrlEquals(url))
+        .exec()
+        .await
+        .unwrap()?;
+    to_link_preview(preview)
+}
+
+/// Finds or fetches the preview of `url` and sends it to the members of the chat
+pub(crate) async fn attach_preview(
+    state: AppState,
+    chat_id: String,
+    message_id: String,
+    url: String,
+) {
+    let Some(preview) = find_or_fetch(&state, url).await else {
+        return;
+    };
+    let Some(chat) = state
+        .client
+        .chat()
+        .find_unique(chat::UniqueWhereParam::IdEquals(chat_id.clone()))
+        .select(chat::select!({
+            members: select {
+                id
+            }
+        }))
+        .exec()
+        .await
+        .unwrap()
+    else {
+        return;
+    };
+
+    let _ = state.message_sender.send(WsMessage {
+        recipient_ids: HashSet::from_iter(chat.members.into_iter().map(|member| member.id)),
+        data: WsMessageData::LinkPreview(WsLinkPreview {
+            chat_id,
+            message_id,
+            preview,
+        }),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Minimal HTTP server answering every request with whatever `respond` returns for its path
+    async fn stand_in(respond: fn(&str) -> Option<Vec<u8>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+                    match respond(&path) {
+                        Some(response) => {
+                            let _ = socket.write_all(&response).await;
+                        }
+                        // Never answers
+                        None => tokio::time::sleep(Duration::from_secs(60)).await,
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str("\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn html(body: &str) -> Option<Vec<u8>> {
+        Some(response(
+            "200 OK",
+            &[("Content-Type", "text/html; charset=utf-8")],
+            body.as_bytes(),
+        ))
+    }
+
+    const PAGE: &str = r#"<html><head>
+        <title>Fallback title</title>
+        <meta property="og:title" content="Taco &amp; friends">
+        <meta name="description" content="Plain description">
+        <meta property='og:description' content='Open &quot;Graph&quot; description'>
+        <meta property="og:image" content="/images/cover.png">
+        </head><body>Hello</body></html>"#;
+
+    fn local_fetcher() -> PreviewFetcher {
+        PreviewFetcher {
+            allow_private_addresses: true,
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    fn routes(path: &str) -> Option<Vec<u8>> {
+        match path {
+            "/page" => html(PAGE),
+            "/redirect" => Some(response("302 Found", &[("Location", "/page")], b"")),
+            "/redirect-loop" => Some(response(
+                "302 Found",
+                &[("Location", "/redirect-loop")],
+                b"",
+            )),
+            "/redirect-file" => Some(response(
+                "302 Found",
+                &[("Location", "file:///etc/passwd")],
+                b"",
+            )),
+            "/json" => Some(response(
+                "200 OK",
+                &[("Content-Type", "application/json")],
+                b"{}",
+            )),
+            "/huge" => {
+                let mut page = PAGE.to_owned();
+                page.push_str(&"x".repeat(4 * MAX_PAGE_SIZE));
+                html(&page)
+            }
+            "/image.png" => Some(response(
+                "200 OK",
+                &[("Content-Type", "image/png")],
+                &[0x89, b'P', b'N', b'G'],
+            )),
+            "/huge.png" => Some(response(
+                "200 OK",
+                &[("Content-Type", "image/png")],
+                &vec![0; MAX_IMAGE_SIZE + 1],
+            )),
+            "/image.svg" => Some(response(
+                "200 OK",
+                &[("Content-Type", "image/svg+xml")],
+                b"<svg/>",
+            )),
+            "/missing" => Some(response("404 Not Found", &[], b"")),
+            "/slow" => None,
+            _ => Some(response("404 Not Found", &[], b"")),
+        }
+    }
+
+    #[test]
+    fn parses_open_graph_tags() {
+        let base = Url::parse("https://example.com/articles/1").unwrap();
+        assert_eq!(
+            parse_metadata(PAGE, &base),
+            PageMetadata {
+                title: Some("Taco & friends".into()),
+                description: Some("Open \"Graph\" description".into()),
+                image: Some("https://example.com/images/cover.png".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_title_and_description() {
+        let base = Url::parse("https://example.com").unwrap();
+        let page = "<HTML><HEAD><TITLE>\n  Just a   title </TITLE>\
+            <META NAME=description CONTENT=Short></HEAD></HTML>";
+        assert_eq!(
+            parse_metadata(page, &base),
+            PageMetadata {
+                title: Some("Just a title".into()),
+                description: Some("Short".into()),
+                image: None,
+            }
+        );
+        assert_eq!(parse_metadata("", &base), PageMetadata::default());
+    }
+
+    #[test]
+    fn ignores_non_http_images() {
+        let base = Url::parse("https://example.com").unwrap();
+        let page = r#"<meta property="og:image" content="javascript:alert(1)">"#;
+        assert_eq!(parse_metadata(page, &base).image, None);
+    }
+
+    #[test]
+    fn finds_first_link_outside_code() {
+        assert_eq!(
+            first_link("```\nhttps://code.example\n```\nsee [this](https://a.example) and https://b.example"),
+            Some("https://a.example".into())
+        );
+        assert_eq!(first_link("no links here"), None);
+    }
+
+    #[test]
+    fn blocks_private_addresses() {
+        for private in [
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)),
+            IpAddr::V4(Ipv4Addr::new(172, 16, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254)),
+            IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1)),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V6("fd00::1".parse().unwrap()),
+            IpAddr::V6("fe80::1".parse().unwrap()),
+            IpAddr::V6("::ffff:127.0.0.1".parse().unwrap()),
+            IpAddr::V6("64:ff9b::10.0.0.1".parse().unwrap()),
+            IpAddr::V6("64:ff9b::169.254.169.254".parse().unwrap()),
+            IpAddr::V6("2002:7f00:1::1".parse().unwrap()),
+            IpAddr::V6("2002:c0a8:101::".parse().unwrap()),
+            IpAddr::V6("::127.0.0.1".parse().unwrap()),
+            IpAddr::V6("::192.168.1.1".parse().unwrap()),
+        ] {
+            assert!(!is_public_address(private), "{private} is private");
+        }
+        for public in [
+            IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+            IpAddr::V6("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap()),
+            IpAddr::V6("64:ff9b::93.184.216.34".parse().unwrap()),
+            IpAddr::V6("2002:5db8:d822::1".parse().unwrap()),
+        ] {
+            assert!(is_public_address(public), "{public} is public");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_private_hosts() {
+        let address = stand_in(routes).await;
+        let fetcher = PreviewFetcher::new();
+        assert_eq!(
+            fetcher.fetch_page(&format!("http://{address}/page")).await,
+            None
+        );
+        assert_eq!(fetcher.fetch_page("http://localhost:1/page").await, None);
+    }
+
+    #[tokio::test]
+    async fn fetches_page_metadata() {
+        let address = stand_in(routes).await;
+        let metadata = local_fetcher()
+            .fetch_page(&format!("http://{address}/page"))
+            .await
+            .unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Taco & friends"));
+        assert_eq!(
+            metadata.image,
+            Some(format!("http://{address}/images/cover.png"))
+        );
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_with_a_limit() {
+        let address = stand_in(routes).await;
+        let fetcher = local_fetcher();
+        let redirected = fetcher
+            .fetch_page(&format!("http://{address}/redirect"))
+            .await
+            .unwrap();
+        // Relative image URLs resolve against the final page
+        assert_eq!(
+            redirected.image,
+            Some(format!("http://{address}/images/cover.png"))
+        );
+        assert_eq!(
+            fetcher
+                .fetch_page(&format!("http://{address}/redirect-loop"))
+                .await,
+            None
+        );
+        assert_eq!(
+            fetcher
+                .fetch_page(&format!("http://{address}/redirect-file"))
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_other_content() {
+        let address = stand_in(routes).await;
+        let fetcher = local_fetcher();
+        assert_eq!(
+            fetcher.fetch_page(&format!("http://{address}/json")).await,
+            None
+        );
+        assert_eq!(
+            fetcher
+                .fetch_page(&format!("http://{address}/missing"))
+                .await,
+            None
+        );
+        assert_eq!(fetcher.fetch_page("ftp://example.com/page").await, None);
+    }
+
+    #[tokio::test]
+    async fn reads_only_the_start_of_huge_pages() {
+        let address = stand_in(routes).await;
+        let metadata = local_fetcher()
+            .fetch_page(&format!("http://{address}/huge"))
+            .await
+            .unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Taco & friends"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_slow_servers() {
+        let address = stand_in(routes).await;
+        let started = std::time::Instant::now();
+        assert_eq!(
+            local_fetcher()
+                .fetch_page(&format!("http://{address}/slow"))
+                .await,
+            None
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn fetches_only_small_raster_images() {
+        let address = stand_in(routes).await;
+        let fetcher = local_fetcher();
+        assert_eq!(
+            fetcher
+                .fetch_image(&format!("http://{address}/image.png"))
+                .await,
+            Some(vec![0x89, b'P', b'N', b'G'])
+        );
+        assert_eq!(
+            fetcher
+                .fetch_image(&format!("http://{address}/huge.png"))
+                .await,
+            None
+        );
+        assert_eq!(
+            fetcher
+                .fetch_image(&format!("http://{address}/image.svg"))
+                .await,
+            None
+        );
+        assert_eq!(
+            fetcher.fetch_image(&format!("http://{address}/page")).await,
+            None
+        );
+    }
+}
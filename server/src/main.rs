@@ -8,16 +8,35 @@ use axum::{
     Router,
 };
 use futures::{sink::SinkExt, stream::StreamExt};
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 use structs::requests::WsMessageData;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 
 #[allow(warnings, unused)]
 mod prisma;
 
+mod accounts;
+mod admin;
+mod archive;
 mod auth;
 pub(crate) use auth::Session;
 
+mod config;
+mod contacts;
+mod disappearing;
+mod e2ee;
+mod link_previews;
+mod privacy;
+mod rate_limit;
+mod reactions;
+mod scheduled;
+mod sessions;
+mod tokens;
+mod two_factor;
 mod upload;
 mod user;
 
@@ -40,10 +59,35 @@ pub(crate) struct WsMessage {
     data: WsMessageData,
 }
 
+impl WsMessage {
+    /// User whose action caused the message, it isn't delivered to users blocked by or blocking them
+    fn origin(&self) -> Option<&str> {
+        match &self.data {
+            WsMessageData::ChatMessage(message) => Some(&message.sender_id),
+            WsMessageData::ProfileUpdated(status) => Some(&status.id),
+            WsMessageData::ReactionAdded(reaction) | WsMessageData::ReactionRemoved(reaction) => {
+                Some(&reaction.user_id)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct AppState {
     client: Arc<prisma::PrismaClient>,
     message_sender: broadcast::Sender<WsMessage>,
+    rate_limits: Arc<rate_limit::RateLimits>,
+    /// Open sockets per user, a user is online while they have any
+    connections: Arc<Mutex<HashMap<String, usize>>>,
+    blocks: Arc<privacy::Blocks>,
+    link_previews: Arc<link_previews::PreviewFetcher>,
+    config: config::Config,
+    /// Wakes the scheduled message delivery when the schedule changes
+    scheduler: Arc<Notify>,
+    session_metrics: Arc<sessions::SessionMetrics>,
+    tokens: Arc<tokens::TokenSigner>,
+    disabled_users: Arc<admin::DisabledUsers>,
 }
 
 #[tokio::main]
@@ -56,18 +100,73 @@ async fn main() {
 
     let (tx, _rx) = broadcast::channel(MAX_MESSAGES);
 
+    let client = Arc::new(prisma::new_client().await.unwrap());
+
+    // `server <command>` runs the command instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        match admin::run_command(&client, &args).await {
+            Ok(output) => println!("{output}"),
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let state = AppState {
+        blocks: Arc::new(privacy::Blocks::load(&client).await),
+        disabled_users: Arc::new(admin::DisabledUsers::load(&client).await),
+        client,
+        message_sender: tx,
+        rate_limits: Arc::new(rate_limit::RateLimits::new()),
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        link_previews: Arc::new(link_previews::PreviewFetcher::new()),
+        config: config::Config::from_env(),
+        scheduler: Arc::new(Notify::new()),
+        session_metrics: Arc::new(sessions::SessionMetrics::default()),
+        tokens: Arc::new(tokens::TokenSigner::from_env()),
+    };
+
+    // Nobody is connected yet, even if the server didn't shut down cleanly
+    state
+        .client
+        .user()
+        .update_many(vec![], vec![prisma::user::SetParam::SetOnline(false)])
+        .exec()
+        .await
+        .unwrap();
+
+    tokio::spawn(scheduled::run(state.clone()));
+    tokio::spawn(disappearing::run(state.clone()));
+    tokio::spawn(sessions::run(state.clone()));
+    tokio::spawn(upload::run(state.clone()));
+
     let app = Router::new()
-        .nest("/", auth::router())
-        .nest("/", user::router())
+        .nest("/", auth::router(state.clone()))
+        .nest("/", two_factor::router(state.clone()))
+        .nest("/", user::router(state.clone()))
+        .nest("/", contacts::router())
+        .nest("/", privacy::router())
+        .nest("/", reactions::router())
+        .nest("/", scheduled::router())
+        .nest("/", disappearing::router())
+        .nest("/", e2ee::router())
+        .nest("/", sessions::router())
         .nest("/", upload::router())
+        .nest("/", admin::router())
+        .nest("/", archive::router())
         .route("/ws", get(ws_handler))
-        .with_state(AppState {
-            client: Arc::new(prisma::new_client().await.unwrap()),
-            message_sender: tx,
-        });
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 async fn ws_handler(
@@ -78,20 +177,82 @@ async fn ws_handler(
     ws.on_upgrade(|socket| handle_client(state, session.user_id, socket))
 }
 
+/// Updates the online flag when the first socket of a user opens or the last one closes
+async fn update_presence(state: &AppState, user_id: &str, connected: bool) {
+    let changed = {
+        let mut connections = state.connections.lock().unwrap();
+        let count = connections.entry(user_id.to_owned()).or_default();
+        if connected {
+            *count += 1;
+            *count == 1
+        } else {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(user_id);
+                true
+            } else {
+                false
+            }
+        }
+    };
+    if !changed {
+        return;
+    }
+
+    // The account may have been deleted while connected
+    let updated = state
+        .client
+        .user()
+        .update(
+            prisma::user::UniqueWhereParam::IdEquals(user_id.to_owned()),
+            vec![prisma::user::SetParam::SetOnline(connected)],
+        )
+        .exec()
+        .await;
+    if updated.is_ok() {
+        user::broadcast_profile(
+            &state.client,
+            &state.message_sender,
+            &state.blocks,
+            user_id.to_owned(),
+        )
+        .await;
+    }
+}
+
 async fn handle_client(state: AppState, user_id: String, ws: WebSocket) {
-    let (mut sender, mut _receiver) = ws.split();
+    let (mut sender, mut receiver) = ws.split();
 
     let mut message_receiver = state.message_sender.subscribe();
+    update_presence(&state, &user_id, true).await;
 
-    while let Ok(msg) = message_receiver.recv().await {
-        if msg.recipient_ids.contains(&user_id) {
-            if sender
-                .send(Message::Text(serde_json::to_string(&msg.data).unwrap()))
-                .await
-                .is_err()
-            {
-                break;
+    loop {
+        tokio::select! {
+            msg = message_receiver.recv() => {
+                let Ok(msg) = msg else {
+                    break;
+                };
+                if !msg.recipient_ids.contains(&user_id)
+                    || msg.origin().is_some_and(|origin| state.blocks.between(origin, &user_id))
+                {
+                    continue;
+                }
+                if sender
+                    .send(Message::Text(serde_json::to_string(&msg.data).unwrap()))
+                    .await
+                    .is_err()
+                    || matches!(msg.data, WsMessageData::AccountDisabled)
+                {
+                    break;
+                }
             }
+            // Clients don't send anything, this only notices the socket closing
+            incoming = receiver.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
         }
     }
+
+    update_presence(&state, &user_id, false).await;
 }
@@ -0,0 +1,257 @@
+use std::{collections::HashSet, sync::RwLock};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use structs::requests::{Audience, BlockRequest, PrivacySettings, UserStatus, WsMessageData};
+
+use crate::{
+    auth::DELETED_USER_ID,
+    contacts::{are_contacts, remove_contacts_between},
+    prisma::{self, block, read_filters::StringFilter, user},
+    user::{broadcast_profile, to_user_status, user_status},
+    AppState, Session, WsMessage,
+};
+
+pub(crate) fn audience_from_db(value: &str) -> Audience {
+    match value {
+        "contacts" => Audience::Contacts,
+        "nobody" => Audience::Nobody,
+        _ => Audience::Everyone,
+    }
+}
+
+fn audience_to_db(audience: Audience) -> String {
+    match audience {
+        Audience::Everyone => "everyone",
+        Audience::Contacts => "contacts",
+        Audience::Nobody => "nobody",
+    }
+    .into()
+}
+
+/// Mirror of the `Block` table, socket fan-out checks it for every message
+pub(crate) struct Blocks(RwLock<HashSet<(String, String)>>);
+
+impl Blocks {
+    pub(crate) async fn load(client: &prisma::PrismaClient) -> Self {
+        let blocks = client.block().find_many(vec![]).exec().await.unwrap();
+        Self(RwLock::new(
+            blocks
+                .into_iter()
+                .map(|block| (block.blocker_id, block.blocked_id))
+                .collect(),
+        ))
+    }
+
+    /// Whether either of the users has blocked the other
+    pub(crate) fn between(&self, a: &str, b: &str) -> bool {
+        let blocks = self.0.read().unwrap();
+        blocks.contains(&(a.to_owned(), b.to_owned()))
+            || blocks.contains(&(b.to_owned(), a.to_owned()))
+    }
+}
+
+/// Whether `viewer_id` belongs to the `audience` chosen by `owner_id`, blocked users never do
+pub(crate) async fn allows(
+    client: &prisma::PrismaClient,
+    blocks: &Blocks,
+    owner_id: &str,
+    audience: Audience,
+    viewer_id: &str,
+) -> bool {
+    if owner_id == viewer_id {
+        return true;
+    }
+    if blocks.between(owner_id, viewer_id) {
+        return false;
+    }
+    match audience {
+        Audience::Everyone => true,
+        Audience::Contacts => are_contacts(client, owner_id, viewer_id).await,
+        Audience::Nobody => false,
+    }
+}
+
+pub(crate) fn hide_status(status: UserStatus) -> UserStatus {
+    UserStatus {
+        online: false,
+        profile_picture: None,
+        ..status
+    }
+}
+
+/// Profile of `user` with the online flag and avatar hidden unless `viewer_id` may see them
+pub(crate) async fn visible_status(
+    client: &prisma::PrismaClient,
+    blocks: &Blocks,
+    user: user_status::Data,
+    viewer_id: &str,
+) -> UserStatus {
+    let audience = audience_from_db(&user.who_can_see_status);
+    let owner_id = user.id.clone();
+    let status = to_user_status(user);
+    if allows(client, blocks, &owner_id, audience, viewer_id).await {
+        status
+    } else {
+        hide_status(status)
+    }
+}
+
+async fn get_privacy(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+) -> Json<PrivacySettings> {
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(session.user_id))
+        .select(user::select!({
+            who_can_start_chats
+            who_can_see_status
+        }))
+        .exec()
+        .await
+        .unwrap()
+        .unwrap();
+
+    Json(PrivacySettings {
+        who_can_start_chats: audience_from_db(&user.who_can_start_chats),
+        who_can_see_status: audience_from_db(&user.who_can_see_status),
+    })
+}
+
+async fn update_privacy(
+    State(AppState {
+        client,
+        message_sender,
+        blocks,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(settings): Json<PrivacySettings>,
+) -> Json<()> {
+    client
+        .user()
+        .update(
+            user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+            vec![
+                user::SetParam::SetWhoCanStartChats(audience_to_db(settings.who_can_start_chats)),
+                user::SetParam::SetWhoCanSeeStatus(audience_to_db(settings.who_can_see_status)),
+            ],
+        )
+        .exec()
+        .await
+        .unwrap();
+
+    // Everyone who could see the status gets the hidden version and the other way around
+    broadcast_profile(&client, &message_sender, &blocks, session.user_id).await;
+    Json(())
+}
+
+async fn get_blocked(
+    State(AppState { client, blocks, .. }): State<AppState>,
+    session: Session,
+) -> Json<Vec<UserStatus>> {
+    let blocked = client
+        .user()
+        .find_many(vec![user::WhereParam::BlockedBySome(vec![
+            block::WhereParam::BlockerId(StringFilter::Equals(session.user_id.clone())),
+        ])])
+        .order_by(user::username::order(prisma_client_rust::Direction::Asc))
+        .select(user_status::select())
+        .exec()
+        .await
+        .unwrap();
+
+    let mut statuses = Vec::with_capacity(blocked.len());
+    for user in blocked {
+        statuses.push(visible_status(&client, &blocks, user, &session.user_id).await);
+    }
+    Json(statuses)
+}
+
+/// Also removes the user from contacts
+async fn block_user(
+    State(AppState {
+        client,
+        message_sender,
+        blocks,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<BlockRequest>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    if request.user_id == session.user_id || request.user_id == DELETED_USER_ID {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Этого пользователя нельзя заблокировать!",
+        ));
+    }
+
+    client
+        .block()
+        .upsert(
+            block::UniqueWhereParam::BlockerIdBlockedIdEquals(
+                session.user_id.clone(),
+                request.user_id.clone(),
+            ),
+            (
+                user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                user::UniqueWhereParam::IdEquals(request.user_id.clone()),
+                vec![],
+            ),
+            vec![],
+        )
+        .exec()
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Такого пользователя не существует!"))?;
+    remove_contacts_between(&client, &session.user_id, &request.user_id).await;
+
+    blocks
+        .0
+        .write()
+        .unwrap()
+        .insert((session.user_id.clone(), request.user_id.clone()));
+
+    message_sender
+        .send(WsMessage {
+            recipient_ids: HashSet::from([session.user_id, request.user_id]),
+            data: WsMessageData::ContactsChanged,
+        })
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn unblock_user(
+    State(AppState { client, blocks, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<BlockRequest>,
+) -> Json<()> {
+    client
+        .block()
+        .delete_many(vec![
+            block::WhereParam::BlockerId(StringFilter::Equals(session.user_id.clone())),
+            block::WhereParam::BlockedId(StringFilter::Equals(request.user_id.clone())),
+        ])
+        .exec()
+        .await
+        .unwrap();
+
+    blocks
+        .0
+        .write()
+        .unwrap()
+        .remove(&(session.user_id, request.user_id));
+    Json(())
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/privacy", get(get_privacy).post(update_privacy))
+        .route("/blocked", get(get_blocked))
+        .route("/block", post(block_user))
+        .route("/unblock", post(unblock_user))
+}
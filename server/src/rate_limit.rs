@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, Session};
+
+/// Buckets are pruned once this many keys are tracked, so random keys can't exhaust memory
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket per key: allows bursts of `capacity` requests, refilled by one token every `refill`
+pub(crate) struct RateLimiter {
+    capacity: f64,
+    tokens_per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(capacity: u32, refill: Duration) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens_per_second: 1.0 / refill.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.tokens_per_second).min(self.capacity);
+        bucket.updated_at = now;
+    }
+
+    /// Takes a token for `key`, returns how long to wait if there are none left
+    pub(crate) fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_KEYS {
+            buckets.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.tokens < self.capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        self.refill(bucket, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.tokens_per_second,
+            ))
+        }
+    }
+}
+
+struct FailedLogins {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Locks a username out for exponentially longer periods after repeated failed logins
+pub(crate) struct LoginLockouts {
+    failures: Mutex<HashMap<String, FailedLogins>>,
+}
+
+impl LoginLockouts {
+    const FREE_ATTEMPTS: u32 = 3;
+    const FIRST_LOCKOUT: Duration = Duration::from_secs(30);
+    const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+    fn new() -> Self {
+        Self {
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long the username stays locked, if it is
+    pub(crate) fn check(&self, username: &str) -> Result<(), Duration> {
+        let failures = self.failures.lock().unwrap();
+        match failures
+            .get(username)
+            .and_then(|failed| failed.locked_until)
+        {
+            Some(locked_until) if locked_until > Instant::now() => {
+                Err(locked_until - Instant::now())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_failure(&self, username: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_KEYS {
+            let now = Instant::now();
+            failures.retain(|_, failed| failed.locked_until.is_some_and(|until| until > now));
+        }
+
+        let failed = failures.entry(username.to_owned()).or_insert(FailedLogins {
+            count: 0,
+            locked_until: None,
+        });
+        failed.count += 1;
+        if failed.count > Self::FREE_ATTEMPTS {
+            let doublings = (failed.count - Self::FREE_ATTEMPTS - 1).min(16);
+            let lockout = (Self::FIRST_LOCKOUT * 2u32.pow(doublings)).min(Self::MAX_LOCKOUT);
+            failed.locked_until = Some(Instant::now() + lockout);
+        }
+    }
+
+    pub(crate) fn record_success(&self, username: &str) {
+        self.failures.lock().unwrap().remove(username);
+    }
+}
+
+pub(crate) struct RateLimits {
+    pub(crate) auth_by_ip: RateLimiter,
+    pub(crate) messages_by_user: RateLimiter,
+    pub(crate) login_lockouts: LoginLockouts,
+}
+
+impl RateLimits {
+    pub(crate) fn new() -> Self {
+        Self {
+            auth_by_ip: RateLimiter::new(10, Duration::from_secs(6)),
+            messages_by_user: RateLimiter::new(20, Duration::from_millis(500)),
+            login_lockouts: LoginLockouts::new(),
+        }
+    }
+}
+
+/// `429 Too Many Requests` with a `Retry-After` header in whole seconds
+pub(crate) fn too_many_requests(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs() + 1;
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.to_string())],
+        format!("Слишком много попыток, повторите через {seconds} с."),
+    )
+        .into_response()
+}
+
+pub(crate) async fn limit_auth_by_ip(
+    State(AppState { rate_limits, .. }): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match rate_limits.auth_by_ip.check(&address.ip().to_string()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => too_many_requests(retry_after),
+    }
+}
+
+pub(crate) async fn limit_messages_by_user(
+    State(AppState { rate_limits, .. }): State<AppState>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    match rate_limits.messages_by_user.check(&session.user_id) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => too_many_requests(retry_after),
+    }
+}
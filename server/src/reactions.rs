@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use structs::requests::{React, WsMessageData, WsReaction};
+
+use crate::{
+    prisma::{self, chat, message, reaction, read_filters::StringFilter, user},
+    AppState, Session, WsMessage,
+};
+
+/// Reactions are emoji, not arbitrary text
+const MAX_EMOJI_LENGTH: usize = 8;
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+/// Asks for the emoji rather than the text presentation of a symbol
+const VARIATION_SELECTOR_16: char = '\u{FE0F}';
+const COMBINING_KEYCAP: char = '\u{20E3}';
+
+/// `Extended_Pictographic` of the Unicode emoji data, with the unassigned parts of the emoji
+/// blocks included so new emoji work without an update
+fn is_pictographic(c: char) -> bool {
+    matches!(c as u32,
+        0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139
+        | 0x2194..=0x2199 | 0x21A9..=0x21AA | 0x231A..=0x231B | 0x2328 | 0x2388 | 0x23CF
+        | 0x23E9..=0x23F3 | 0x23F8..=0x23FA | 0x24C2 | 0x25AA..=0x25AB | 0x25B6 | 0x25C0
+        | 0x25FB..=0x25FE | 0x2600..=0x27BF | 0x2934..=0x2935 | 0x2B05..=0x2B07
+        | 0x2B1B..=0x2B1C | 0x2B50 | 0x2B55 | 0x3030 | 0x303D | 0x3297 | 0x3299
+        | 0x1F000..=0x1F0FF | 0x1F10D..=0x1F10F | 0x1F12F | 0x1F16C..=0x1F171
+        | 0x1F17E..=0x1F17F | 0x1F18E | 0x1F191..=0x1F19A | 0x1F1AD..=0x1F1E5
+        | 0x1F201..=0x1F3FA | 0x1F400..=0x1FAFF | 0x1FC00..=0x1FFFD)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// Tag characters spell out subdivision flags like England's after a black flag
+fn is_tag(c: char) -> bool {
+    ('\u{E0020}'..='\u{E007F}').contains(&c)
+}
+
+/// A single emoji: a pictograph with its modifiers, a ZWJ sequence of those, a flag or a keycap
+fn is_emoji(emoji: &str) -> bool {
+    let chars: Vec<char> = emoji.chars().collect();
+    match chars.as_slice() {
+        [] => false,
+        ['0'..='9' | '#' | '*', rest @ ..] => {
+            matches!(
+                rest,
+                [COMBINING_KEYCAP] | [VARIATION_SELECTOR_16, COMBINING_KEYCAP]
+            )
+        }
+        [first, second] if is_regional_indicator(*first) => is_regional_indicator(*second),
+        pictographs => {
+            // Pictographs with their modifiers, joined by ZWJ
+            let mut expects_pictograph = true;
+            for &c in pictographs {
+                if expects_pictograph {
+                    if !is_pictographic(c) {
+                        return false;
+                    }
+                    expects_pictograph = false;
+                } else if c == ZERO_WIDTH_JOINER {
+                    expects_pictograph = true;
+                } else if !(is_skin_tone(c) || is_tag(c) || c == VARIATION_SELECTOR_16) {
+                    return false;
+                }
+            }
+            !expects_pictograph
+        }
+    }
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), (StatusCode, &'static str)> {
+    if emoji.chars().count() > MAX_EMOJI_LENGTH || !is_emoji(emoji) {
+        return Err((StatusCode::BAD_REQUEST, "Недопустимая реакция!"));
+    }
+    Ok(())
+}
+
+message::select!(reacted_message {
+    id
+    chat: select {
+        id
+        members: select {
+            id
+        }
+    }
+});
+
+/// Finds the message if the user is a member of its chat
+async fn find_message(
+    client: &prisma::PrismaClient,
+    message_id: String,
+    user_id: String,
+) -> Result<reacted_message::Data, (StatusCode, &'static str)> {
+    client
+        .message()
+        .find_first(vec![
+            message::WhereParam::Id(StringFilter::Equals(message_id)),
+            message::WhereParam::ChatIs(vec![chat::WhereParam::MembersSome(vec![
+                user::WhereParam::Id(StringFilter::Equals(user_id)),
+            ])]),
+        ])
+        .select(reacted_message::select())
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Сообщение не найдено!"))
+}
+
+fn reaction_event(
+    kind: fn(WsReaction) -> WsMessageData,
+    message: reacted_message::Data,
+    user_id: String,
+    emoji: String,
+) -> WsMessage {
+    WsMessage {
+        recipient_ids: HashSet::from_iter(message.chat.members.into_iter().map(|member| member.id)),
+        data: kind(WsReaction {
+            chat_id: message.chat.id,
+            message_id: message.id,
+            user_id,
+            emoji,
+        }),
+    }
+}
+
+async fn react(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<React>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    validate_emoji(&request.emoji)?;
+    let message = find_message(&client, request.message_id, session.user_id.clone()).await?;
+
+    client
+        .reaction()
+        .upsert(
+            reaction::UniqueWhereParam::MessageIdUserIdEmojiEquals(
+                message.id.clone(),
+                session.user_id.clone(),
+                request.emoji.clone(),
+            ),
+            (
+                message::UniqueWhereParam::IdEquals(message.id.clone()),
+                user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                request.emoji.clone(),
+                vec![],
+            ),
+            vec![],
+        )
+        .exec()
+        .await
+        .unwrap();
+
+    message_sender
+        .send(reaction_event(
+            WsMessageData::ReactionAdded,
+            message,
+            session.user_id,
+            request.emoji,
+        ))
+        .unwrap();
+    Ok(Json(()))
+}
+
+async fn unreact(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<React>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    let message = find_message(&client, request.message_id, session.user_id.clone()).await?;
+
+    client
+        .reaction()
+        .delete_many(vec![
+            reaction::WhereParam::MessageId(StringFilter::Equals(message.id.clone())),
+            reaction::WhereParam::UserId(StringFilter::Equals(session.user_id.clone())),
+            reaction::WhereParam::Emoji(StringFilter::Equals(request.emoji.clone())),
+        ])
+        .exec()
+        .await
+        .unwrap();
+
+    message_sender
+        .send(reaction_event(
+            WsMessageData::ReactionRemoved,
+            message,
+            session.user_id,
+            request.emoji,
+        ))
+        .unwrap();
+    Ok(Json(()))
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/react", post(react))
+        .route("/unreact", post(unreact))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_emoji() {
+        for emoji in [
+            "👍",
+            "❤️",
+            "🔥",
+            "👍🏽",
+            "👨‍👩‍👧",
+            "🏳️‍🌈",
+            "🇷🇺",
+            "1️⃣",
+            "#⃣",
+            "🏴\u{E0067}\u{E0062}\u{E0065}\u{E006E}\u{E0067}\u{E007F}",
+            "☕",
+            "©️",
+        ] {
+            assert!(validate_emoji(emoji).is_ok(), "{emoji} is an emoji");
+        }
+    }
+
+    #[test]
+    fn rejects_text() {
+        for text in [
+            "",
+            "!!!",
+            "123",
+            "1",
+            "$$",
+            "a",
+            "да",
+            " ",
+            "\u{7}",
+            "\u{200D}",
+            "\u{FE0F}",
+            "🏽",
+            "🇷",
+            "🇷🇺🇷",
+            "👍a",
+            "👍 ",
+            "👍\u{200D}",
+            "👍\u{200D}\u{200D}👍",
+            "👍👍",
+            "👍👍👍👍👍👍👍👍👍",
+        ] {
+            assert!(validate_emoji(text).is_err(), "{text:?} isn't an emoji");
+        }
+    }
+}
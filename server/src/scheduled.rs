@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use prisma_client_rust::Direction;
+use structs::requests::{
+    CancelScheduledMessage, CreateMessage, EditScheduledMessage, ScheduledMessage,
+};
+
+use crate::{
+    e2ee, option_vec,
+    prisma::{
+        chat, message,
+        read_filters::{DateTimeFilter, StringFilter},
+        scheduled_message, user,
+    },
+    user::{check_can_write, check_reply, send_message, validate_content},
+    AppState, Session,
+};
+
+/// The scheduler looks for due messages at least this often, even if nothing woke it up
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+const MAX_DAYS_AHEAD: i64 = 365;
+
+fn validate_time(scheduled_for: DateTime<Utc>) -> Result<(), (StatusCode, &'static str)> {
+    let now = Utc::now();
+    if scheduled_for <= now {
+        return Err((StatusCode::BAD_REQUEST, "Это время уже прошло!"));
+    }
+    if scheduled_for - now > chrono::Duration::days(MAX_DAYS_AHEAD) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Сообщение можно запланировать не больше чем на год вперёд!",
+        ));
+    }
+    Ok(())
+}
+
+fn to_scheduled_message(scheduled: scheduled_message::Data) -> ScheduledMessage {
+    ScheduledMessage {
+        id: scheduled.id,
+        chat_id: scheduled.chat_id,
+        content: scheduled.content,
+        reply_to: scheduled.reply_id,
+        scheduled_for: scheduled.scheduled_for.into(),
+    }
+}
+
+/// Stores a message sent with `scheduled_for`, returns the id of the scheduled message
+pub(crate) async fn schedule(
+    state: &AppState,
+    sender_id: String,
+    message: CreateMessage,
+    scheduled_for: DateTime<Utc>,
+) -> Result<String, (StatusCode, &'static str)> {
+    if message.voice_note_id.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Голосовые сообщения нельзя запланировать!",
+        ));
+    }
+    let content = validate_content(&message.content, &state.config)?;
+    validate_time(scheduled_for)?;
+    check_can_write(&state.client, &state.blocks, &message.chat_id, &sender_id).await?;
+    check_reply(
+        &state.client,
+        &message.chat_id,
+        message.reply_to_id.as_deref(),
+    )
+    .await?;
+    // Content is only encrypted right before sending
+    if message.key_id.is_some() || e2ee::is_encrypted(&state.client, &message.chat_id).await {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Сообщения в зашифрованных чатах нельзя запланировать!",
+        ));
+    }
+
+    let scheduled = state
+        .client
+        .scheduled_message()
+        .create(
+            chat::UniqueWhereParam::IdEquals(message.chat_id),
+            user::UniqueWhereParam::IdEquals(sender_id),
+            content,
+            scheduled_for.into(),
+            option_vec![message.reply_to_id.map(|id| {
+                scheduled_message::SetParam::ConnectReplyTo(message::UniqueWhereParam::IdEquals(id))
+            })],
+        )
+        .exec()
+        .await
+        .unwrap();
+    state.scheduler.notify_one();
+    Ok(scheduled.id)
+}
+
+async fn get_scheduled(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Path(chat_id): Path<String>,
+) -> Json<Vec<ScheduledMessage>> {
+    let scheduled = client
+        .scheduled_message()
+        .find_many(vec![
+            scheduled_message::WhereParam::ChatId(StringFilter::Equals(chat_id)),
+            scheduled_message::WhereParam::UserId(StringFilter::Equals(session.user_id)),
+        ])
+        .order_by(scheduled_message::scheduled_for::order(Direction::Asc))
+        .exec()
+        .await
+        .unwrap();
+    Json(scheduled.into_iter().map(to_scheduled_message).collect())
+}
+
+async fn edit_scheduled(
+    State(AppState {
+        client,
+        config,
+        scheduler,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(request): Json<EditScheduledMessage>,
+) -> Result<Json<ScheduledMessage>, (StatusCode, &'static str)> {
+    let content = validate_content(&request.content, &config)?;
+    validate_time(request.scheduled_for)?;
+
+    let updated = client
+        .scheduled_message()
+        .update_many(
+            vec![
+                scheduled_message::WhereParam::Id(StringFilter::Equals(request.id.clone())),
+                scheduled_message::WhereParam::UserId(StringFilter::Equals(session.user_id)),
+            ],
+            vec![
+                scheduled_message::SetParam::SetContent(content),
+                scheduled_message::SetParam::SetScheduledFor(request.scheduled_for.into()),
+            ],
+        )
+        .exec()
+        .await
+        .unwrap();
+    // Already sent or cancelled
+    if updated == 0 {
+        return Err((StatusCode::NOT_FOUND, "Сообщение не найдено!"));
+    }
+    scheduler.notify_one();
+
+    let scheduled = client
+        .scheduled_message()
+        .find_unique(scheduled_message::UniqueWhereParam::IdEquals(request.id))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Сообщение не найдено!"))?;
+    Ok(Json(to_scheduled_message(scheduled)))
+}
+
+async fn cancel_scheduled(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<CancelScheduledMessage>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    let deleted = client
+        .scheduled_message()
+        .delete_many(vec![
+            scheduled_message::WhereParam::Id(StringFilter::Equals(request.id)),
+            scheduled_message::WhereParam::UserId(StringFilter::Equals(session.user_id)),
+        ])
+        .exec()
+        .await
+        .unwrap();
+    if deleted == 0 {
+        return Err((StatusCode::NOT_FOUND, "Сообщение не найдено!"));
+    }
+    Ok(Json(()))
+}
+
+/// Sends every message that is due, messages to chats the sender can't write to anymore are
+/// dropped and logged
+async fn deliver_due(state: &AppState) {
+    let due = state
+        .client
+        .scheduled_message()
+        .find_many(vec![scheduled_message::WhereParam::ScheduledFor(
+            DateTimeFilter::Lte(Utc::now().into()),
+        )])
+        .order_by(scheduled_message::scheduled_for::order(Direction::Asc))
+        .exec()
+        .await
+        .unwrap();
+
+    for scheduled in due {
+        // Deleting first claims the message, so a cancel at the same moment can't race the send
+        let claimed = state
+            .client
+            .scheduled_message()
+            .delete_many(vec![scheduled_message::WhereParam::Id(
+                StringFilter::Equals(scheduled.id.clone()),
+            )])
+            .exec()
+            .await
+            .unwrap();
+        // Banned after scheduling, `set_disabled` may not have deleted it yet
+        if claimed == 0 || state.disabled_users.contains(&scheduled.user_id) {
+            continue;
+        }
+        let sent = send_message(
+            state.clone(),
+            scheduled.user_id.clone(),
+            CreateMessage {
+                chat_id: scheduled.chat_id.clone(),
+                content: scheduled.content,
+                reply_to_id: scheduled.reply_id,
+                voice_note_id: None,
+                scheduled_for: None,
+                key_id: None,
+            },
+        )
+        .await;
+        if let Err((status, reason)) = sent {
+            eprintln!(
+                "Scheduled message {} of {} to chat {} dropped: {status} {reason}",
+                scheduled.id, scheduled.user_id, scheduled.chat_id
+            );
+        }
+    }
+}
+
+/// Runs for the lifetime of the server, woken early through `AppState::scheduler`
+pub(crate) async fn run(state: AppState) {
+    loop {
+        deliver_due(&state).await;
+
+        let next = state
+            .client
+            .scheduled_message()
+            .find_first(vec![])
+            .order_by(scheduled_message::scheduled_for::order(Direction::Asc))
+            .exec()
+            .await
+            .unwrap();
+        let sleep = next.map_or(MAX_SLEEP, |next| {
+            // Negative when a message became due while the others were sent
+            (next.scheduled_for.with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(MAX_SLEEP)
+        });
+
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = state.scheduler.notified() => {}
+        }
+    }
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/scheduled/:chat_id", get(get_scheduled))
+        .route("/edit_scheduled", post(edit_scheduled))
+        .route("/cancel_scheduled", post(cancel_scheduled))
+}
@@ -0,0 +1,116 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axum::{extract::State, routing::get, Router};
+use chrono::Utc;
+
+use crate::{
+    prisma::{self, read_filters::DateTimeFilter, session},
+    two_factor, AppState,
+};
+
+/// Counters since the server started, served at `/metrics`
+#[derive(Default)]
+pub(crate) struct SessionMetrics {
+    /// By the periodic job and by `/refresh` finding an expired one
+    purged: AtomicU64,
+    purge_runs: AtomicU64,
+    renewed: AtomicU64,
+}
+
+impl SessionMetrics {
+    pub(crate) fn record_purged(&self, count: u64) {
+        self.purged.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_renewed(&self) {
+        self.renewed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn purge_expired(client: &prisma::PrismaClient) -> u64 {
+    client
+        .session()
+        .delete_many(vec![session::WhereParam::ExpiresAt(DateTimeFilter::Lt(
+            Utc::now().into(),
+        ))])
+        .exec()
+        .await
+        .unwrap() as u64
+}
+
+/// Runs for the lifetime of the server, the first purge happens at startup. Logins waiting for
+/// a second factor are cleaned up along with the sessions
+pub(crate) async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.session_purge_interval_secs.max(1),
+    ));
+    loop {
+        interval.tick().await;
+        let purged = purge_expired(&state.client).await;
+        state.session_metrics.record_purged(purged);
+        two_factor::purge_expired_logins(&state.client).await;
+        state
+            .session_metrics
+            .purge_runs
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus text format
+async fn metrics(
+    State(AppState {
+        client,
+        session_metrics,
+        ..
+    }): State<AppState>,
+) -> String {
+    let active = client
+        .session()
+        .count(vec![session::WhereParam::ExpiresAt(DateTimeFilter::Gte(
+            Utc::now().into(),
+        ))])
+        .exec()
+        .await
+        .unwrap();
+
+    let mut output = String::new();
+    for (name, kind, help, value) in [
+        (
+            "taco_sessions_purged_total",
+            "counter",
+            "Expired sessions deleted",
+            session_metrics.purged.load(Ordering::Relaxed),
+        ),
+        (
+            "taco_session_purge_runs_total",
+            "counter",
+            "Runs of the expired session cleanup",
+            session_metrics.purge_runs.load(Ordering::Relaxed),
+        ),
+        (
+            "taco_session_renewals_total",
+            "counter",
+            "Sessions extended by a refresh",
+            session_metrics.renewed.load(Ordering::Relaxed),
+        ),
+        (
+            "taco_sessions_active",
+            "gauge",
+            "Sessions that haven't expired",
+            active as u64,
+        ),
+    ] {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} {kind}");
+        let _ = writeln!(output, "{name} {value}");
+    }
+    output
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
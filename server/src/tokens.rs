@@ -0,0 +1,113 @@
+use std::env;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const ACCESS_TOKEN_LIFETIME_MINUTES: i64 = 15;
+
+/// Who an access token was issued to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Claims {
+    pub(crate) session_id: String,
+    pub(crate) user_id: String,
+}
+
+/// Issues and checks access tokens: `<session id>.<user id>.<expiry>.<signature>`, so requests
+/// are authenticated without looking the session up. Revoking a session takes effect once its
+/// current access token expires
+pub(crate) struct TokenSigner {
+    secret: Vec<u8>,
+}
+
+impl TokenSigner {
+    /// `TACO_TOKEN_SECRET` keeps tokens valid across restarts, otherwise a random secret is used
+    /// and clients refresh their tokens after a restart
+    pub(crate) fn from_env() -> Self {
+        let secret = match env::var("TACO_TOKEN_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+                .iter()
+                .flat_map(|uuid| *uuid.as_bytes())
+                .collect(),
+        };
+        Self { secret }
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Returns the token and when it expires
+    pub(crate) fn sign(&self, session_id: &str, user_id: &str) -> (String, DateTime<Utc>) {
+        let expires_at = Utc::now() + Duration::minutes(ACCESS_TOKEN_LIFETIME_MINUTES);
+        let payload = format!("{session_id}.{user_id}.{}", expires_at.timestamp());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        (format!("{payload}.{signature}"), expires_at)
+    }
+
+    /// `None` if the token is malformed, forged or expired
+    pub(crate) fn verify(&self, token: &str) -> Option<Claims> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+
+        // Legacy user ids are old usernames and may contain dots, session ids and expiries don't
+        let (session_id, rest) = payload.split_once('.')?;
+        let (user_id, expires_at) = rest.rsplit_once('.')?;
+        let expires_at = Utc.timestamp_opt(expires_at.parse().ok()?, 0).single()?;
+        if expires_at <= Utc::now() {
+            return None;
+        }
+        Some(Claims {
+            session_id: session_id.into(),
+            user_id: user_id.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> TokenSigner {
+        TokenSigner {
+            secret: b"secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn round_trips_user_ids_with_dots() {
+        let signer = signer();
+        for user_id in ["2f1c9a4e-0b7d-4c1e-9a55-5e0f3b8d7c21", "john.doe", "a..b."] {
+            let (token, _) = signer.sign("session", user_id);
+            assert_eq!(
+                signer.verify(&token),
+                Some(Claims {
+                    session_id: "session".into(),
+                    user_id: user_id.into(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_forged_tokens() {
+        let signer = signer();
+        let (token, _) = signer.sign("session", "john.doe");
+        let forged = token.replacen("john.doe", "john.dox", 1);
+        assert_eq!(signer.verify(&forged), None);
+        assert_eq!(signer.verify("session.john.doe"), None);
+
+        let other = TokenSigner {
+            secret: b"other".to_vec(),
+        };
+        assert_eq!(other.verify(&token), None);
+    }
+}
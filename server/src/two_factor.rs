@@ -0,0 +1,426 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+use structs::requests::{
+    ConfirmTotp, DisableTotp, RecoveryCodes, Session as LoggedIn, TotpSetup, TotpStatus,
+    TwoFactorLogin,
+};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{
+    accounts::hash,
+    auth::{check_password, create_session},
+    prisma::{
+        self, pending_login,
+        read_filters::{
+            DateTimeFilter, IntFilter, IntNullableFilter, StringFilter, StringNullableFilter,
+        },
+        recovery_code, user,
+    },
+    rate_limit::{self, too_many_requests},
+    AppState, Session,
+};
+
+/// Shown next to the account in authenticator apps
+const ISSUER: &str = "Taco";
+const STEP_SECS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const LOGIN_TOKEN_LIFETIME_MINUTES: i64 = 5;
+/// Wrong codes allowed per login token, the password has to be entered again after that
+const MAX_CODE_ATTEMPTS: i32 = 5;
+
+fn totp(secret: &str, username: &str) -> TOTP {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .expect("secrets are stored base32 encoded");
+    // ':' separates the issuer from the account name in the provisioning URI
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        secret,
+        Some(ISSUER.into()),
+        username.replace(':', "_"),
+    )
+    .expect("generated secrets are long enough")
+}
+
+/// Returns the time step the code belongs to, a step of clock drift either way is allowed.
+/// Steps up to `last_step` are skipped, so every code works once
+fn check_code(totp: &TOTP, code: &str, last_step: Option<i32>) -> Option<i32> {
+    // Apps show the code as "123 456"
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = Utc::now().timestamp() as u64 / STEP_SECS;
+    (current - 1..=current + 1)
+        .filter(|&step| !last_step.is_some_and(|last| step <= last as u64))
+        .find(|&step| totp.check(&code, step * STEP_SECS))
+        .map(|step| step as i32)
+}
+
+/// Marks the step of an accepted code as used, `false` if a request with the same code got there
+/// first
+async fn claim_step(client: &prisma::PrismaClient, user_id: &str, step: i32) -> bool {
+    client
+        .user()
+        .update_many(
+            vec![
+                user::WhereParam::Id(StringFilter::Equals(user_id.to_owned())),
+                user::WhereParam::Or(vec![
+                    user::WhereParam::TotpLastStep(IntNullableFilter::Equals(None)),
+                    user::WhereParam::TotpLastStep(IntNullableFilter::Lt(step)),
+                ]),
+            ],
+            vec![user::SetParam::SetTotpLastStep(Some(step))],
+        )
+        .exec()
+        .await
+        .unwrap()
+        > 0
+}
+
+/// Case, spaces and dashes don't matter when a recovery code is typed in
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Replaces the recovery codes of the user, returns the new ones
+async fn replace_recovery_codes(client: &prisma::PrismaClient, user_id: &str) -> Vec<String> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let random = uuid::Uuid::new_v4().simple().to_string();
+            format!("{}-{}", &random[..5], &random[5..10])
+        })
+        .collect();
+
+    client
+        .recovery_code()
+        .delete_many(vec![recovery_code::WhereParam::UserId(
+            StringFilter::Equals(user_id.to_owned()),
+        )])
+        .exec()
+        .await
+        .unwrap();
+    client
+        ._batch(
+            codes
+                .iter()
+                .map(|code| {
+                    client.recovery_code().create(
+                        user::UniqueWhereParam::IdEquals(user_id.to_owned()),
+                        hash(normalize_recovery_code(code)),
+                        vec![],
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+    codes
+}
+
+/// Uses the recovery code up, `false` if the user doesn't have it
+async fn use_recovery_code(client: &prisma::PrismaClient, user_id: &str, code: &str) -> bool {
+    client
+        .recovery_code()
+        .delete_many(vec![
+            recovery_code::WhereParam::UserId(StringFilter::Equals(user_id.to_owned())),
+            recovery_code::WhereParam::CodeHash(StringFilter::Equals(hash(
+                normalize_recovery_code(code),
+            ))),
+        ])
+        .exec()
+        .await
+        .unwrap()
+        > 0
+}
+
+/// Returns the token the client sends to `/login/totp` along with the code
+pub(crate) async fn start_login(client: &prisma::PrismaClient, user_id: String) -> String {
+    let login_token = uuid::Uuid::new_v4().simple().to_string();
+    client
+        .pending_login()
+        .create(
+            hash(&login_token),
+            user::UniqueWhereParam::IdEquals(user_id),
+            (Utc::now() + Duration::minutes(LOGIN_TOKEN_LIFETIME_MINUTES)).into(),
+            vec![],
+        )
+        .exec()
+        .await
+        .unwrap();
+    login_token
+}
+
+pub(crate) async fn purge_expired_logins(client: &prisma::PrismaClient) {
+    client
+        .pending_login()
+        .delete_many(vec![pending_login::WhereParam::ExpiresAt(
+            DateTimeFilter::Lt(Utc::now().into()),
+        )])
+        .exec()
+        .await
+        .unwrap();
+}
+
+async fn log_in_with_code(
+    State(AppState {
+        client,
+        rate_limits,
+        tokens,
+        ..
+    }): State<AppState>,
+    Json(request): Json<TwoFactorLogin>,
+) -> Result<Json<LoggedIn>, Response> {
+    const EXPIRED: (StatusCode, &str) = (
+        StatusCode::UNAUTHORIZED,
+        "Время на ввод кода истекло, войдите заново",
+    );
+
+    let token_hash = hash(&request.login_token);
+    let pending = client
+        .pending_login()
+        .find_unique(pending_login::UniqueWhereParam::TokenHashEquals(
+            token_hash.clone(),
+        ))
+        .include(pending_login::include!({
+            user: select { id username totp_secret totp_last_step }
+        }))
+        .exec()
+        .await
+        .unwrap()
+        .filter(|pending| pending.expires_at > Utc::now())
+        .ok_or_else(|| EXPIRED.into_response())?;
+    let user = pending.user;
+    rate_limits
+        .login_lockouts
+        .check(&user.username)
+        .map_err(too_many_requests)?;
+    // Turned off since the password was checked
+    let Some(secret) = user.totp_secret else {
+        return Err(EXPIRED.into_response());
+    };
+
+    let accepted = match check_code(
+        &totp(&secret, &user.username),
+        &request.code,
+        user.totp_last_step,
+    ) {
+        Some(step) => claim_step(&client, &user.id, step).await,
+        None => use_recovery_code(&client, &user.id, &request.code).await,
+    };
+    if !accepted {
+        rate_limits.login_lockouts.record_failure(&user.username);
+        client
+            ._batch((
+                client.pending_login().update_many(
+                    vec![pending_login::WhereParam::TokenHash(StringFilter::Equals(
+                        token_hash.clone(),
+                    ))],
+                    vec![pending_login::SetParam::IncrementAttempts(1)],
+                ),
+                client.pending_login().delete_many(vec![
+                    pending_login::WhereParam::TokenHash(StringFilter::Equals(token_hash)),
+                    pending_login::WhereParam::Attempts(IntFilter::Gte(MAX_CODE_ATTEMPTS)),
+                ]),
+            ))
+            .await
+            .unwrap();
+        return Err((StatusCode::FORBIDDEN, "Неверный код!").into_response());
+    }
+
+    // Deleting claims the token, so it can't be used for two sessions
+    let claimed = client
+        .pending_login()
+        .delete_many(vec![pending_login::WhereParam::TokenHash(
+            StringFilter::Equals(token_hash),
+        )])
+        .exec()
+        .await
+        .unwrap();
+    if claimed == 0 {
+        return Err(EXPIRED.into_response());
+    }
+    rate_limits.login_lockouts.record_success(&user.username);
+
+    let (session_id, tokens) = create_session(&client, &tokens, user.id.clone()).await;
+    Ok(Json(LoggedIn {
+        user_id: user.id,
+        username: user.username,
+        session_id,
+        tokens,
+    }))
+}
+
+async fn get_status(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+) -> Json<TotpStatus> {
+    let enabled = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(session.user_id.clone()))
+        .select(user::select!({ totp_secret }))
+        .exec()
+        .await
+        .unwrap()
+        .is_some_and(|user| user.totp_secret.is_some());
+    let recovery_codes_left = client
+        .recovery_code()
+        .count(vec![recovery_code::WhereParam::UserId(
+            StringFilter::Equals(session.user_id),
+        )])
+        .exec()
+        .await
+        .unwrap();
+    Json(TotpStatus {
+        enabled,
+        recovery_codes_left: recovery_codes_left as usize,
+    })
+}
+
+/// Starts over if an earlier setup wasn't confirmed
+async fn set_up(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+) -> Result<Json<TotpSetup>, (StatusCode, &'static str)> {
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(session.user_id.clone()))
+        .select(user::select!({ username totp_secret }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Пользователь не найден"))?;
+    if user.totp_secret.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Двухфакторная аутентификация уже включена!",
+        ));
+    }
+
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let provisioning_uri = totp(&secret, &user.username).get_url();
+    client
+        .user()
+        .update(
+            user::UniqueWhereParam::IdEquals(session.user_id),
+            vec![user::SetParam::SetPendingTotpSecret(Some(secret.clone()))],
+        )
+        .exec()
+        .await
+        .unwrap();
+    Ok(Json(TotpSetup {
+        provisioning_uri,
+        secret,
+    }))
+}
+
+/// Turns two-factor login on once the app shows the right code
+async fn confirm(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<ConfirmTotp>,
+) -> Result<Json<RecoveryCodes>, (StatusCode, &'static str)> {
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(session.user_id.clone()))
+        .select(user::select!({ username totp_secret pending_totp_secret }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Пользователь не найден"))?;
+    if user.totp_secret.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Двухфакторная аутентификация уже включена!",
+        ));
+    }
+    let secret = user
+        .pending_totp_secret
+        .ok_or((StatusCode::BAD_REQUEST, "Сначала начните настройку!"))?;
+    let step = check_code(&totp(&secret, &user.username), &request.code, None)
+        .ok_or((StatusCode::FORBIDDEN, "Неверный код!"))?;
+
+    // Matching the secret too, the setup may have been started over in the meantime
+    let enabled = client
+        .user()
+        .update_many(
+            vec![
+                user::WhereParam::Id(StringFilter::Equals(session.user_id.clone())),
+                user::WhereParam::PendingTotpSecret(StringNullableFilter::Equals(Some(
+                    secret.clone(),
+                ))),
+            ],
+            vec![
+                user::SetParam::SetTotpSecret(Some(secret)),
+                user::SetParam::SetPendingTotpSecret(None),
+                user::SetParam::SetTotpLastStep(Some(step)),
+            ],
+        )
+        .exec()
+        .await
+        .unwrap();
+    if enabled == 0 {
+        return Err((StatusCode::CONFLICT, "Настройка была начата заново!"));
+    }
+
+    Ok(Json(RecoveryCodes {
+        codes: replace_recovery_codes(&client, &session.user_id).await,
+    }))
+}
+
+/// Recovery codes and logins waiting for a code are dropped as well
+async fn disable(
+    State(AppState { client, .. }): State<AppState>,
+    session: Session,
+    Json(request): Json<DisableTotp>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let user_id = check_password(&client, session.user_id, request.password).await?;
+
+    client
+        ._batch((
+            client.user().update(
+                user::UniqueWhereParam::IdEquals(user_id.clone()),
+                vec![
+                    user::SetParam::SetTotpSecret(None),
+                    user::SetParam::SetPendingTotpSecret(None),
+                    user::SetParam::SetTotpLastStep(None),
+                ],
+            ),
+            client
+                .recovery_code()
+                .delete_many(vec![recovery_code::WhereParam::UserId(
+                    StringFilter::Equals(user_id.clone()),
+                )]),
+            client
+                .pending_login()
+                .delete_many(vec![pending_login::WhereParam::UserId(
+                    StringFilter::Equals(user_id),
+                )]),
+        ))
+        .await
+        .unwrap();
+    Ok(Json(()))
+}
+
+pub(crate) fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/login/totp", post(log_in_with_code))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            rate_limit::limit_auth_by_ip,
+        ))
+        .route("/totp", get(get_status))
+        .route("/totp/setup", post(set_up))
+        .route("/totp/confirm", post(confirm))
+        .route("/totp/disable", post(disable))
+}
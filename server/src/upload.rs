@@ -1,7 +1,10 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use axum::{
-    extract::{DefaultBodyLimit, Multipart},
+    extract::{DefaultBodyLimit, Multipart, State},
     http::StatusCode,
     routing::post,
     Router,
@@ -10,7 +13,33 @@ use axum::{
 use tokio::{fs::File, io::AsyncWriteExt};
 use tower_http::services::ServeDir;
 
-use crate::AppState;
+use chrono::Utc;
+
+use crate::{
+    prisma::{
+        self,
+        read_filters::{DateTimeFilter, StringFilter},
+        user, voice_note,
+    },
+    AppState, Session,
+};
+
+const KB: usize = 1024;
+const MB: usize = KB * 1024;
+const FILE_SIZE_LIMIT: usize = 25 * MB;
+/// Opus at voice bitrates stays far below this for the longest allowed recordings
+const VOICE_SIZE_LIMIT: usize = 4 * MB;
+const MAX_WAVEFORM_LENGTH: usize = 100;
+/// Ogg Opus counts granule positions at 48 kHz whatever the input rate was
+const OPUS_GRANULE_RATE: u64 = 48_000;
+/// Notes no message refers to are deleted once they are this old, sending one right after
+/// uploading it never races the purge
+const UNSENT_VOICE_NOTE_TTL: Duration = Duration::from_secs(60 * 60);
+const VOICE_NOTE_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn voice_note_path(id: &str) -> PathBuf {
+    Path::new("content").join(format!("voice-{id}.ogg"))
+}
 
 async fn upload_picture(mut multipart: Multipart) -> Result<String, (StatusCode, String)> {
     let no_file_field = Err((StatusCode::BAD_REQUEST, "no 'file' form field".into()));
@@ -37,13 +66,159 @@ async fn upload_picture(mut multipart: Multipart) -> Result<String, (StatusCode,
     Ok(file_id)
 }
 
-pub(crate) fn router() -> Router<AppState> {
-    const KB: usize = 1024;
-    const MB: usize = KB * 1024;
-    const FILE_SIZE_LIMIT: usize = 25 * MB;
+/// Length of an Ogg Opus stream, `None` if it isn't one
+fn ogg_opus_duration(bytes: &[u8]) -> Option<Duration> {
+    let mut offset = 0;
+    let mut pre_skip = None;
+    let mut last_granule = 0;
+    while offset < bytes.len() {
+        let header = bytes.get(offset..offset + 27)?;
+        if &header[..4] != b"OggS" || header[4] != 0 {
+            return None;
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().ok()?);
+        let segment_count = header[26] as usize;
+        let segments = bytes.get(offset + 27..offset + 27 + segment_count)?;
+        let body_start = offset + 27 + segment_count;
+        let body_length: usize = segments.iter().map(|&length| length as usize).sum();
+        let body = bytes.get(body_start..body_start + body_length)?;
+
+        if pre_skip.is_none() {
+            // The first page holds just the identification header
+            if body.len() < 19 || &body[..8] != b"OpusHead" {
+                return None;
+            }
+            pre_skip = Some(u16::from_le_bytes([body[10], body[11]]) as u64);
+        } else if granule != u64::MAX {
+            // All ones means no packet ends on the page
+            last_granule = granule;
+        }
+        offset = body_start + body_length;
+    }
+    // A forged granule position could overflow, such a stream isn't a real recording
+    let samples = last_granule.checked_sub(pre_skip?)?;
+    Some(Duration::from_millis(
+        samples.checked_mul(1000)? / OPUS_GRANULE_RATE,
+    ))
+}
+
+/// Takes the recording as `file` and its levels as `waveform`, returns the id to send it with
+async fn upload_voice(
+    State(AppState { client, config, .. }): State<AppState>,
+    session: Session,
+    mut multipart: Multipart,
+) -> Result<String, (StatusCode, String)> {
+    let mut file = None;
+    let mut waveform = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().map(ToOwned::to_owned);
+        let bytes = field.bytes().await.map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "file too big, max size is 4mb".to_string(),
+            )
+        })?;
+        match name.as_deref() {
+            Some("file") => file = Some(bytes),
+            Some("waveform") => waveform = Some(bytes),
+            _ => {}
+        }
+    }
+    let (Some(file), Some(waveform)) = (file, waveform) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "no 'file' or 'waveform' form field".into(),
+        ));
+    };
 
+    if file.len() > VOICE_SIZE_LIMIT {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "file too big, max size is 4mb".into(),
+        ));
+    }
+    if waveform.is_empty() || waveform.len() > MAX_WAVEFORM_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "invalid waveform".into()));
+    }
+    let duration = ogg_opus_duration(&file).ok_or((
+        StatusCode::BAD_REQUEST,
+        "Поддерживаются только записи Ogg Opus!".to_string(),
+    ))?;
+    let max_duration = Duration::from_secs(config.max_voice_duration_secs.into());
+    if duration.is_zero() || duration > max_duration {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Голосовое сообщение может длиться до {} секунд!",
+                config.max_voice_duration_secs
+            ),
+        ));
+    }
+
+    let voice_note = client
+        .voice_note()
+        .create(
+            user::UniqueWhereParam::IdEquals(session.user_id),
+            duration.as_millis() as i32,
+            waveform.to_vec(),
+            vec![],
+        )
+        .exec()
+        .await
+        .unwrap();
+    let mut output = File::create(voice_note_path(&voice_note.id)).await.unwrap();
+    output.write_all(&file).await.unwrap();
+    Ok(voice_note.id)
+}
+
+/// Deletes voice notes that were uploaded but never sent, or whose messages are all gone, along
+/// with their files
+async fn purge_unsent_voice_notes(client: &prisma::PrismaClient) {
+    let cutoff = Utc::now() - chrono::Duration::from_std(UNSENT_VOICE_NOTE_TTL).unwrap();
+    let unsent = client
+        .voice_note()
+        .find_many(vec![
+            voice_note::WhereParam::MessagesNone(vec![]),
+            voice_note::WhereParam::CreatedAt(DateTimeFilter::Lt(cutoff.into())),
+        ])
+        .select(voice_note::select!({ id }))
+        .exec()
+        .await
+        .unwrap();
+    for voice_note in unsent {
+        // Checked again in case a forward picked it up in the meantime
+        let deleted = client
+            .voice_note()
+            .delete_many(vec![
+                voice_note::WhereParam::Id(StringFilter::Equals(voice_note.id.clone())),
+                voice_note::WhereParam::MessagesNone(vec![]),
+            ])
+            .exec()
+            .await
+            .unwrap();
+        if deleted > 0 {
+            let _ = tokio::fs::remove_file(voice_note_path(&voice_note.id)).await;
+        }
+    }
+}
+
+/// Runs for the lifetime of the server, the first purge happens at startup
+pub(crate) async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(VOICE_NOTE_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        purge_unsent_voice_notes(&state.client).await;
+    }
+}
+
+pub(crate) fn router() -> Router<AppState> {
     Router::new()
         .route("/upload_picture", post(upload_picture))
+        // Stops oversized recordings before they are buffered, with room for the waveform
+        .route(
+            "/upload_voice",
+            post(upload_voice).layer(DefaultBodyLimit::max(VOICE_SIZE_LIMIT + KB)),
+        )
         .nest_service("/content", ServeDir::new("content"))
         .layer(DefaultBodyLimit::max(FILE_SIZE_LIMIT))
 }
@@ -1,48 +1,289 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    option_vec,
-    prisma::{chat, message, read_filters::StringFilter, user},
-    AppState, WsMessage,
+    config::Config,
+    disappearing, e2ee, link_previews, option_vec,
+    prisma::{
+        self, chat, chat_key, chat_preference, contact, link_preview, message, reaction,
+        read_filters::StringFilter, user, voice_note,
+    },
+    privacy::{self, Blocks},
+    rate_limit, scheduled, AppState, WsMessage,
 };
 use axum::{
     extract::{Path, State},
     http::StatusCode,
+    middleware,
     routing::{get, post},
     Json, Router,
 };
 
-use chrono::Utc;
-use prisma_client_rust::Direction;
+use chrono::{DateTime, FixedOffset, Utc};
+use prisma_client_rust::{prisma_errors::query_engine::UniqueKeyViolation, Direction};
 use structs::requests::{
-    ChatWithMembers, CreateChat, CreateMessage, DeleteMessage, LeaveChat, UpdateProfile,
-    UserStatus, WsChatMessage, WsCreateChat, WsDeleteMessage, WsLeaveChat, WsMessageData,
+    ChatMember, ChatPreferences, ChatWithMembers, CreateChat, CreateMessage, DeleteMessage,
+    ForwardMessages, ForwardedFrom, LeaveChat, Limits, LinkPreview, MessageKind,
+    UpdateChatPreferences, UpdateProfile, UserStatus, VoiceNote, WsChatMessage, WsChatPreferences,
+    WsCreateChat, WsDeleteMessage, WsLeaveChat, WsMessageData,
 };
+use tokio::sync::broadcast;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::Session;
 
+/// Longest message preview sent with the chat list, in characters
+const PREVIEW_LENGTH: usize = 100;
+const MAX_FORWARDED_MESSAGES: usize = 100;
+
+const CHAT_NOT_CREATED: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "Не удалось создать чат, попробуйте ещё раз",
+);
+
+/// Normalizes message text so equal-looking messages are stored the same way
+pub(crate) fn validate_content(
+    content: &str,
+    config: &Config,
+) -> Result<String, (StatusCode, &'static str)> {
+    let content: String = content.trim().nfc().collect();
+    if content.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Сообщение не может быть пустым!"));
+    }
+    if content.chars().count() > config.max_message_length {
+        return Err((StatusCode::BAD_REQUEST, "Сообщение слишком длинное!"));
+    }
+    Ok(content)
+}
+
+pub(crate) fn message_kind(voice_note: Option<voice_note::Data>) -> MessageKind {
+    match voice_note {
+        Some(voice_note) => MessageKind::Voice(Box::new(VoiceNote {
+            id: voice_note.id,
+            duration_ms: voice_note.duration_ms as u32,
+            waveform: voice_note.waveform,
+        })),
+        None => MessageKind::Text,
+    }
+}
+
+fn message_preview(message: chat_summary::messages::Data) -> WsChatMessage {
+    WsChatMessage {
+        chat_id: message.chat_id,
+        sender_id: message.user_id,
+        // Cutting encrypted content would make it undecryptable
+        message: if message.key_id.is_some() {
+            message.content
+        } else {
+            message.content.chars().take(PREVIEW_LENGTH).collect()
+        },
+        message_id: message.id,
+        reply_to: message.reply_id,
+        created_at: message.created_at.into(),
+        reactions: Vec::new(),
+        forwarded_from: None,
+        link_preview: None,
+        kind: message_kind(message.voice_note),
+        expires_at: message.expires_at.map(Into::into),
+        key_id: message.key_id,
+    }
+}
+
+/// Read from the columns copied onto the forward, they outlive the original message
+fn forwarded_from(
+    forwarded_from_id: Option<String>,
+    sender_id: Option<String>,
+    sender_name: Option<String>,
+    created_at: Option<DateTime<FixedOffset>>,
+) -> Option<ForwardedFrom> {
+    Some(ForwardedFrom {
+        message_id: forwarded_from_id,
+        sender_id: sender_id?,
+        sender_name: sender_name?,
+        created_at: created_at?.into(),
+    })
+}
+
+user::select!(user_status {
+    id
+    username
+    display_name
+    bio
+    status_text
+    profile_picture
+    online
+    who_can_see_status
+});
+
+chat::select!((user_id: String) => chat_summary {
+    id
+    members: select {
+        id
+        username
+        display_name
+    }
+    preferences(vec![chat_preference::WhereParam::UserId(StringFilter::Equals(user_id))]): select {
+        pinned
+        muted_until
+        archived
+    }
+    messages(vec![]).order_by(message::created_at::order(Direction::Desc)).take(1): include {
+        voice_note
+    }
+    message_ttl
+    keys(vec![]).order_by(chat_key::created_at::order(Direction::Asc))
+    last_updated
+});
+
+/// `chat` has to be selected with the preferences of the user it is sent to
+fn to_chat_with_members(chat: chat_summary::Data) -> ChatWithMembers {
+    ChatWithMembers {
+        id: chat.id,
+        members: chat
+            .members
+            .into_iter()
+            .map(|user| ChatMember {
+                id: user.id,
+                username: user.username,
+                display_name: user.display_name,
+            })
+            .collect(),
+        last_updated: chat.last_updated.into(),
+        preferences: chat
+            .preferences
+            .into_iter()
+            .next()
+            .map(|preferences| ChatPreferences {
+                pinned: preferences.pinned,
+                muted_until: preferences.muted_until.map(Into::into),
+                archived: preferences.archived,
+            })
+            .unwrap_or_default(),
+        last_message: chat.messages.into_iter().next().map(message_preview),
+        message_ttl: chat.message_ttl.map(|ttl| ttl as u32),
+        chat_keys: chat.keys.into_iter().map(e2ee::to_chat_key).collect(),
+    }
+}
+
+/// Same for both members whoever starts the chat
+fn direct_key(a: &str, b: &str) -> String {
+    if a < b {
+        format!("{a}:{b}")
+    } else {
+        format!("{b}:{a}")
+    }
+}
+
+pub(crate) fn to_user_status(status: user_status::Data) -> UserStatus {
+    UserStatus {
+        id: status.id,
+        username: status.username,
+        display_name: status.display_name,
+        bio: status.bio,
+        status_text: status.status_text,
+        profile_picture: status.profile_picture,
+        online: status.online,
+    }
+}
+
+/// Sends the current profile of the user to them, their contacts and everyone sharing a chat with them
+pub(crate) async fn broadcast_profile(
+    client: &prisma::PrismaClient,
+    message_sender: &broadcast::Sender<WsMessage>,
+    blocks: &Blocks,
+    user_id: String,
+) {
+    let Some(user) = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(user_id.clone()))
+        .select(user_status::select())
+        .exec()
+        .await
+        .unwrap()
+    else {
+        return;
+    };
+
+    let chats = client
+        .chat()
+        .find_many(vec![chat::WhereParam::MembersSome(vec![
+            user::WhereParam::Id(StringFilter::Equals(user_id.clone())),
+        ])])
+        .select(chat::select!({
+            members: select {
+                id
+            }
+        }))
+        .exec()
+        .await
+        .unwrap();
+
+    let contacts = client
+        .contact()
+        .find_many(vec![contact::WhereParam::Or(vec![
+            contact::WhereParam::OwnerId(StringFilter::Equals(user_id.clone())),
+            contact::WhereParam::ContactId(StringFilter::Equals(user_id.clone())),
+        ])])
+        .exec()
+        .await
+        .unwrap();
+
+    let others: HashSet<String> = chats
+        .into_iter()
+        .flat_map(|chat| chat.members)
+        .map(|member| member.id)
+        .chain(
+            contacts
+                .into_iter()
+                .flat_map(|contact| [contact.owner_id, contact.contact_id]),
+        )
+        .filter(|id| id != &user_id)
+        .collect();
+
+    let audience = privacy::audience_from_db(&user.who_can_see_status);
+    let status = to_user_status(user);
+    let mut visible_ids = HashSet::from([user_id.clone()]);
+    let mut hidden_ids = HashSet::new();
+    for id in others {
+        if privacy::allows(client, blocks, &user_id, audience, &id).await {
+            visible_ids.insert(id);
+        } else {
+            hidden_ids.insert(id);
+        }
+    }
+
+    if !hidden_ids.is_empty() {
+        message_sender
+            .send(WsMessage {
+                recipient_ids: hidden_ids,
+                data: WsMessageData::ProfileUpdated(privacy::hide_status(status.clone())),
+            })
+            .unwrap();
+    }
+    message_sender
+        .send(WsMessage {
+            recipient_ids: visible_ids,
+            data: WsMessageData::ProfileUpdated(status),
+        })
+        .unwrap();
+}
+
 async fn get_user_status(
-    State(AppState { client, .. }): State<AppState>,
+    State(AppState { client, blocks, .. }): State<AppState>,
+    session: Session,
     Path(user_id): Path<String>,
 ) -> Json<Option<UserStatus>> {
-    Json(
-        client
-            .user()
-            .find_unique(user::UniqueWhereParam::IdEquals(user_id))
-            .select(user::select!({
-                id
-                profile_picture
-                online
-            }))
-            .exec()
-            .await
-            .unwrap()
-            .map(|status| UserStatus {
-                id: status.id,
-                profile_picture: status.profile_picture,
-                online: status.online,
-            }),
-    )
+    let user = client
+        .user()
+        .find_unique(user::UniqueWhereParam::IdEquals(user_id))
+        .select(user_status::select())
+        .exec()
+        .await
+        .unwrap();
+
+    Json(match user {
+        Some(user) => Some(privacy::visible_status(&client, &blocks, user, &session.user_id).await),
+        None => None,
+    })
 }
 
 async fn get_user_chats(
@@ -51,28 +292,16 @@ async fn get_user_chats(
 ) -> Json<Vec<ChatWithMembers>> {
     Json(
         client
-            .user()
-            .find_unique(user::UniqueWhereParam::IdEquals(session.user_id))
-            .select(user::select!({
-                chats: select {
-                    id
-                    members: select {
-                        id
-                    }
-                    last_updated
-                }
-            }))
+            .chat()
+            .find_many(vec![chat::WhereParam::MembersSome(vec![
+                user::WhereParam::Id(StringFilter::Equals(session.user_id.clone())),
+            ])])
+            .select(chat_summary::select(session.user_id))
             .exec()
             .await
             .unwrap()
-            .unwrap()
-            .chats
             .into_iter()
-            .map(|chat| ChatWithMembers {
-                id: chat.id,
-                members: chat.members.into_iter().map(|user| user.id).collect(),
-                last_updated: chat.last_updated.into(),
-            })
+            .map(to_chat_with_members)
             .collect(),
     )
 }
@@ -81,69 +310,199 @@ async fn create_chat(
     State(AppState {
         client,
         message_sender,
+        blocks,
+        ..
     }): State<AppState>,
     session: Session,
     Json(create_chat): Json<CreateChat>,
 ) -> Result<Json<ChatWithMembers>, (StatusCode, &'static str)> {
-    if create_chat.other_members == session.user_id {
-        return Err((StatusCode::CONFLICT, "Нельзя создать чат с самим собой!"));
-    }
-
     let user = client
         .user()
-        .find_unique(user::UniqueWhereParam::IdEquals(
-            create_chat.other_members.clone(),
+        .find_unique(user::UniqueWhereParam::UsernameEquals(
+            create_chat.other_members,
         ))
+        .select(user::select!({
+            id
+            who_can_start_chats
+        }))
         .exec()
         .await
-        .unwrap();
-    if user.is_none() {
-        return Err((
+        .unwrap()
+        .ok_or((
             StatusCode::BAD_REQUEST,
             "Такого пользователя не существует!",
-        ));
+        ))?;
+    if user.id == session.user_id {
+        return Err((StatusCode::CONFLICT, "Нельзя создать чат с самим собой!"));
     }
 
-    let chat = client
-        .chat()
-        .create(vec![chat::SetParam::ConnectMembers(vec![
-            user::UniqueWhereParam::IdEquals(session.user_id),
-            user::UniqueWhereParam::IdEquals(create_chat.other_members),
-        ])])
-        .select(chat::select!({
-            id
-            members: select {
+    let key = direct_key(&session.user_id, &user.id);
+    let find_existing = || {
+        client
+            .chat()
+            .find_unique(chat::UniqueWhereParam::DirectKeyEquals(key.clone()))
+            .select(chat::select!({
                 id
+                members: select {
+                    id
+                }
+            }))
+            .exec()
+    };
+    let existing = find_existing().await.unwrap();
+    let existing_members: HashSet<String> = existing
+        .iter()
+        .flat_map(|chat| chat.members.iter().map(|member| member.id.clone()))
+        .collect();
+
+    // Blocks keep anyone from rejoining, but reopening a chat the other user is still in doesn't
+    // need to be in their audience
+    let allowed = if existing_members.contains(&user.id) {
+        !blocks.between(&user.id, &session.user_id)
+    } else {
+        let audience = privacy::audience_from_db(&user.who_can_start_chats);
+        privacy::allows(&client, &blocks, &user.id, audience, &session.user_id).await
+    };
+    if !allowed {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Этот пользователь ограничил, кто может ему написать!",
+        ));
+    }
+
+    let chat_id = match existing {
+        Some(chat) => chat.id,
+        None => match client
+            .chat()
+            .create(vec![chat::SetParam::SetDirectKey(Some(key.clone()))])
+            .select(chat::select!({ id }))
+            .exec()
+            .await
+        {
+            Ok(chat) => chat.id,
+            // The other user created it at the same moment
+            Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => {
+                find_existing().await.unwrap().ok_or(CHAT_NOT_CREATED)?.id
             }
-            last_updated
-        }))
+            Err(_) => return Err(CHAT_NOT_CREATED),
+        },
+    };
+
+    // Members who left the chat before rejoin it with its history
+    let joined: HashSet<String> = [session.user_id.clone(), user.id]
+        .into_iter()
+        .filter(|id| !existing_members.contains(id))
+        .collect();
+    if !joined.is_empty() {
+        client
+            .chat()
+            .update(
+                chat::UniqueWhereParam::IdEquals(chat_id.clone()),
+                vec![chat::SetParam::ConnectMembers(
+                    joined
+                        .iter()
+                        .map(|id| user::UniqueWhereParam::IdEquals(id.clone()))
+                        .collect(),
+                )],
+            )
+            .exec()
+            .await
+            .unwrap();
+    }
+
+    let chat = to_chat_with_members(
+        client
+            .chat()
+            .find_unique(chat::UniqueWhereParam::IdEquals(chat_id))
+            .select(chat_summary::select(session.user_id))
+            .exec()
+            .await
+            .unwrap()
+            .unwrap(),
+    );
+
+    if !joined.is_empty() {
+        message_sender
+            .send(WsMessage {
+                recipient_ids: joined,
+                data: WsMessageData::CreateChat(WsCreateChat {
+                    chat_id: chat.id.clone(),
+                    members: chat.members.clone(),
+                }),
+            })
+            .unwrap();
+    }
+
+    Ok(Json(chat))
+}
+
+async fn update_chat_preferences(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(update): Json<UpdateChatPreferences>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    client
+        .chat()
+        .find_first(vec![
+            chat::WhereParam::Id(StringFilter::Equals(update.chat_id.clone())),
+            chat::WhereParam::MembersSome(vec![user::WhereParam::Id(StringFilter::Equals(
+                session.user_id.clone(),
+            ))]),
+        ])
+        .select(chat::select!({ id }))
         .exec()
         .await
-        .unwrap();
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден!"))?;
 
-    let member_ids: Vec<String> = chat.members.into_iter().map(|member| member.id).collect();
+    let preferences = update.preferences;
+    let set_params = || {
+        vec![
+            chat_preference::SetParam::SetPinned(preferences.pinned),
+            chat_preference::SetParam::SetMutedUntil(preferences.muted_until.map(Into::into)),
+            chat_preference::SetParam::SetArchived(preferences.archived),
+        ]
+    };
+    client
+        .chat_preference()
+        .upsert(
+            chat_preference::UniqueWhereParam::ChatIdUserIdEquals(
+                update.chat_id.clone(),
+                session.user_id.clone(),
+            ),
+            (
+                chat::UniqueWhereParam::IdEquals(update.chat_id.clone()),
+                user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                set_params(),
+            ),
+            set_params(),
+        )
+        .exec()
+        .await
+        .unwrap();
 
+    // Other devices of the same user pick the change up through the socket
     message_sender
         .send(WsMessage {
-            recipient_ids: HashSet::from_iter(member_ids.clone()),
-            data: WsMessageData::CreateChat(WsCreateChat {
-                chat_id: chat.id.clone(),
-                members: member_ids.clone(),
+            recipient_ids: HashSet::from([session.user_id]),
+            data: WsMessageData::ChatPreferences(WsChatPreferences {
+                chat_id: update.chat_id,
+                preferences,
             }),
         })
         .unwrap();
-
-    Ok(Json(ChatWithMembers {
-        id: chat.id,
-        members: member_ids,
-        last_updated: chat.last_updated.into(),
-    }))
+    Ok(Json(()))
 }
 
 async fn leave_chat(
     State(AppState {
         client,
         message_sender,
+        ..
     }): State<AppState>,
     session: Session,
     Json(chat): Json<LeaveChat>,
@@ -171,12 +530,34 @@ async fn leave_chat(
             "Вы не являетесь участником данного чата!",
         ));
     }
-    client
-        .chat()
-        .delete(chat::UniqueWhereParam::IdEquals(chat.id.clone()))
-        .exec()
-        .await
-        .unwrap();
+
+    // The chat stays with the other member, it is revived once someone starts it again
+    if chat.members.len() > 1 {
+        client
+            ._batch((
+                client.chat().update(
+                    chat::UniqueWhereParam::IdEquals(chat.id.clone()),
+                    vec![chat::SetParam::DisconnectMembers(vec![
+                        user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                    ])],
+                ),
+                client.chat_preference().delete_many(vec![
+                    chat_preference::WhereParam::ChatId(StringFilter::Equals(chat.id.clone())),
+                    chat_preference::WhereParam::UserId(StringFilter::Equals(
+                        session.user_id.clone(),
+                    )),
+                ]),
+            ))
+            .await
+            .unwrap();
+    } else {
+        client
+            .chat()
+            .delete(chat::UniqueWhereParam::IdEquals(chat.id.clone()))
+            .exec()
+            .await
+            .unwrap();
+    }
     message_sender
         .send(WsMessage {
             recipient_ids: HashSet::from_iter(chat.members.into_iter().map(|member| member.id)),
@@ -189,6 +570,72 @@ async fn leave_chat(
     Ok(Json(()))
 }
 
+/// Oldest first, with everything the client shows along with them
+pub(crate) async fn chat_messages(
+    client: &prisma::PrismaClient,
+    chat_id: String,
+) -> Vec<WsChatMessage> {
+    let messages = client
+        .message()
+        .find_many(vec![message::WhereParam::ChatId(StringFilter::Equals(
+            chat_id,
+        ))])
+        .order_by(message::created_at::order(Direction::Asc))
+        .include(message::include!({
+            reactions(vec![]).order_by(reaction::created_at::order(Direction::Asc))
+            voice_note
+        }))
+        .exec()
+        .await
+        .unwrap();
+
+    let link_urls: Vec<String> = messages
+        .iter()
+        .filter_map(|message| message.link_url.clone())
+        .collect();
+    let previews: HashMap<String, LinkPreview> = client
+        .link_preview()
+        .find_many(vec![link_preview::WhereParam::Url(StringFilter::InVec(
+            link_urls,
+        ))])
+        .exec()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(link_previews::to_link_preview)
+        .map(|preview| (preview.url.clone(), preview))
+        .collect();
+
+    messages
+        .into_iter()
+        .map(|message| {
+            let mut chat_message = WsChatMessage {
+                chat_id: message.chat_id,
+                sender_id: message.user_id,
+                message: message.content,
+                message_id: message.id,
+                reply_to: message.reply_id,
+                created_at: message.created_at.into(),
+                reactions: Vec::new(),
+                forwarded_from: forwarded_from(
+                    message.forwarded_from_id,
+                    message.forwarded_sender_id,
+                    message.forwarded_sender_name,
+                    message.forwarded_created_at,
+                ),
+                link_preview: message.link_url.and_then(|url| previews.get(&url).cloned()),
+                kind: message_kind(message.voice_note),
+                expires_at: message.expires_at.map(Into::into),
+                key_id: message.key_id,
+            };
+            for reaction in message.reactions {
+                chat_message.add_reaction(reaction.user_id, reaction.emoji);
+            }
+            chat_message
+        })
+        .collect()
+}
+
 async fn get_messages(
     State(AppState { client, .. }): State<AppState>,
     session: Session,
@@ -202,50 +649,178 @@ async fn get_messages(
                 session.user_id,
             ))]),
         ])
+        .select(chat::select!({ id }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден"))?;
+    Ok(Json(chat_messages(&client, chat.id).await))
+}
+
+/// Returns the members of the chat if the user may post to it
+pub(crate) async fn check_can_write(
+    client: &prisma::PrismaClient,
+    blocks: &Blocks,
+    chat_id: &str,
+    user_id: &str,
+) -> Result<HashSet<String>, (StatusCode, &'static str)> {
+    let chat = client
+        .chat()
+        .find_first(vec![
+            chat::WhereParam::Id(StringFilter::Equals(chat_id.into())),
+            chat::WhereParam::MembersSome(vec![user::WhereParam::Id(StringFilter::Equals(
+                user_id.into(),
+            ))]),
+        ])
         .select(chat::select!({
-            messages(vec![]).order_by(message::created_at::order(Direction::Asc))
+            members: select {
+                id
+            }
         }))
         .exec()
         .await
         .unwrap()
-        .ok_or((StatusCode::NOT_FOUND, "Чат не найден"))?;
+        .ok_or((StatusCode::NOT_FOUND, "Чат не найден!"))?;
+    if chat
+        .members
+        .iter()
+        .any(|member| blocks.between(&member.id, user_id))
+    {
+        return Err((StatusCode::FORBIDDEN, "Вы не можете писать в этот чат!"));
+    }
+    Ok(chat.members.into_iter().map(|member| member.id).collect())
+}
 
-    Ok(Json(
-        chat.messages
-            .into_iter()
-            .map(|message| WsChatMessage {
-                chat_id: message.chat_id,
-                sender_id: message.user_id,
-                message: message.content,
-                message_id: message.id,
-                reply_to: message.reply_id,
-                created_at: message.created_at.into(),
-            })
-            .collect(),
-    ))
+async fn get_limits(State(AppState { config, .. }): State<AppState>) -> Json<Limits> {
+    Json(Limits {
+        max_message_length: config.max_message_length,
+        max_voice_duration_secs: config.max_voice_duration_secs,
+    })
 }
 
 async fn create_message(
-    State(AppState {
-        client,
-        message_sender,
-    }): State<AppState>,
+    State(state): State<AppState>,
     session: Session,
     Json(message): Json<CreateMessage>,
-) -> Json<String> {
+) -> Result<Json<String>, (StatusCode, &'static str)> {
+    match message.scheduled_for {
+        Some(scheduled_for) => {
+            scheduled::schedule(&state, session.user_id, message, scheduled_for).await
+        }
+        None => send_message(state, session.user_id, message).await,
+    }
+    .map(Json)
+}
+
+/// Replies only go to messages of the same chat
+pub(crate) async fn check_reply(
+    client: &prisma::PrismaClient,
+    chat_id: &str,
+    reply_to_id: Option<&str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    let Some(reply_to_id) = reply_to_id else {
+        return Ok(());
+    };
+    client
+        .message()
+        .find_first(vec![
+            message::WhereParam::Id(StringFilter::Equals(reply_to_id.to_owned())),
+            message::WhereParam::ChatId(StringFilter::Equals(chat_id.to_owned())),
+        ])
+        .select(message::select!({ id }))
+        .exec()
+        .await
+        .unwrap()
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "Сообщение, на которое вы отвечаете, не найдено!",
+        ))?;
+    Ok(())
+}
+
+/// Stores the message and sends it to the members of the chat, scheduled messages are
+/// delivered through here too
+pub(crate) async fn send_message(
+    state: AppState,
+    sender_id: String,
+    message: CreateMessage,
+) -> Result<String, (StatusCode, &'static str)> {
+    let AppState {
+        client,
+        message_sender,
+        blocks,
+        config,
+        ..
+    } = &state;
+    if message.key_id.is_some() && message.voice_note_id.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Голосовые сообщения в зашифрованных чатах не поддерживаются!",
+        ));
+    }
+    let content = if message.key_id.is_some() {
+        e2ee::validate_ciphertext(&message.content, config)?
+    } else if message.voice_note_id.is_some() && message.content.trim().is_empty() {
+        String::new()
+    } else {
+        validate_content(&message.content, config)?
+    };
+    check_can_write(client, blocks, &message.chat_id, &sender_id).await?;
+    check_reply(client, &message.chat_id, message.reply_to_id.as_deref()).await?;
+    e2ee::check_key(client, &message.chat_id, message.key_id.as_deref()).await?;
+    let expires_at = disappearing::expiry(client, &message.chat_id).await;
+
+    // A note is sent once by its uploader, forwards share it afterwards
+    let voice_note = match message.voice_note_id {
+        Some(id) => Some(
+            client
+                .voice_note()
+                .find_first(vec![
+                    voice_note::WhereParam::Id(StringFilter::Equals(id)),
+                    voice_note::WhereParam::UploaderId(StringFilter::Equals(sender_id.clone())),
+                    voice_note::WhereParam::MessagesNone(vec![]),
+                ])
+                .exec()
+                .await
+                .unwrap()
+                .ok_or((StatusCode::NOT_FOUND, "Голосовое сообщение не найдено!"))?,
+        ),
+        None => None,
+    };
+
+    // The server can't look into encrypted messages for links
+    let link_url = match message.key_id {
+        Some(_) => None,
+        None => link_previews::first_link(&content),
+    };
     let (message, _) = client
         ._batch((
             client
                 .message()
                 .create(
                     chat::UniqueWhereParam::IdEquals(message.chat_id.clone()),
-                    message.content,
-                    user::UniqueWhereParam::IdEquals(session.user_id.clone()),
-                    option_vec![message
-                        .reply_to_id
-                        .map(|id| message::SetParam::ConnectReplyTo(
-                            message::UniqueWhereParam::IdEquals(id)
-                        ))],
+                    content,
+                    user::UniqueWhereParam::IdEquals(sender_id.clone()),
+                    option_vec![
+                        message
+                            .reply_to_id
+                            .map(|id| message::SetParam::ConnectReplyTo(
+                                message::UniqueWhereParam::IdEquals(id)
+                            )),
+                        link_url
+                            .clone()
+                            .map(|url| message::SetParam::SetLinkUrl(Some(url))),
+                        voice_note.as_ref().map(|voice_note| {
+                            message::SetParam::ConnectVoiceNote(
+                                voice_note::UniqueWhereParam::IdEquals(voice_note.id.clone()),
+                            )
+                        }),
+                        expires_at
+                            .map(|expires_at| message::SetParam::SetExpiresAt(Some(expires_at))),
+                        message.key_id.map(|id| message::SetParam::ConnectKey(
+                            chat_key::UniqueWhereParam::IdEquals(id)
+                        )),
+                    ],
                 )
                 .include(message::include!({
                     chat: select {
@@ -267,30 +842,196 @@ async fn create_message(
             recipient_ids: HashSet::from_iter(
                 message.chat.members.into_iter().map(|member| member.id),
             ),
-            data: WsMessageData::ChatMessage(WsChatMessage {
-                chat_id: message.chat_id,
-                sender_id: session.user_id,
+            data: WsMessageData::ChatMessage(Box::new(WsChatMessage {
+                chat_id: message.chat_id.clone(),
+                sender_id,
                 message: message.content,
                 message_id: message.id.clone(),
                 reply_to: message.reply_id,
                 created_at: message.created_at.into(),
-            }),
+                reactions: Vec::new(),
+                forwarded_from: None,
+                link_preview: None,
+                kind: message_kind(voice_note),
+                expires_at: message.expires_at.map(Into::into),
+                key_id: message.key_id,
+            })),
         })
         .unwrap();
 
-    Json(message.id)
+    // The preview follows over the socket, sending doesn't wait for the linked site
+    if let Some(url) = link_url {
+        tokio::spawn(link_previews::attach_preview(
+            state,
+            message.chat_id,
+            message.id.clone(),
+            url,
+        ));
+    }
+
+    Ok(message.id)
 }
 
-async fn delete_message(
+async fn forward_messages(
     State(AppState {
         client,
         message_sender,
+        blocks,
+        ..
     }): State<AppState>,
-    Json(message): Json<DeleteMessage>,
-) {
+    session: Session,
+    Json(request): Json<ForwardMessages>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    if request.message_ids.is_empty() || request.message_ids.len() > MAX_FORWARDED_MESSAGES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Можно переслать от 1 до 100 сообщений!",
+        ));
+    }
+    let recipient_ids =
+        check_can_write(&client, &blocks, &request.chat_id, &session.user_id).await?;
+    if e2ee::is_encrypted(&client, &request.chat_id).await {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "В зашифрованный чат нельзя пересылать сообщения!",
+        ));
+    }
+    let expires_at = disappearing::expiry(&client, &request.chat_id).await;
+
+    // Only messages from the user's own chats can be forwarded
+    let sources = client
+        .message()
+        .find_many(vec![
+            message::WhereParam::Id(StringFilter::InVec(request.message_ids)),
+            message::WhereParam::ChatIs(vec![chat::WhereParam::MembersSome(vec![
+                user::WhereParam::Id(StringFilter::Equals(session.user_id.clone())),
+            ])]),
+        ])
+        .order_by(message::created_at::order(Direction::Asc))
+        .include(message::include!({
+            sender: select {
+                id
+                username
+                display_name
+            }
+            voice_note
+        }))
+        .exec()
+        .await
+        .unwrap();
+    if sources.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "Сообщения не найдены!"));
+    }
+    // Only the members of the original chat can read them
+    if sources.iter().any(|source| source.key_id.is_some()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Зашифрованные сообщения нельзя переслать!",
+        ));
+    }
+
+    for source in sources {
+        let original = forwarded_from(
+            source.forwarded_from_id,
+            source.forwarded_sender_id,
+            source.forwarded_sender_name,
+            source.forwarded_created_at,
+        )
+        .unwrap_or(ForwardedFrom {
+            message_id: Some(source.id),
+            sender_id: source.sender.id,
+            sender_name: source.sender.display_name.unwrap_or(source.sender.username),
+            created_at: source.created_at.into(),
+        });
+
+        // The copy shows the preview the original already has, nothing is fetched again
+        let link_preview = match &source.link_url {
+            Some(url) => client
+                .link_preview()
+                .find_unique(link_preview::UniqueWhereParam::UrlEquals(url.clone()))
+                .exec()
+                .await
+                .unwrap()
+                .and_then(link_previews::to_link_preview),
+            None => None,
+        };
+
+        let message = client
+            .message()
+            .create(
+                chat::UniqueWhereParam::IdEquals(request.chat_id.clone()),
+                source.content,
+                user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+                option_vec![
+                    original.message_id.clone().map(|id| {
+                        message::SetParam::ConnectForwardedFrom(
+                            message::UniqueWhereParam::IdEquals(id),
+                        )
+                    }),
+                    Some(message::SetParam::SetForwardedSenderId(Some(
+                        original.sender_id.clone()
+                    ))),
+                    Some(message::SetParam::SetForwardedSenderName(Some(
+                        original.sender_name.clone()
+                    ))),
+                    Some(message::SetParam::SetForwardedCreatedAt(Some(
+                        original.created_at.into()
+                    ))),
+                    Some(message::SetParam::SetLinkUrl(source.link_url.clone())),
+                    source.voice_note.as_ref().map(|voice_note| {
+                        message::SetParam::ConnectVoiceNote(voice_note::UniqueWhereParam::IdEquals(
+                            voice_note.id.clone(),
+                        ))
+                    }),
+                    expires_at.map(|expires_at| message::SetParam::SetExpiresAt(Some(expires_at))),
+                ],
+            )
+            .exec()
+            .await
+            .unwrap();
+
+        message_sender
+            .send(WsMessage {
+                recipient_ids: recipient_ids.clone(),
+                data: WsMessageData::ChatMessage(Box::new(WsChatMessage {
+                    chat_id: message.chat_id,
+                    sender_id: message.user_id,
+                    message: message.content,
+                    message_id: message.id,
+                    reply_to: None,
+                    created_at: message.created_at.into(),
+                    reactions: Vec::new(),
+                    forwarded_from: Some(original),
+                    link_preview,
+                    kind: message_kind(source.voice_note),
+                    expires_at: message.expires_at.map(Into::into),
+                    key_id: None,
+                })),
+            })
+            .unwrap();
+    }
+
+    client
+        .chat()
+        .update(
+            chat::UniqueWhereParam::IdEquals(request.chat_id),
+            vec![chat::SetParam::SetLastUpdated(Utc::now().into())],
+        )
+        .exec()
+        .await
+        .unwrap();
+    Ok(Json(()))
+}
+
+/// For everyone in the chat, by its sender or an admin
+pub(crate) async fn delete_for_everyone(
+    client: &prisma::PrismaClient,
+    message_sender: &broadcast::Sender<WsMessage>,
+    message_id: String,
+) -> Result<(), (StatusCode, &'static str)> {
     let message = client
         .message()
-        .delete(message::UniqueWhereParam::IdEquals(message.id))
+        .delete(message::UniqueWhereParam::IdEquals(message_id))
         .include(message::include!({
             chat: select {
                 id
@@ -301,7 +1042,7 @@ async fn delete_message(
         }))
         .exec()
         .await
-        .unwrap();
+        .map_err(|_| (StatusCode::NOT_FOUND, "Сообщение не найдено!"))?;
     message_sender
         .send(WsMessage {
             recipient_ids: HashSet::from_iter(
@@ -313,35 +1054,120 @@ async fn delete_message(
             }),
         })
         .unwrap();
+    Ok(())
+}
+
+async fn delete_message(
+    State(AppState {
+        client,
+        message_sender,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Json(message): Json<DeleteMessage>,
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    let own = client
+        .message()
+        .count(vec![
+            message::WhereParam::Id(StringFilter::Equals(message.id.clone())),
+            message::WhereParam::UserId(StringFilter::Equals(session.user_id)),
+        ])
+        .exec()
+        .await
+        .unwrap()
+        > 0;
+    if !own {
+        return Err((StatusCode::NOT_FOUND, "Сообщение не найдено!"));
+    }
+    delete_for_everyone(&client, &message_sender, message.id).await?;
+    Ok(Json(()))
+}
+
+/// Blank text clears the field
+fn profile_text(
+    text: Option<String>,
+    max_length: usize,
+    error: &'static str,
+) -> Result<Option<String>, (StatusCode, &'static str)> {
+    match text.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(text) if text.chars().count() > max_length => Err((StatusCode::BAD_REQUEST, error)),
+        Some(text) => Ok(Some(text.into())),
+    }
 }
 
 async fn update_profile(
-    State(AppState { client, .. }): State<AppState>,
+    State(AppState {
+        client,
+        message_sender,
+        blocks,
+        ..
+    }): State<AppState>,
     session: Session,
     Json(update_profile): Json<UpdateProfile>,
-) -> Json<()> {
+) -> Result<Json<()>, (StatusCode, &'static str)> {
+    const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+    const MAX_BIO_LENGTH: usize = 200;
+    const MAX_STATUS_TEXT_LENGTH: usize = 64;
+
+    let display_name = profile_text(
+        update_profile.display_name,
+        MAX_DISPLAY_NAME_LENGTH,
+        "Отображаемое имя слишком длинное!",
+    )?;
+    let bio = profile_text(
+        update_profile.bio,
+        MAX_BIO_LENGTH,
+        "Описание слишком длинное!",
+    )?;
+    let status_text = profile_text(
+        update_profile.status_text,
+        MAX_STATUS_TEXT_LENGTH,
+        "Статус слишком длинный!",
+    )?;
+
     client
         .user()
         .update(
-            user::UniqueWhereParam::IdEquals(session.user_id),
-            vec![user::SetParam::SetProfilePicture(
-                update_profile.profile_picture,
-            )],
+            user::UniqueWhereParam::IdEquals(session.user_id.clone()),
+            vec![
+                user::SetParam::SetProfilePicture(update_profile.profile_picture),
+                user::SetParam::SetDisplayName(display_name),
+                user::SetParam::SetBio(bio),
+                user::SetParam::SetStatusText(status_text),
+            ],
         )
         .exec()
         .await
         .unwrap();
-    Json(())
+
+    broadcast_profile(&client, &message_sender, &blocks, session.user_id).await;
+    Ok(Json(()))
 }
 
-pub(crate) fn router() -> Router<AppState> {
+pub(crate) fn router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/chats", get(get_user_chats))
         .route("/status/:user_id", get(get_user_status))
         .route("/messages/:chat_id", get(get_messages))
+        .route("/limits", get(get_limits))
         .route("/update_profile", post(update_profile))
-        .route("/create_message", post(create_message))
+        .route(
+            "/create_message",
+            post(create_message).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit::limit_messages_by_user,
+            )),
+        )
+        .route(
+            "/forward_messages",
+            post(forward_messages).route_layer(middleware::from_fn_with_state(
+                state,
+                rate_limit::limit_messages_by_user,
+            )),
+        )
         .route("/create_chat", post(create_chat))
         .route("/leave_chat", post(leave_chat))
+        .route("/chat_preferences", post(update_chat_preferences))
         .route("/delete_message", post(delete_message))
 }
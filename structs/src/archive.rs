@@ -0,0 +1,484 @@
+//! Self-contained archive of a chat. Members export it from `/chats/:id/export`, admins import
+//! it into a server with `/admin/import_chat`
+
+use std::{collections::HashSet, hash::Hash};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    markdown::{self, Block, Span},
+    requests::{ChatKey, ChatMember, MessageKind, WsChatMessage},
+    DateTime, Utc,
+};
+
+/// Raised when older servers can't import the archive anymore
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Shown in HTML archives instead of messages that stayed encrypted
+pub const ENCRYPTED_PLACEHOLDER: &str = "🔒 Зашифрованное сообщение";
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ChatArchive {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub chat_id: String,
+    /// Set for direct chats, a server keeps one per pair of users
+    pub direct_key: Option<String>,
+    pub message_ttl: Option<u32>,
+    pub member_ids: Vec<String>,
+    /// Members and everyone who sent or reacted to a message, they may have left since
+    pub users: Vec<ChatMember>,
+    /// Oldest first
+    pub chat_keys: Vec<ChatKey>,
+    /// Oldest first. Encrypted ones hold ciphertext unless the exporting client decrypted them,
+    /// which clears their `key_id`
+    pub messages: Vec<WsChatMessage>,
+    pub uploads: Vec<Upload>,
+}
+
+/// File under `/content` on the server that a message refers to
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Upload {
+    pub name: String,
+    /// Base64 encoded
+    pub data: String,
+}
+
+impl Upload {
+    pub fn new(name: String, bytes: &[u8]) -> Self {
+        Self {
+            name,
+            data: STANDARD.encode(bytes),
+        }
+    }
+
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        STANDARD.decode(&self.data).ok()
+    }
+}
+
+/// Names the server gives uploads, anything else could point outside of `/content`
+pub fn is_upload_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '.')
+}
+
+/// Names of the uploads the message refers to
+pub fn referenced_uploads(message: &WsChatMessage) -> Vec<String> {
+    let mut names = Vec::new();
+    if let MessageKind::Voice(voice_note) = &message.kind {
+        names.push(voice_note.file_name());
+    }
+    if let Some(image) = message
+        .link_preview
+        .as_ref()
+        .and_then(|preview| preview.image.clone())
+    {
+        names.push(image);
+    }
+    names
+}
+
+fn all_unique<T: Eq + Hash>(items: impl IntoIterator<Item = T>) -> bool {
+    let mut seen = HashSet::new();
+    items.into_iter().all(|item| seen.insert(item))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Preview images are stored without an extension, browsers need the type in data URLs
+fn image_type(bytes: &[u8]) -> &'static str {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xff, 0xd8, ..] => "image/jpeg",
+        [b'G', b'I', b'F', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn span_html(span: &Span) -> String {
+    let mut html = escape(&span.text).replace('\n', "<br>");
+    if span.code {
+        html = format!("<code>{html}</code>");
+    }
+    if span.italic {
+        html = format!("<em>{html}</em>");
+    }
+    if span.bold {
+        html = format!("<strong>{html}</strong>");
+    }
+    if let Some(link) = &span.link {
+        html = format!("<a href=\"{}\">{html}</a>", escape(link));
+    }
+    html
+}
+
+fn markdown_html(source: &str) -> String {
+    markdown::parse(source)
+        .iter()
+        .map(|block| match block {
+            Block::Paragraph(spans) => {
+                format!("<p>{}</p>", spans.iter().map(span_html).collect::<String>())
+            }
+            Block::Code(code) => format!("<pre>{}</pre>", escape(code)),
+        })
+        .collect()
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:auto;padding:1em}\
+.message{border-bottom:1px solid #ddd;padding:.5em 0}.meta,.muted{color:#777;font-size:.85em}\
+.quote{display:block;border-left:3px solid #aaa;padding-left:.5em;color:#555}\
+.preview{display:block;border:1px solid #ddd;padding:.5em;margin-top:.5em}\
+.preview img{max-width:100%}pre{background:#f4f4f4;padding:.5em;overflow:auto}";
+
+impl ChatArchive {
+    pub fn upload(&self, name: &str) -> Option<&Upload> {
+        self.uploads.iter().find(|upload| upload.name == name)
+    }
+
+    /// Everything the archive refers to is in it, and nothing is in it twice
+    pub fn is_consistent(&self) -> bool {
+        let user_ids: HashSet<&str> = self.users.iter().map(|user| user.id.as_str()).collect();
+        let key_ids: HashSet<&str> = self.chat_keys.iter().map(|key| key.id.as_str()).collect();
+        let users_known = self
+            .member_ids
+            .iter()
+            .chain(self.messages.iter().map(|message| &message.sender_id))
+            .chain(
+                self.messages
+                    .iter()
+                    .flat_map(|message| &message.reactions)
+                    .flat_map(|reaction| &reaction.user_ids),
+            )
+            .all(|id| user_ids.contains(id.as_str()));
+        let keys_known = self
+            .messages
+            .iter()
+            .filter_map(|message| message.key_id.as_deref())
+            .all(|id| key_ids.contains(id));
+        let no_duplicates = user_ids.len() == self.users.len()
+            && key_ids.len() == self.chat_keys.len()
+            && all_unique(self.users.iter().map(|user| &user.username))
+            && all_unique(&self.member_ids)
+            && all_unique(self.messages.iter().map(|message| &message.message_id))
+            && all_unique(self.uploads.iter().map(|upload| &upload.name))
+            && self.messages.iter().all(|message| {
+                all_unique(message.reactions.iter().map(|reaction| &reaction.emoji))
+                    && message
+                        .reactions
+                        .iter()
+                        .all(|reaction| all_unique(&reaction.user_ids))
+            });
+        // Stored names are later joined to `content`, so they can't be paths
+        let uploads_valid = self
+            .messages
+            .iter()
+            .flat_map(referenced_uploads)
+            .all(|name| is_upload_name(&name))
+            && self
+                .uploads
+                .iter()
+                .all(|upload| is_upload_name(&upload.name) && upload.bytes().is_some());
+        users_known && keys_known && no_duplicates && uploads_valid
+    }
+
+    fn user_name(&self, user_id: &str) -> &str {
+        self.users
+            .iter()
+            .find(|user| user.id == user_id)
+            .map(ChatMember::name)
+            .unwrap_or("Удалённый аккаунт")
+    }
+
+    fn data_url(&self, name: &str, content_type: Option<&str>) -> Option<String> {
+        let upload = self.upload(name)?;
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            None => image_type(&upload.bytes()?),
+        };
+        Some(format!("data:{content_type};base64,{}", upload.data))
+    }
+
+    fn message_html(&self, message: &WsChatMessage) -> String {
+        let mut html = format!(
+            "<div class=\"message\" id=\"m-{}\"><div class=\"meta\"><strong>{}</strong> {}</div>",
+            escape(&message.message_id),
+            escape(self.user_name(&message.sender_id)),
+            message.created_at.format("%d.%m.%Y %H:%M UTC")
+        );
+        if let Some(original) = &message.forwarded_from {
+            html += &format!(
+                "<div class=\"muted\">Переслано от {}</div>",
+                escape(&original.sender_name)
+            );
+        }
+        if let Some(reply) = message
+            .reply_to
+            .as_ref()
+            .and_then(|id| self.messages.iter().find(|other| &other.message_id == id))
+        {
+            let quoted = if reply.key_id.is_some() {
+                ENCRYPTED_PLACEHOLDER.to_owned()
+            } else {
+                markdown::to_plain_text(&reply.message)
+                    .chars()
+                    .take(100)
+                    .collect()
+            };
+            html += &format!(
+                "<a class=\"quote\" href=\"#m-{}\">{}: {}</a>",
+                escape(&reply.message_id),
+                escape(self.user_name(&reply.sender_id)),
+                escape(&quoted)
+            );
+        }
+        if let MessageKind::Voice(voice_note) = &message.kind {
+            match self.data_url(&voice_note.file_name(), Some("audio/ogg")) {
+                Some(url) => html += &format!("<audio controls src=\"{url}\"></audio>"),
+                None => html += "<p class=\"muted\">🎤 Голосовое сообщение</p>",
+            }
+        }
+        if message.key_id.is_some() {
+            html += &format!("<p class=\"muted\">{ENCRYPTED_PLACEHOLDER}</p>");
+        } else {
+            html += &markdown_html(&message.message);
+        }
+        if let Some(preview) = &message.link_preview {
+            html += &format!("<a class=\"preview\" href=\"{}\">", escape(&preview.url));
+            if let Some(title) = &preview.title {
+                html += &format!("<strong>{}</strong>", escape(title));
+            }
+            if let Some(description) = &preview.description {
+                html += &format!("<div class=\"muted\">{}</div>", escape(description));
+            }
+            if let Some(url) = preview
+                .image
+                .as_ref()
+                .and_then(|image| self.data_url(image, None))
+            {
+                html += &format!("<img src=\"{url}\" alt=\"\">");
+            }
+            html += "</a>";
+        }
+        if !message.reactions.is_empty() {
+            let reactions: Vec<String> = message
+                .reactions
+                .iter()
+                .map(|reaction| format!("{} {}", escape(&reaction.emoji), reaction.user_ids.len()))
+                .collect();
+            html += &format!("<div class=\"meta\">{}</div>", reactions.join(" · "));
+        }
+        html + "</div>"
+    }
+
+    /// Single page with the uploads inlined, readable without the server or the app
+    pub fn to_html(&self) -> String {
+        let members: Vec<&str> = self
+            .member_ids
+            .iter()
+            .map(|id| self.user_name(id))
+            .collect();
+        let title = escape(&members.join(", "));
+        let messages: String = self
+            .messages
+            .iter()
+            .map(|message| self.message_html(message))
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"ru\"><head><meta charset=\"utf-8\"><title>{title}</title>\
+            <style>{STYLE}</style></head><body><h1>{title}</h1>\
+            <p class=\"muted\">Экспортировано {}, сообщений: {}</p>{messages}</body></html>\n",
+            self.exported_at.format("%d.%m.%Y %H:%M UTC"),
+            self.messages.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::{LinkPreview, MessageReaction, VoiceNote};
+
+    fn member(id: &str, username: &str) -> ChatMember {
+        ChatMember {
+            id: id.into(),
+            username: username.into(),
+            display_name: None,
+        }
+    }
+
+    fn message(id: &str, sender_id: &str, text: &str) -> WsChatMessage {
+        WsChatMessage {
+            chat_id: "chat".into(),
+            sender_id: sender_id.into(),
+            message_id: id.into(),
+            message: text.into(),
+            reply_to: None,
+            created_at: Utc::now(),
+            reactions: Vec::new(),
+            forwarded_from: None,
+            link_preview: None,
+            kind: MessageKind::Text,
+            expires_at: None,
+            key_id: None,
+        }
+    }
+
+    /// What `/chats/:id/export` gives for a chat with a reply, a voice note, a preview and a key
+    fn exported() -> ChatArchive {
+        let mut reply = message("m2", "bob", "**yes** <b>");
+        reply.reply_to = Some("m1".into());
+        reply.reactions = vec![MessageReaction {
+            emoji: "👍".into(),
+            user_ids: vec!["alice".into(), "carol".into()],
+        }];
+        let mut voice = message("m3", "alice", "");
+        voice.kind = MessageKind::Voice(Box::new(VoiceNote {
+            id: "note".into(),
+            duration_ms: 1500,
+            waveform: vec![0, 128, 255],
+        }));
+        let mut link = message("m4", "bob", "https://example.com");
+        link.link_preview = Some(LinkPreview {
+            url: "https://example.com".into(),
+            title: Some("Example".into()),
+            description: None,
+            image: Some("img-preview".into()),
+        });
+        let mut encrypted = message("m5", "alice", "ciphertext");
+        encrypted.key_id = Some("key".into());
+
+        ChatArchive {
+            version: ARCHIVE_VERSION,
+            exported_at: Utc::now(),
+            chat_id: "chat".into(),
+            direct_key: Some("alice:bob".into()),
+            message_ttl: None,
+            member_ids: vec!["alice".into(), "bob".into()],
+            users: vec![
+                member("alice", "alice"),
+                member("bob", "bob"),
+                member("carol", "carol"),
+            ],
+            chat_keys: vec![ChatKey {
+                id: "key".into(),
+                chat_id: "chat".into(),
+                initiator_id: "alice".into(),
+                initiator_key: "a".into(),
+                responder_key: "b".into(),
+                ephemeral_key: "e".into(),
+                created_at: Utc::now(),
+            }],
+            messages: vec![message("m1", "alice", "ok?"), reply, voice, link, encrypted],
+            uploads: vec![
+                Upload::new("voice-note.ogg".into(), b"OggS"),
+                Upload::new("img-preview".into(), &[0x89, b'P', b'N', b'G']),
+            ],
+        }
+    }
+
+    #[test]
+    fn upload_names() {
+        for name in ["voice-1a2b.ogg", "img-0f9e", "a.b"] {
+            assert!(is_upload_name(name), "{name}");
+        }
+        for name in [
+            "",
+            ".",
+            "..",
+            ".hidden",
+            "../secret",
+            "a/b",
+            "a\\b",
+            "/etc",
+            "voice 1",
+        ] {
+            assert!(!is_upload_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+        assert_eq!(escape("привет"), "привет");
+    }
+
+    #[test]
+    fn round_trip() {
+        let archive = exported();
+        assert!(archive.is_consistent());
+        let json = serde_json::to_string(&archive).unwrap();
+        let imported: ChatArchive = serde_json::from_str(&json).unwrap();
+        assert_eq!(imported, archive);
+        assert!(imported.is_consistent());
+        assert_eq!(
+            imported.upload("voice-note.ogg").unwrap().bytes().unwrap(),
+            b"OggS"
+        );
+
+        let html = imported.to_html();
+        assert!(html.contains("<strong>yes</strong> &lt;b&gt;"));
+        assert!(html.contains("data:audio/ogg;base64,"));
+        assert!(html.contains("data:image/png;base64,"));
+        assert!(html.contains(ENCRYPTED_PLACEHOLDER));
+        assert!(!html.contains("ciphertext"));
+    }
+
+    #[test]
+    fn rejects_inconsistent() {
+        let broken: Vec<fn(&mut ChatArchive)> = vec![
+            |archive| archive.member_ids.push("dave".into()),
+            |archive| archive.messages[0].sender_id = "dave".into(),
+            |archive| {
+                archive.messages[1].reactions[0]
+                    .user_ids
+                    .push("dave".into())
+            },
+            |archive| archive.messages[4].key_id = Some("other".into()),
+            |archive| archive.uploads[0].name = "../voice-note.ogg".into(),
+            |archive| archive.uploads[0].data = "not base64!".into(),
+            // Duplicates
+            |archive| archive.users.push(member("alice", "alice2")),
+            |archive| archive.users.push(member("dave", "alice")),
+            |archive| archive.member_ids.push("alice".into()),
+            |archive| archive.chat_keys.push(archive.chat_keys[0].clone()),
+            |archive| archive.messages.push(archive.messages[0].clone()),
+            |archive| archive.uploads.push(archive.uploads[0].clone()),
+            |archive| {
+                archive.messages[1].reactions[0]
+                    .user_ids
+                    .push("alice".into())
+            },
+            |archive| {
+                let reaction = archive.messages[1].reactions[0].clone();
+                archive.messages[1].reactions.push(reaction);
+            },
+        ];
+        for (index, breaks) in broken.into_iter().enumerate() {
+            let mut archive = exported();
+            breaks(&mut archive);
+            assert!(!archive.is_consistent(), "case {index}");
+        }
+    }
+}
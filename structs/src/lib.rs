@@ -1,13 +1,33 @@
 pub use chrono::prelude::*;
 pub use chrono::Duration;
 
+pub mod archive;
+pub mod markdown;
+
 pub mod requests {
     use serde::{Deserialize, Serialize};
 
+    /// Returned by `/login` and `/register`
     #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
     pub struct Session {
         pub session_id: String,
         pub user_id: String,
+        pub username: String,
+        pub tokens: Tokens,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct Tokens {
+        /// Sent as `Authorization: Bearer`, valid for a few minutes
+        pub access_token: String,
+        pub access_expires_at: super::DateTime<super::Utc>,
+        /// Exchanged for new tokens at `/refresh`, works once
+        pub refresh_token: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct RefreshRequest {
+        pub refresh_token: String,
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -16,15 +36,138 @@ pub mod requests {
         pub password: String,
     }
 
+    /// Answer to `/login`, accounts with two-factor login get a session only after `/login/totp`
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub enum LoginResult {
+        LoggedIn(Session),
+        /// The password was right, a few minutes are left to send the code
+        TwoFactorRequired { login_token: String },
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct TwoFactorLogin {
+        pub login_token: String,
+        /// From the authenticator app or one of the recovery codes
+        pub code: String,
+    }
+
+    #[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct TotpStatus {
+        pub enabled: bool,
+        pub recovery_codes_left: usize,
+    }
+
+    /// Not enabled until a code from the app is sent to `/totp/confirm`
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct TotpSetup {
+        /// `otpauth://` URI, shown as a QR code
+        pub provisioning_uri: String,
+        /// Base32, for typing into the app by hand
+        pub secret: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ConfirmTotp {
+        pub code: String,
+    }
+
+    /// Shown once, only their hashes are stored
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct RecoveryCodes {
+        pub codes: Vec<String>,
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
+    pub struct DisableTotp {
+        pub password: String,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
     pub struct UserStatus {
         pub id: String,
+        pub username: String,
+        pub display_name: Option<String>,
+        pub bio: Option<String>,
+        pub status_text: Option<String>,
         pub profile_picture: Option<String>,
         pub online: bool,
     }
 
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ChangePassword {
+        pub old_password: String,
+        pub new_password: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ChangeUsername {
+        pub username: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct DeleteAccount {
+        pub password: String,
+    }
+
+    /// `id` never changes, `username` is the handle the user can rename
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct ChatMember {
+        pub id: String,
+        pub username: String,
+        pub display_name: Option<String>,
+    }
+
+    impl ChatMember {
+        /// Display name if the member has set one, otherwise the username
+        pub fn name(&self) -> &str {
+            self.display_name.as_deref().unwrap_or(&self.username)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+    pub enum ContactState {
+        Accepted,
+        /// Waiting for the other user to accept
+        Outgoing,
+        /// The other user wants to be added
+        Incoming,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct Contact {
+        pub user: UserStatus,
+        pub state: ContactState,
+    }
+
+    /// Adding a user who has already sent a request accepts it, removing also declines requests
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ContactRequest {
+        pub user_id: String,
+    }
+
+    #[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+    pub enum Audience {
+        #[default]
+        Everyone,
+        Contacts,
+        Nobody,
+    }
+
+    #[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct PrivacySettings {
+        pub who_can_start_chats: Audience,
+        /// Others see the user offline and without an avatar
+        pub who_can_see_status: Audience,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct BlockRequest {
+        pub user_id: String,
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     pub struct CreateChat {
+        /// Username of the other member
         pub other_members: String,
     }
 
@@ -36,8 +179,66 @@ pub mod requests {
     #[derive(Debug, Deserialize, Serialize)]
     pub struct CreateMessage {
         pub chat_id: String,
+        /// May be empty when a voice note is attached
         pub content: String,
         pub reply_to_id: Option<String>,
+        /// Returned by `/upload_voice`
+        #[serde(default)]
+        pub voice_note_id: Option<String>,
+        /// Stores the message until then, the returned id is of the `ScheduledMessage`
+        #[serde(default)]
+        pub scheduled_for: Option<super::DateTime<super::Utc>>,
+        /// Set when `content` is encrypted with this `ChatKey`
+        #[serde(default)]
+        pub key_id: Option<String>,
+    }
+
+    /// Own message waiting to be sent to a chat
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct ScheduledMessage {
+        pub id: String,
+        pub chat_id: String,
+        pub content: String,
+        pub reply_to: Option<String>,
+        pub scheduled_for: super::DateTime<super::Utc>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct EditScheduledMessage {
+        pub id: String,
+        pub content: String,
+        pub scheduled_for: super::DateTime<super::Utc>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct CancelScheduledMessage {
+        pub id: String,
+    }
+
+    /// Used until the server reports its own limit
+    pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 4000;
+
+    #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+    pub struct Limits {
+        /// In characters, after Unicode normalization
+        pub max_message_length: usize,
+        #[serde(default = "default_max_voice_duration")]
+        pub max_voice_duration_secs: u32,
+    }
+
+    pub const DEFAULT_MAX_VOICE_DURATION_SECS: u32 = 300;
+
+    fn default_max_voice_duration() -> u32 {
+        DEFAULT_MAX_VOICE_DURATION_SECS
+    }
+
+    impl Default for Limits {
+        fn default() -> Self {
+            Self {
+                max_message_length: DEFAULT_MAX_MESSAGE_LENGTH,
+                max_voice_duration_secs: DEFAULT_MAX_VOICE_DURATION_SECS,
+            }
+        }
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -45,9 +246,26 @@ pub mod requests {
         pub id: String,
     }
 
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ForwardMessages {
+        /// Forwarded in the order they were sent
+        pub message_ids: Vec<String>,
+        pub chat_id: String,
+    }
+
+    /// Used by both `/react` and `/unreact`
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct React {
+        pub message_id: String,
+        pub emoji: String,
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     pub struct UpdateProfile {
         pub profile_picture: Option<String>,
+        pub display_name: Option<String>,
+        pub bio: Option<String>,
+        pub status_text: Option<String>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -58,12 +276,107 @@ pub mod requests {
         pub message: String,
         pub reply_to: Option<String>,
         pub created_at: super::DateTime<super::Utc>,
+        /// In the order the emoji were first used
+        #[serde(default)]
+        pub reactions: Vec<MessageReaction>,
+        #[serde(default)]
+        pub forwarded_from: Option<ForwardedFrom>,
+        /// Of the first link, arrives later as `WsMessageData::LinkPreview` for new messages
+        #[serde(default)]
+        pub link_preview: Option<LinkPreview>,
+        #[serde(default)]
+        pub kind: MessageKind,
+        /// Set in chats with disappearing messages, the message is deleted for everyone then
+        #[serde(default)]
+        pub expires_at: Option<super::DateTime<super::Utc>>,
+        /// `ChatKey` the message is encrypted with, the client replaces `message` with the
+        /// decrypted text
+        #[serde(default)]
+        pub key_id: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+    pub enum MessageKind {
+        #[default]
+        Text,
+        /// The text of the message is an optional caption
+        Voice(Box<VoiceNote>),
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct VoiceNote {
+        pub id: String,
+        pub duration_ms: u32,
+        /// Peak levels of equal slices of the recording, for drawing it
+        pub waveform: Vec<u8>,
+    }
+
+    impl VoiceNote {
+        /// Ogg Opus file under `/content` on the server
+        pub fn file_name(&self) -> String {
+            format!("voice-{}.ogg", self.id)
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct LinkPreview {
+        pub url: String,
+        pub title: Option<String>,
+        pub description: Option<String>,
+        /// Name of the image under `/content` on the server
+        pub image: Option<String>,
+    }
+
+    /// Original of a forwarded message, its sender may not be a member of the chat
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct ForwardedFrom {
+        /// `None` once the original is deleted
+        pub message_id: Option<String>,
+        pub sender_id: String,
+        pub sender_name: String,
+        pub created_at: super::DateTime<super::Utc>,
+    }
+
+    impl WsChatMessage {
+        pub fn add_reaction(&mut self, user_id: String, emoji: String) {
+            match self
+                .reactions
+                .iter_mut()
+                .find(|reaction| reaction.emoji == emoji)
+            {
+                Some(reaction) => {
+                    if !reaction.user_ids.contains(&user_id) {
+                        reaction.user_ids.push(user_id);
+                    }
+                }
+                None => self.reactions.push(MessageReaction {
+                    emoji,
+                    user_ids: vec![user_id],
+                }),
+            }
+        }
+
+        pub fn remove_reaction(&mut self, user_id: &str, emoji: &str) {
+            for reaction in &mut self.reactions {
+                if reaction.emoji == emoji {
+                    reaction.user_ids.retain(|id| id != user_id);
+                }
+            }
+            self.reactions.retain(|reaction| !reaction.user_ids.is_empty());
+        }
+    }
+
+    /// Everyone who reacted to a message with the same emoji
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct MessageReaction {
+        pub emoji: String,
+        pub user_ids: Vec<String>,
     }
 
     #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
     pub struct WsCreateChat {
         pub chat_id: String,
-        pub members: Vec<String>,
+        pub members: Vec<ChatMember>,
     }
 
     #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -78,18 +391,198 @@ pub mod requests {
         pub message_id: String,
     }
 
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub struct WsReaction {
+        pub chat_id: String,
+        pub message_id: String,
+        pub user_id: String,
+        pub emoji: String,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub struct WsLinkPreview {
+        pub chat_id: String,
+        pub message_id: String,
+        pub preview: LinkPreview,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub struct WsChatTtl {
+        pub chat_id: String,
+        pub message_ttl: Option<u32>,
+        /// Member who changed it
+        pub changed_by: String,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub struct WsChatPreferences {
+        pub chat_id: String,
+        pub preferences: ChatPreferences,
+    }
+
     #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
     pub enum WsMessageData {
-        ChatMessage(WsChatMessage),
+        ChatMessage(Box<WsChatMessage>),
         CreateChat(WsCreateChat),
         LeaveChat(WsLeaveChat),
         DeleteMessage(WsDeleteMessage),
+        ChatPreferences(WsChatPreferences),
+        /// Sent to the user and everyone sharing a chat with them
+        ProfileUpdated(UserStatus),
+        /// Contacts of the recipient have changed and should be reloaded
+        ContactsChanged,
+        ReactionAdded(WsReaction),
+        ReactionRemoved(WsReaction),
+        LinkPreview(WsLinkPreview),
+        ChatTtl(WsChatTtl),
+        /// New messages of the chat are encrypted with this key from now on
+        ChatKey(ChatKey),
+        /// An admin has disabled the account, the socket is closed right after
+        AccountDisabled,
+    }
+
+    /// Per-member settings of a chat, every member has their own
+    #[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+    pub struct ChatPreferences {
+        pub pinned: bool,
+        pub muted_until: Option<super::DateTime<super::Utc>>,
+        pub archived: bool,
+    }
+
+    impl ChatPreferences {
+        pub fn is_muted(&self) -> bool {
+            self.muted_until
+                .is_some_and(|muted_until| muted_until > super::Utc::now())
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct UpdateChatPreferences {
+        pub chat_id: String,
+        pub preferences: ChatPreferences,
     }
 
     #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
     pub struct ChatWithMembers {
         pub id: String,
-        pub members: Vec<String>,
+        pub members: Vec<ChatMember>,
         pub last_updated: super::DateTime<super::Utc>,
+        pub preferences: ChatPreferences,
+        /// Latest message with its content truncated for the chat list
+        pub last_message: Option<WsChatMessage>,
+        #[serde(default)]
+        pub message_ttl: Option<u32>,
+        /// Oldest first, the chat is end-to-end encrypted if there are any
+        #[serde(default)]
+        pub chat_keys: Vec<ChatKey>,
+    }
+
+    /// Disappearing messages are as short-lived as this at most
+    pub const MIN_MESSAGE_TTL_SECS: u32 = 60;
+    pub const MAX_MESSAGE_TTL_SECS: u32 = 365 * 24 * 60 * 60;
+
+    /// Shared by every member, applies to messages sent after the change
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct SetChatTtl {
+        pub chat_id: String,
+        /// In seconds, `None` keeps messages forever
+        pub message_ttl: Option<u32>,
+    }
+
+    /// X25519 public key of a user, base64 encoded
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct IdentityKey {
+        pub user_id: String,
+        /// `None` until the user's client publishes one
+        pub identity_key: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct PublishIdentityKey {
+        pub identity_key: String,
+    }
+
+    /// Key agreement of a direct chat. Both members derive the same chat key from it with
+    /// their identity key, the server only relays the public halves
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct ChatKey {
+        pub id: String,
+        pub chat_id: String,
+        pub initiator_id: String,
+        /// Identity keys of both members at the time of the agreement
+        pub initiator_key: String,
+        pub responder_key: String,
+        /// One-time public key of the initiator
+        pub ephemeral_key: String,
+        pub created_at: super::DateTime<super::Utc>,
+    }
+
+    /// Rejected if either identity key isn't the current one of its member
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct CreateChatKey {
+        pub chat_id: String,
+        pub initiator_key: String,
+        pub responder_key: String,
+        pub ephemeral_key: String,
+    }
+
+    /// Against a user, or one of their messages if `message_id` is set
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct CreateReport {
+        pub user_id: String,
+        #[serde(default)]
+        pub message_id: Option<String>,
+        pub reason: String,
+    }
+
+    /// Account as listed to admins
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct AdminUser {
+        pub id: String,
+        pub username: String,
+        pub display_name: Option<String>,
+        pub is_admin: bool,
+        pub disabled: bool,
+        pub online: bool,
+    }
+
+    /// Disabling signs the user out everywhere
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct SetUserDisabled {
+        pub user_id: String,
+        pub disabled: bool,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct Report {
+        pub id: String,
+        pub reporter_id: String,
+        pub reporter_username: String,
+        pub reported_id: String,
+        pub reported_username: String,
+        /// The message may have been deleted since
+        pub message_id: Option<String>,
+        /// As the server had it when the report was made, encrypted messages stay unreadable
+        pub message_content: Option<String>,
+        pub reason: String,
+        pub resolved: bool,
+        pub created_at: super::DateTime<super::Utc>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ResolveReport {
+        pub id: String,
+    }
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+    pub struct ServerStats {
+        pub users: u64,
+        pub disabled_users: u64,
+        /// With an open socket
+        pub online_users: u64,
+        pub chats: u64,
+        pub messages: u64,
+        pub active_sessions: u64,
+        pub open_reports: u64,
     }
 }
@@ -0,0 +1,391 @@
+//! The subset of Markdown messages are written in: `**bold**`, `*italic*` or `_italic_`,
+//! `` `code` ``, fenced code blocks, `[links](https://...)` and bare URLs.
+//! Markers that aren't closed are kept as typed, a backslash escapes them.
+
+/// Piece of a paragraph sharing one style
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    /// Only `http` and `https` URLs become links
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Span>),
+    /// Contents of a fenced block without the fences and language tag
+    Code(String),
+}
+
+const FENCE: &str = "```";
+const ESCAPABLE: &[char] = &['\\', '*', '_', '`', '[', ']'];
+
+pub fn parse(source: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut code: Option<Vec<&str>> = None;
+
+    for line in source.lines() {
+        match &mut code {
+            Some(code_lines) => {
+                if line.trim_end() == FENCE {
+                    blocks.push(Block::Code(code_lines.join("\n")));
+                    code = None;
+                } else {
+                    code_lines.push(line);
+                }
+            }
+            None if line.trim_start().starts_with(FENCE) => {
+                if !paragraph.is_empty() {
+                    blocks.push(Block::Paragraph(parse_inline(&paragraph.join("\n"))));
+                    paragraph.clear();
+                }
+                code = Some(Vec::new());
+            }
+            None => paragraph.push(line),
+        }
+    }
+
+    // An unclosed fence still makes a code block, like most editors show it
+    if let Some(code_lines) = code {
+        blocks.push(Block::Code(code_lines.join("\n")));
+    }
+    if !paragraph.is_empty() {
+        blocks.push(Block::Paragraph(parse_inline(&paragraph.join("\n"))));
+    }
+    blocks
+}
+
+/// The text without formatting, for previews and notifications
+pub fn to_plain_text(source: &str) -> String {
+    parse(source)
+        .into_iter()
+        .map(|block| match block {
+            Block::Paragraph(spans) => spans.into_iter().map(|span| span.text).collect(),
+            Block::Code(code) => code,
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn parse_inline(text: &str) -> Vec<Span> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    parse_spans(&chars, &Span::default(), &mut spans);
+    spans
+}
+
+fn find(chars: &[char], from: usize, pattern: &[char]) -> Option<usize> {
+    (from..=chars.len().checked_sub(pattern.len())?)
+        .find(|&index| chars[index..].starts_with(pattern) && !is_escaped(chars, index))
+}
+
+fn is_escaped(chars: &[char], index: usize) -> bool {
+    chars[..index]
+        .iter()
+        .rev()
+        .take_while(|&&c| c == '\\')
+        .count()
+        % 2
+        == 1
+}
+
+fn push_text(spans: &mut Vec<Span>, style: &Span, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(last) = spans.last_mut() {
+        if last.bold == style.bold
+            && last.italic == style.italic
+            && last.code == style.code
+            && last.link == style.link
+        {
+            last.text.push_str(text);
+            return;
+        }
+    }
+    spans.push(Span {
+        text: text.into(),
+        ..style.clone()
+    });
+}
+
+fn url_at(chars: &[char], index: usize) -> Option<usize> {
+    let starts_word = index == 0 || !chars[index - 1].is_alphanumeric();
+    let rest: String = chars[index..chars.len().min(index + 8)].iter().collect();
+    if !starts_word || !(rest.starts_with("http://") || rest.starts_with("https://")) {
+        return None;
+    }
+    let mut end = index;
+    while end < chars.len() && !chars[end].is_whitespace() {
+        end += 1;
+    }
+    // Punctuation right after a URL usually belongs to the sentence
+    while end > index && ".,!?:;)'\"".contains(chars[end - 1]) {
+        end -= 1;
+    }
+    let scheme_length = if rest.starts_with("https://") { 8 } else { 7 };
+    (end > index + scheme_length).then_some(end)
+}
+
+fn parse_spans(chars: &[char], style: &Span, spans: &mut Vec<Span>) {
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c == '\\'
+            && chars
+                .get(index + 1)
+                .is_some_and(|next| ESCAPABLE.contains(next))
+        {
+            push_text(spans, style, &chars[index + 1].to_string());
+            index += 2;
+            continue;
+        }
+
+        if c == '`' {
+            if let Some(end) = find(chars, index + 1, &['`']) {
+                let code: String = chars[index + 1..end].iter().collect();
+                push_text(
+                    spans,
+                    &Span {
+                        code: true,
+                        ..style.clone()
+                    },
+                    &code,
+                );
+                index = end + 1;
+                continue;
+            }
+        }
+
+        if chars[index..].starts_with(&['*', '*']) {
+            if let Some(end) = find(chars, index + 2, &['*', '*']).filter(|&end| end > index + 2) {
+                let bold = Span {
+                    bold: true,
+                    ..style.clone()
+                };
+                parse_spans(&chars[index + 2..end], &bold, spans);
+                index = end + 2;
+                continue;
+            }
+        }
+
+        if c == '*' || c == '_' {
+            // `snake_case` words aren't italic
+            let opens = c == '*' || index == 0 || !chars[index - 1].is_alphanumeric();
+            let end = find(chars, index + 1, &[c]).filter(|&end| {
+                end > index + 1
+                    && !chars[end - 1].is_whitespace()
+                    && (c == '*'
+                        || !chars
+                            .get(end + 1)
+                            .is_some_and(|next| next.is_alphanumeric()))
+            });
+            if let (true, Some(end)) = (opens, end) {
+                let italic = Span {
+                    italic: true,
+                    ..style.clone()
+                };
+                parse_spans(&chars[index + 1..end], &italic, spans);
+                index = end + 1;
+                continue;
+            }
+        }
+
+        if c == '[' {
+            let link = find(chars, index + 1, &[']', '(']).and_then(|label_end| {
+                let url_end = find(chars, label_end + 2, &[')'])?;
+                let url: String = chars[label_end + 2..url_end].iter().collect();
+                let valid = (url.starts_with("http://") || url.starts_with("https://"))
+                    && !url.contains(char::is_whitespace);
+                valid.then_some((label_end, url_end, url))
+            });
+            if let Some((label_end, url_end, url)) = link {
+                let label: String = chars[index + 1..label_end].iter().collect();
+                let label = if label.is_empty() { url.clone() } else { label };
+                push_text(
+                    spans,
+                    &Span {
+                        link: Some(url),
+                        ..style.clone()
+                    },
+                    &label,
+                );
+                index = url_end + 1;
+                continue;
+            }
+        }
+
+        if style.link.is_none() {
+            if let Some(end) = url_at(chars, index) {
+                let url: String = chars[index..end].iter().collect();
+                push_text(
+                    spans,
+                    &Span {
+                        link: Some(url.clone()),
+                        ..style.clone()
+                    },
+                    &url,
+                );
+                index = end;
+                continue;
+            }
+        }
+
+        push_text(spans, style, &c.to_string());
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Span {
+        Span {
+            text: text.into(),
+            ..Span::default()
+        }
+    }
+
+    fn styled(text: &str, bold: bool, italic: bool, code: bool) -> Span {
+        Span {
+            text: text.into(),
+            bold,
+            italic,
+            code,
+            link: None,
+        }
+    }
+
+    fn link(text: &str, url: &str) -> Span {
+        Span {
+            text: text.into(),
+            link: Some(url.into()),
+            ..Span::default()
+        }
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            parse_inline("a **b** *c* _d_ `e`"),
+            vec![
+                plain("a "),
+                styled("b", true, false, false),
+                plain(" "),
+                styled("c", false, true, false),
+                plain(" "),
+                styled("d", false, true, false),
+                plain(" "),
+                styled("e", false, false, true),
+            ]
+        );
+        assert_eq!(
+            parse_inline("**bold _both_**"),
+            vec![
+                styled("bold ", true, false, false),
+                styled("both", true, true, false),
+            ]
+        );
+        assert_eq!(
+            parse_inline("`**not bold**`"),
+            vec![styled("**not bold**", false, false, true)]
+        );
+        assert_eq!(
+            parse_inline("snake_case_name"),
+            vec![plain("snake_case_name")]
+        );
+        assert_eq!(parse_inline(r"\*not italic\*"), vec![plain("*not italic*")]);
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            parse("before\n```rust\nfn main() {}\n  **kept**\n```\nafter"),
+            vec![
+                Block::Paragraph(vec![plain("before")]),
+                Block::Code("fn main() {}\n  **kept**".into()),
+                Block::Paragraph(vec![plain("after")]),
+            ]
+        );
+        assert_eq!(parse("```\nunclosed"), vec![Block::Code("unclosed".into())]);
+        assert_eq!(to_plain_text("**a**\n```\nb\n```"), "a\nb");
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            parse_inline("see [the *docs*](https://example.com/a)"),
+            vec![plain("see "), link("the *docs*", "https://example.com/a")]
+        );
+        assert_eq!(
+            parse_inline("[](http://example.com)"),
+            vec![link("http://example.com", "http://example.com")]
+        );
+        for source in ["[x](javascript:alert(1))", "[x](ftp://example.com)"] {
+            assert_eq!(parse_inline(source), vec![plain(source)], "{source}");
+        }
+        // Not a link with a space in the URL, its start is still a bare one
+        assert_eq!(
+            parse_inline("[x](https://a b)"),
+            vec![plain("[x]("), link("https://a", "https://a"), plain(" b)")]
+        );
+    }
+
+    #[test]
+    fn bare_urls() {
+        assert_eq!(
+            parse_inline("go to https://example.com/path?q=1, then"),
+            vec![
+                plain("go to "),
+                link(
+                    "https://example.com/path?q=1",
+                    "https://example.com/path?q=1"
+                ),
+                plain(", then"),
+            ]
+        );
+        assert_eq!(
+            parse_inline("(http://example.com)"),
+            vec![
+                plain("("),
+                link("http://example.com", "http://example.com"),
+                plain(")"),
+            ]
+        );
+        // Only at the start of a word and with something after the scheme
+        assert_eq!(
+            parse_inline("xhttps://example.com"),
+            vec![plain("xhttps://example.com")]
+        );
+        assert_eq!(parse_inline("https://"), vec![plain("https://")]);
+    }
+
+    #[test]
+    fn unclosed_markers() {
+        for source in [
+            "**bold",
+            "*italic",
+            "_italic",
+            "`code",
+            "****",
+            "* not italic *",
+            "[label](",
+            "[label] (x)",
+        ] {
+            assert_eq!(parse_inline(source), vec![plain(source)], "{source}");
+        }
+        assert_eq!(
+            parse_inline("[label](https://example.com"),
+            vec![
+                plain("[label]("),
+                link("https://example.com", "https://example.com"),
+            ]
+        );
+    }
+}